@@ -0,0 +1,68 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Analytical view factors, used for testing the numerical ones.
+
+use crate::{Float, PI};
+
+/// The view factor between two parallel, directly opposed, rectangles
+/// of size `a` by `b`, separated by a distance `c`
+pub(crate) fn parallel_rectangles(a: Float, b: Float, c: Float) -> Float {
+    let x = a / c;
+    let y = b / c;
+    let x2 = x * x;
+    let y2 = y * y;
+    2. / (PI * x * y)
+        * ((((1. + x2) * (1. + y2)) / (1. + x2 + y2)).sqrt().ln()
+            + x * (1. + y2).sqrt() * (x / (1. + y2).sqrt()).atan()
+            + y * (1. + x2).sqrt() * (y / (1. + x2).sqrt()).atan()
+            - x * x.atan()
+            - y * y.atan())
+}
+
+/// The view factor from a rectangle of size `l` by `w` to a perpendicular
+/// rectangle of size `l` by `h` that shares the edge of length `l`
+pub(crate) fn perpendicular_rectangles(l: Float, w: Float, h: Float) -> Float {
+    let h = h / l;
+    let w = w / l;
+    let h2 = h * h;
+    let w2 = w * w;
+    let hw = (h2 + w2).sqrt();
+    let a = (1. + w2) * (1. + h2) / (1. + w2 + h2);
+    let b = w2 * (1. + w2 + h2) / ((1. + w2) * (w2 + h2));
+    let c = h2 * (1. + h2 + w2) / ((1. + h2) * (h2 + w2));
+    1. / (PI * w)
+        * (w * (1. / w).atan() + h * (1. / h).atan() - hw * (1. / hw).atan()
+            + 0.25 * (a.ln() + w2 * b.ln() + h2 * c.ln()))
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use validate::assert_close;
+
+    #[test]
+    fn test_tabulated() {
+        // From tables
+        assert_close!(parallel_rectangles(1., 1., 1.), 0.1998, 1e-3);
+        assert_close!(perpendicular_rectangles(1., 1., 1.), 0.2000, 1e-3);
+        assert_close!(perpendicular_rectangles(1., 1., 2.), 0.2329, 1e-3);
+    }
+}
@@ -17,66 +17,582 @@ LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
+use clap::{Args, Parser, Subcommand};
+use communication_protocols::{MetaOptions, SimulationModel};
+use light::daylight::{read_sensors, write_matrix};
+use light::metrics::first_hour;
+use light::{
+    Float, OpticalInfo, SolarModel, SolarModelOptions, WorkplaneOptions,
+    OPTICAL_INFO_FORMAT_VERSION,
+};
+use matrix::Matrix;
+use rendering::{colour_matrix::colour_matrix_to_radiance, DCFactory, Scene, Wavelengths};
+use simple_model::{SimpleModel, SimulationStateHeader, SolarOptions};
 use solar::ReinhartSky;
-// use rendering::from_radiance::from
-use clap::Parser;
-use geometry3d::{Point3D, Ray3D, Vector3D};
-use rendering::{DCFactory, Scene};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use weather::EPWWeather;
 
+/// Calculates solar radiation, longwave radiation and daylight in SIMPLE models
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Inputs {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Precomputes the optical information of a SIMPLE model and writes it
+    /// into a file, so that simulations can reuse it
+    Precompute(PrecomputeArgs),
+
+    /// Runs an annual simulation using an EPW file, and writes the
+    /// incident solar and infrared irradiance of each surface into a CSV file
+    Simulate(SimulateArgs),
+
+    /// Runs an annual simulation using an EPW file, and writes the
+    /// illuminance at a grid of sensors over the floor of each space
+    /// into a CSV file
+    Daylight(DaylightArgs),
+
+    /// Summarizes the contents of a file containing optical information
+    Inspect(InspectArgs),
+
+    /// Calculates the Daylight Coefficients of the sensors in a points file
+    Dc(DcArgs),
+}
+
+/// The parameters of the ray-tracing calculations
+#[derive(Args, Debug)]
+struct CalculationArgs {
+    /// The Reinhart subdivision (MF) of the sky. Defaults to the one in the model.
+    #[arg(long)]
+    mf: Option<usize>,
+
+    /// The number of points (and rays) sampled over each surface. Defaults
+    /// to the one in the model.
+    #[arg(long)]
+    n_points: Option<usize>,
+
+    /// The number of ambient samples sent from each ray-surface
+    /// intersection. Defaults to the one in the model.
+    #[arg(long)]
+    ambient_samples: Option<usize>,
+
+    /// The maximum number of bounces of the solar radiation. Defaults to
+    /// the one in the options.
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// A JSON file containing the `SolarModelOptions`
+    #[arg(long)]
+    options: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct PrecomputeArgs {
+    /// The SIMPLE model (`.spl` or `.simple`)
     #[arg(short, long)]
     input: String,
-    // #[arg(short, long)]
-    // weather: String,
+
+    /// The path where the optical information will be written. Paths ending
+    /// in `.bin` or `.bin.gz` are written in a compact (and maybe compressed)
+    /// binary format, and any other path is written as JSON. If the file
+    /// exists, the results that are still valid are reused.
+    #[arg(short, long)]
+    output: String,
+
+    #[command(flatten)]
+    calculation: CalculationArgs,
 }
 
-fn main() {
-    let args = Inputs::parse();
+#[derive(Args, Debug)]
+struct SimulateArgs {
+    /// The SIMPLE model (`.spl` or `.simple`)
+    #[arg(short, long)]
+    input: String,
+
+    /// The EPW weather file
+    #[arg(short, long)]
+    weather: String,
+
+    /// The CSV file where the results will be written
+    #[arg(short, long)]
+    output: String,
+
+    /// A file containing precomputed optical information. It is
+    /// calculated and written if it does not exist.
+    #[arg(long)]
+    optical_data: Option<String>,
+
+    #[command(flatten)]
+    calculation: CalculationArgs,
+}
+
+#[derive(Args, Debug)]
+struct DaylightArgs {
+    /// The SIMPLE model (`.spl` or `.simple`)
+    #[arg(short, long)]
+    input: String,
+
+    /// The EPW weather file
+    #[arg(short, long)]
+    weather: String,
+
+    /// The CSV file where the results will be written
+    #[arg(short, long)]
+    output: String,
+
+    /// The height of the sensors over the floor
+    #[arg(long, default_value_t = 0.8)]
+    height: Float,
+
+    /// The distance between sensors
+    #[arg(long, default_value_t = 0.5)]
+    spacing: Float,
+
+    /// A file containing precomputed optical information. It is
+    /// calculated and written if it does not exist.
+    #[arg(long)]
+    optical_data: Option<String>,
+
+    #[command(flatten)]
+    calculation: CalculationArgs,
+}
+
+#[derive(Args, Debug)]
+struct InspectArgs {
+    /// The file containing the optical information
+    #[arg(short, long)]
+    input: String,
+}
+
+#[derive(Args, Debug)]
+struct DcArgs {
+    /// The model: a Radiance (`.rad`) or a SIMPLE (`.spl` or `.simple`) file
+    #[arg(short, long)]
+    input: String,
+
+    /// The sensors: a Radiance-style points file (`.pts`) or a CSV file, with
+    /// the position and direction of one sensor per line (i.e., `x y z dx dy dz`)
+    #[arg(short, long)]
+    sensors: String,
+
+    /// The file where the Daylight Coefficients matrix will be written, in
+    /// Radiance's ASCII matrix format. It is written to the standard output
+    /// if not given.
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// The Reinhart subdivision (MF) of the sky
+    #[arg(long, default_value_t = 1)]
+    mf: usize,
 
-    let input_file = args.input;
+    /// The number of ambient samples sent from each ray-surface intersection
+    #[arg(long, default_value_t = 10000)]
+    ambient_samples: usize,
 
-    let mut scene = if input_file.ends_with(".rad") {
-        Scene::from_radiance(input_file)
-    } else if input_file.ends_with(".simple") || input_file.ends_with(".spl") {
-        panic!("Reading SIMPLE models is still not suppoerted")
+    /// The maximum number of bounces
+    #[arg(long, default_value_t = 1)]
+    max_depth: usize,
+}
+
+impl CalculationArgs {
+    /// Gets the `SolarOptions` of the model, overriden by the
+    /// values given in the command line
+    fn solar_options(&self, model: &SimpleModel) -> SolarOptions {
+        let mut options = match &model.solar_options {
+            Some(v) => v.clone(),
+            None => SolarOptions::new(),
+        };
+        if let Some(mf) = self.mf {
+            options.set_solar_sky_discretization(mf);
+        }
+        if let Some(n) = self.n_points {
+            options.set_n_solar_irradiance_points(n);
+        }
+        if let Some(n) = self.ambient_samples {
+            options.set_solar_ambient_divitions(n);
+        }
+        options
+    }
+
+    /// Reads the `SolarModelOptions` from a JSON file (or uses
+    /// the default ones), overriden by the values given in the command line
+    fn model_options(&self) -> Result<SolarModelOptions, String> {
+        let mut options = match &self.options {
+            Some(path) => SolarModelOptions::from_file(Path::new(path))?,
+            None => SolarModelOptions::default(),
+        };
+        if let Some(depth) = self.max_depth {
+            options.solar_max_depth = depth;
+        }
+        Ok(options)
+    }
+}
+
+/// Reads the location from the header of an EPW file
+fn read_epw_location(path: &str) -> Result<MetaOptions, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Unable to read EPW file '{}': {}", path, e)),
+    };
+    let line = match content.lines().next() {
+        Some(v) if v.starts_with("LOCATION") => v,
+        _ => {
+            return Err(format!(
+                "EPW file '{}' does not start with a LOCATION line",
+                path
+            ))
+        }
+    };
+    let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+    if fields.len() < 10 {
+        return Err(format!("Incomplete LOCATION line in EPW file '{}'", path));
+    }
+    let parse = |i: usize| -> Result<Float, String> {
+        fields[i].parse::<Float>().map_err(|_| {
+            format!(
+                "Invalid LOCATION field '{}' in EPW file '{}'",
+                fields[i], path
+            )
+        })
+    };
+    Ok(MetaOptions {
+        latitude: parse(6)?.to_radians(),
+        longitude: parse(7)?.to_radians(),
+        standard_meridian: (15. * parse(8)?).to_radians(),
+        elevation: parse(9)?,
+    })
+}
+
+/// Reads a SIMPLE model
+fn read_model(path: &str) -> Result<(SimpleModel, SimulationStateHeader), String> {
+    if !path.ends_with(".spl") && !path.ends_with(".simple") {
+        return Err(format!(
+            "Don't know how to read file '{}'... SIMPLE models should have a .spl or .simple extension",
+            path
+        ));
+    }
+    SimpleModel::from_file(path)
+}
+
+/// Creates a file, mapping the error into a `String`
+fn create_file(path: &str) -> Result<File, String> {
+    File::create(path).map_err(|e| format!("Unable to create file '{}': {}", path, e))
+}
+
+/// Writes a line into a file, mapping the error into a `String`
+fn write_line(file: &mut File, line: &str) -> Result<(), String> {
+    writeln!(file, "{}", line).map_err(|e| format!("{}", e))
+}
+
+fn precompute(args: PrecomputeArgs) -> Result<(), String> {
+    let (model, mut state_header) = read_model(&args.input)?;
+    let solar_options = args.calculation.solar_options(&model);
+    let model_options = args.calculation.model_options()?;
+
+    // Reuse what did not change since the last time, if possible
+    let output = Path::new(&args.output);
+    let previous = if output.is_file() {
+        OpticalInfo::from_file(output).ok()
     } else {
-        eprintln!(
-            "Don't know how to read file '{}'... only .rad is supported for now",
-            input_file
-        );
-        std::process::exit(1);
+        None
+    };
+    let info = match &previous {
+        Some(previous) => {
+            eprintln!(
+                "Updating optical information of '{}', reusing '{}'...",
+                args.input, args.output
+            );
+            OpticalInfo::new_from_previous(
+                previous,
+                &solar_options,
+                &model_options,
+                &model,
+                &mut state_header,
+            )?
+        }
+        None => {
+            eprintln!("Calculating optical information of '{}'...", args.input);
+            OpticalInfo::new(&solar_options, &model_options, &model, &mut state_header)?
+        }
+    };
+    info.to_file(output)?;
+    eprintln!("Optical information written to '{}'", args.output);
+    Ok(())
+}
+
+/// Builds a `SolarModel`, reading or writing the optical information
+/// from the `optical_data` path, if given.
+fn build_solar_model(
+    meta_options: &MetaOptions,
+    calculation: &CalculationArgs,
+    model_options: SolarModelOptions,
+    optical_data: &Option<String>,
+    model: &SimpleModel,
+    state_header: &mut SimulationStateHeader,
+) -> Result<SolarModel, String> {
+    let mut solar_options = calculation.solar_options(model);
+    if let Some(path) = optical_data {
+        solar_options.set_optical_data_path(path.clone());
+    }
+    SolarModel::new_with_options(
+        meta_options,
+        solar_options,
+        model_options,
+        model,
+        state_header,
+        1,
+    )
+}
+
+fn simulate(args: SimulateArgs) -> Result<(), String> {
+    let (model, mut state_header) = read_model(&args.input)?;
+    let meta_options = read_epw_location(&args.weather)?;
+    let weather = EPWWeather::from_file(&args.weather)?;
+    let model_options = args.calculation.model_options()?;
+    let solar_model = build_solar_model(
+        &meta_options,
+        &args.calculation,
+        model_options,
+        &args.optical_data,
+        &model,
+        &mut state_header,
+    )?;
+    let mut state = match state_header.take_values() {
+        Some(v) => v,
+        None => return Err("Could not take the values of the simulation state".into()),
+    };
+    let mut memory = solar_model.allocate_memory()?;
+
+    let mut file = create_file(&args.output)?;
+    let mut header = vec!["Month".to_string(), "Day".to_string(), "Hour".to_string()];
+    for s in model.surfaces.iter() {
+        header.push(format!("{} - front solar [W/m2]", s.name));
+        header.push(format!("{} - back solar [W/m2]", s.name));
+        header.push(format!("{} - front IR [W/m2]", s.name));
+        header.push(format!("{} - back IR [W/m2]", s.name));
+    }
+    for s in model.fenestrations.iter() {
+        header.push(format!("{} - front solar [W/m2]", s.name));
+        header.push(format!("{} - back solar [W/m2]", s.name));
+        header.push(format!("{} - front IR [W/m2]", s.name));
+        header.push(format!("{} - back IR [W/m2]", s.name));
+    }
+    write_line(&mut file, &header.join(","))?;
+
+    let value = |v: Option<Float>| match v {
+        Some(v) => format!("{}", v),
+        None => String::new(),
+    };
+    let mut date = first_hour();
+    for _ in 0..8760 {
+        solar_model.march(date, &weather, &model, &mut state, &mut memory)?;
+        let mut row = vec![
+            format!("{}", date.month),
+            format!("{}", date.day),
+            format!("{}", date.hour),
+        ];
+        for s in model.surfaces.iter() {
+            row.push(value(s.front_incident_solar_irradiance(&state)));
+            row.push(value(s.back_incident_solar_irradiance(&state)));
+            row.push(value(s.front_ir_irradiance(&state)));
+            row.push(value(s.back_ir_irradiance(&state)));
+        }
+        for s in model.fenestrations.iter() {
+            row.push(value(s.front_incident_solar_irradiance(&state)));
+            row.push(value(s.back_incident_solar_irradiance(&state)));
+            row.push(value(s.front_ir_irradiance(&state)));
+            row.push(value(s.back_ir_irradiance(&state)));
+        }
+        write_line(&mut file, &row.join(","))?;
+        date.add_hours(1.);
+    }
+    eprintln!("Results written to '{}'", args.output);
+    Ok(())
+}
+
+fn daylight(args: DaylightArgs) -> Result<(), String> {
+    let (model, mut state_header) = read_model(&args.input)?;
+    let meta_options = read_epw_location(&args.weather)?;
+    let weather = EPWWeather::from_file(&args.weather)?;
+    let mut model_options = args.calculation.model_options()?;
+    let mut workplanes = model_options.workplanes.clone().unwrap_or_default();
+    workplanes.height = args.height;
+    workplanes.spacing = args.spacing;
+    if let Some(depth) = args.calculation.max_depth {
+        workplanes.max_depth = depth;
+    }
+    model_options.workplanes = Some(workplanes);
+    let solar_model = build_solar_model(
+        &meta_options,
+        &args.calculation,
+        model_options,
+        &args.optical_data,
+        &model,
+        &mut state_header,
+    )?;
+    let mut state = match state_header.take_values() {
+        Some(v) => v,
+        None => return Err("Could not take the values of the simulation state".into()),
     };
+    let mut memory = solar_model.allocate_memory()?;
+
+    let mut file = create_file(&args.output)?;
+    let mut header = vec!["Month".to_string(), "Day".to_string(), "Hour".to_string()];
+    for workplane in solar_model.workplanes() {
+        for (i, p) in workplane.sensors.iter().enumerate() {
+            header.push(format!(
+                "{} - sensor {} ({} {} {}) [lux]",
+                workplane.space, i, p.x, p.y, p.z
+            ));
+        }
+    }
+    write_line(&mut file, &header.join(","))?;
+
+    let mut date = first_hour();
+    for _ in 0..8760 {
+        solar_model.march(date, &weather, &model, &mut state, &mut memory)?;
+        let mut row = vec![
+            format!("{}", date.month),
+            format!("{}", date.day),
+            format!("{}", date.hour),
+        ];
+        for illuminance in memory.workplane_illuminance.iter() {
+            row.extend(illuminance.iter().map(|v| format!("{}", v)));
+        }
+        write_line(&mut file, &row.join(","))?;
+        date.add_hours(1.);
+    }
+    eprintln!("Results written to '{}'", args.output);
+    Ok(())
+}
+
+fn inspect(args: InspectArgs) -> Result<(), String> {
+    let info = OpticalInfo::from_file(Path::new(&args.input))?;
+
+    // Derive the MF from the number of columns
+    let mf = |ncols: usize| -> String {
+        match (1..=12).find(|mf| ReinhartSky::n_bins(*mf) == ncols) {
+            Some(mf) => format!("MF = {}", mf),
+            None => "unknown MF".to_string(),
+        }
+    };
+    let describe = |name: &str, dc: &Matrix| {
+        let (nrows, ncols) = dc.size();
+        if nrows == 0 {
+            println!("    {}: none", name);
+        } else {
+            println!(
+                "    {}: {} rows x {} columns ({})",
+                name,
+                nrows,
+                ncols,
+                mf(ncols)
+            );
+        }
+    };
+
+    println!("Optical information in '{}'", args.input);
+    println!(
+        "  Format version: {} (current is {})",
+        info.signature.format_version, OPTICAL_INFO_FORMAT_VERSION
+    );
+    println!("  Model hash: {:016x}", info.signature.hash);
+    println!("  Daylight coefficients (sky):");
+    describe("front of surfaces", &info.front_surfaces_dc);
+    describe("back of surfaces", &info.back_surfaces_dc);
+    describe("front of fenestrations", &info.front_fenestrations_dc);
+    describe("back of fenestrations", &info.back_fenestrations_dc);
+    println!("  Daylight coefficients (direct sun):");
+    describe("front of surfaces", &info.front_surfaces_sun_dc);
+    describe("back of surfaces", &info.back_surfaces_sun_dc);
+    describe("front of fenestrations", &info.front_fenestrations_sun_dc);
+    describe("back of fenestrations", &info.back_fenestrations_sun_dc);
+
+    println!("  View factors:");
+    println!(
+        "    surfaces: {} front, {} back",
+        info.front_surfaces_view_factors.len(),
+        info.back_surfaces_view_factors.len()
+    );
+    println!(
+        "    fenestrations: {} front, {} back",
+        info.front_fenestrations_view_factors.len(),
+        info.back_fenestrations_view_factors.len()
+    );
+    println!("    spaces: {}", info.interior_view_factors.len());
 
+    println!("  Workplanes:");
+    if info.workplanes.is_empty() {
+        println!("    none");
+    }
+    for w in info.workplanes.iter() {
+        println!("    {}: {} sensors", w.space, w.sensors.len());
+    }
+    println!("  Glare viewpoints: {}", info.glare_views.len());
+
+    match serde_json::to_string_pretty(&info.options) {
+        Ok(v) => println!("  Options: {}", v),
+        Err(e) => return Err(format!("{}", e)),
+    }
+    Ok(())
+}
+
+fn dc(args: DcArgs) -> Result<(), String> {
+    let mut scene = if args.input.ends_with(".rad") {
+        Scene::from_radiance(args.input.clone())
+    } else if args.input.ends_with(".spl") || args.input.ends_with(".simple") {
+        let (model, ..) = SimpleModel::from_file(&args.input)?;
+        Scene::from_simple_model(&model, Wavelengths::Visible)?
+    } else {
+        return Err(format!(
+            "Don't know how to read file '{}'... only .rad, .spl and .simple are supported",
+            args.input
+        ));
+    };
     scene.build_accelerator();
 
-    // Setup sensors
-    let up = Vector3D::new(0., 0., 1.);
-    let rays = vec![
-        Ray3D {
-            origin: Point3D::new(2., 0.5, 0.8),
-            direction: up,
-        },
-        Ray3D {
-            origin: Point3D::new(2., 2.5, 0.8),
-            direction: up,
-        },
-        Ray3D {
-            origin: Point3D::new(2., 5.5, 0.8),
-            direction: up,
-        },
-    ];
-
-    eprintln!("Ready to calc!... # Surface = {}", scene.triangles.len());
-
-    let mf = 1;
+    let rays = read_sensors(&args.sensors)?;
+    eprintln!(
+        "Calculating the Daylight Coefficients of {} sensors... # Surface = {}",
+        rays.len(),
+        scene.triangles.len()
+    );
+
     let factory = DCFactory {
-        max_depth: 1,
-        n_ambient_samples: 10000,
-        reinhart: ReinhartSky::new(mf),
+        max_depth: args.max_depth,
+        n_ambient_samples: args.ambient_samples,
+        reinhart: ReinhartSky::new(args.mf),
         ..DCFactory::default()
     };
-    let dc_matrix = factory.calc_dc(&rays, &scene);
-    println!("Matrix = {}", dc_matrix);
+    let dc_matrix = colour_matrix_to_radiance(&factory.calc_dc(&rays, &scene));
+    match &args.output {
+        Some(path) => {
+            let mut file = create_file(path)?;
+            write_matrix(&mut file, &dc_matrix)?;
+            eprintln!("Daylight Coefficients written to '{}'", path);
+        }
+        None => write_matrix(&mut std::io::stdout(), &dc_matrix)?,
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Inputs::parse();
+
+    let result = match args.command {
+        Command::Precompute(args) => precompute(args),
+        Command::Simulate(args) => simulate(args),
+        Command::Daylight(args) => daylight(args),
+        Command::Inspect(args) => inspect(args),
+        Command::Dc(args) => dc(args),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,376 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Daylighting calculations. The illuminance is calculated at grids of sensors
+//! (i.e., [`Workplane`]s) laid over the floors of each space, using Daylight
+//! Coefficients calculated in the visible part of the spectrum.
+
+use crate::options::WorkplaneOptions;
+use crate::solar_distribution::{SolarDistribution, FLOOR_MIN_Z};
+use crate::Float;
+use geometry3d::{Point3D, Polygon3D, Ray3D, Vector3D};
+use matrix::Matrix;
+use rendering::{colour_matrix::*, DCFactory, Scene};
+use serde::{Deserialize, Serialize};
+use simple_model::SimpleModel;
+use std::io::Write;
+
+/// The luminous efficacy used for transforming visible irradiance
+/// into illuminance, in lm/W (the same one used by Radiance)
+pub const WHITE_EFFICACY: Float = 179.;
+
+/// A grid of upward-looking illuminance sensors over the floors of a space
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Workplane {
+    /// The name of the space
+    pub space: String,
+
+    /// The position of the sensors
+    pub sensors: Vec<Point3D>,
+}
+
+/// Calculates the distance between a point and the closest edge of a
+/// polygon, in the horizontal plane
+fn horizontal_distance_to_edges(vertices: &[Point3D], p: Point3D) -> Float {
+    let n = vertices.len();
+    let mut ret = Float::MAX;
+    for i in 0..n {
+        let a = vertices[i];
+        let b = vertices[(i + 1) % n];
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let l2 = dx * dx + dy * dy;
+        let t = if l2 > 0.0 {
+            (((p.x - a.x) * dx + (p.y - a.y) * dy) / l2).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (ex, ey) = (a.x + t * dx - p.x, a.y + t * dy - p.y);
+        ret = ret.min((ex * ex + ey * ey).sqrt());
+    }
+    ret
+}
+
+/// Places points every `spacing` metres along a range of `length` metres, leaving
+/// `margin` at both ends and centring what is left
+fn grid_positions(min: Float, length: Float, spacing: Float, margin: Float) -> Vec<Float> {
+    let available = length - 2. * margin;
+    if available < 0.0 {
+        return Vec::new();
+    }
+    let n = (available / spacing).floor() as usize + 1;
+    let offset = (available - (n - 1) as Float * spacing) / 2.;
+    (0..n)
+        .map(|i| min + margin + offset + i as Float * spacing)
+        .collect()
+}
+
+/// Lays a grid of points over a floor, raised `options.height` metres
+fn floor_grid(polygon: &Polygon3D, options: &WorkplaneOptions) -> Result<Vec<Point3D>, String> {
+    let vertices = polygon.outer().vertices();
+    let origin = vertices[0];
+    let normal = polygon.normal();
+
+    let (mut min_x, mut max_x) = (Float::MAX, Float::MIN);
+    let (mut min_y, mut max_y) = (Float::MAX, Float::MIN);
+    for v in vertices.iter() {
+        min_x = min_x.min(v.x);
+        max_x = max_x.max(v.x);
+        min_y = min_y.min(v.y);
+        max_y = max_y.max(v.y);
+    }
+
+    let mut ret = Vec::new();
+    for x in grid_positions(min_x, max_x - min_x, options.spacing, options.margin) {
+        for y in grid_positions(min_y, max_y - min_y, options.spacing, options.margin) {
+            // Project the point onto the (not necessarily horizontal) floor
+            let z = origin.z - (normal.x * (x - origin.x) + normal.y * (y - origin.y)) / normal.z;
+            let p = Point3D::new(x, y, z);
+            if !polygon.test_point(p)? {
+                continue;
+            }
+            if options.margin > 0.0
+                && horizontal_distance_to_edges(vertices, p) < options.margin - 1e-4
+            {
+                continue;
+            }
+            ret.push(p + Vector3D::new(0., 0., options.height));
+        }
+    }
+    Ok(ret)
+}
+
+impl Workplane {
+    /// Lays a `Workplane` over the floors of each space of a model, in
+    /// the same order as the spaces. Spaces without floors get a
+    /// `Workplane` without sensors.
+    pub fn from_model(
+        model: &SimpleModel,
+        options: &WorkplaneOptions,
+    ) -> Result<Vec<Self>, String> {
+        if options.spacing <= 0.0 {
+            return Err(format!(
+                "The spacing of the workplane sensors must be positive... found {}",
+                options.spacing
+            ));
+        }
+        let mut ret: Vec<Self> = model
+            .spaces
+            .iter()
+            .map(|s| Self {
+                space: s.name.clone(),
+                sensors: Vec::new(),
+            })
+            .collect();
+
+        for s in model.surfaces.iter() {
+            let normal = s.vertices.normal();
+            for (front, boundary) in [(true, &s.front_boundary), (false, &s.back_boundary)] {
+                if let Some(space) = SolarDistribution::space_index(model, boundary)? {
+                    let inward_normal = if front { normal } else { normal * -1. };
+                    if inward_normal.z > FLOOR_MIN_Z {
+                        let mut sensors = floor_grid(&s.vertices, options)?;
+                        ret[space].sensors.append(&mut sensors);
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// The upward-looking rays of the sensors
+    pub fn rays(&self) -> Vec<Ray3D> {
+        let up = Vector3D::new(0., 0., 1.);
+        self.sensors
+            .iter()
+            .map(|p| Ray3D {
+                origin: *p,
+                direction: up,
+            })
+            .collect()
+    }
+
+    /// Calculates the Daylight Coefficients matrix of the sensors, with one row per
+    /// sensor. The `scene` is meant to be built with `Wavelengths::Visible`.
+    pub fn calc_dc(&self, scene: &Scene, factory: &DCFactory) -> Matrix {
+        if self.sensors.is_empty() {
+            return Matrix::empty();
+        }
+        let dc = factory.calc_dc(&self.rays(), scene);
+        colour_matrix_to_radiance(&dc)
+    }
+}
+
+/// Parses the contents of a Radiance-style points file (e.g., `.pts`), in which
+/// each line has the position and direction of a sensor (i.e., `x y z dx dy dz`).
+///
+/// Values can be separated by whitespace or by commas (i.e., CSV files). Empty
+/// lines and comments (i.e., starting with `#`) are ignored, and so is the first
+/// line if it is not numeric (i.e., the header of a CSV file).
+pub fn parse_sensors(content: &str) -> Result<Vec<Ray3D>, String> {
+    let mut ret = Vec::new();
+    let mut first = true;
+    for (line_n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|f| !f.is_empty())
+            .collect();
+        let values: Result<Vec<Float>, _> = fields.iter().map(|f| f.parse::<Float>()).collect();
+        let is_first = first;
+        first = false;
+        let values = match values {
+            Ok(v) => v,
+            Err(_) if is_first => continue, // header
+            Err(_) => {
+                return Err(format!(
+                    "Line {} of sensors file has non-numeric values: '{}'",
+                    line_n + 1,
+                    line
+                ))
+            }
+        };
+        if values.len() != 6 {
+            return Err(format!(
+                "Line {} of sensors file should have 6 values (x y z dx dy dz)... found {}",
+                line_n + 1,
+                values.len()
+            ));
+        }
+        let direction = Vector3D::new(values[3], values[4], values[5]);
+        if direction.length() < 1e-9 {
+            return Err(format!(
+                "Sensor in line {} of sensors file has no direction",
+                line_n + 1
+            ));
+        }
+        ret.push(Ray3D {
+            origin: Point3D::new(values[0], values[1], values[2]),
+            direction: direction.get_normalized(),
+        });
+    }
+    Ok(ret)
+}
+
+/// Reads the sensors in a Radiance-style points file (`.pts`) or a CSV file. See
+/// [`parse_sensors`].
+pub fn read_sensors(path: &str) -> Result<Vec<Ray3D>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Unable to read sensors file '{}': {}", path, e)),
+    };
+    parse_sensors(&content).map_err(|e| format!("{} (in '{}')", e, path))
+}
+
+/// Writes a matrix (e.g., a Daylight Coefficients matrix) in the ASCII matrix
+/// format used by Radiance (e.g., `rmtxop` or `dctimestep`):
+///
+/// ```text
+/// #?RADIANCE
+/// NROWS=3
+/// NCOLS=146
+/// NCOMP=1
+/// FORMAT=ascii
+///
+/// 0.1 0.2 ... (one row per line, NCOLS values separated by spaces)
+/// ```
+///
+/// For Daylight Coefficients, there is one row per sensor (in the order in which
+/// they were read) and one column per patch of the Reinhart sky (the first one
+/// being the ground).
+pub fn write_matrix<W: Write>(writer: &mut W, matrix: &Matrix) -> Result<(), String> {
+    let (nrows, ncols) = matrix.size();
+    let mut out = format!(
+        "#?RADIANCE\nNROWS={}\nNCOLS={}\nNCOMP=1\nFORMAT=ascii\n\n",
+        nrows, ncols
+    );
+    for row in 0..nrows {
+        let values = (0..ncols)
+            .map(|col| matrix.get(row, col).map(|v| format!("{}", v)))
+            .collect::<Result<Vec<String>, String>>()?;
+        out.push_str(&values.join(" "));
+        out.push('\n');
+    }
+    writer
+        .write_all(out.as_bytes())
+        .map_err(|e| format!("Unable to write matrix: {}", e))
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use validate::assert_close;
+
+    #[test]
+    fn test_grid_positions() {
+        let p = grid_positions(0., 4., 0.5, 0.5);
+        assert_eq!(p.len(), 7);
+        assert_close!(p[0], 0.5);
+        assert_close!(p[6], 3.5);
+
+        // Centred
+        let p = grid_positions(1., 4., 0.5, 0.6);
+        assert_eq!(p.len(), 6);
+        assert_close!(p[0], 1.75);
+        assert_close!(p[5], 4.25);
+
+        // Too narrow
+        assert!(grid_positions(0., 1., 0.5, 0.6).is_empty());
+    }
+
+    #[test]
+    fn test_from_model() {
+        let (model, _) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let workplanes = Workplane::from_model(&model, &WorkplaneOptions::default()).unwrap();
+        assert_eq!(workplanes.len(), 1);
+        assert_eq!(workplanes[0].space, "the room");
+
+        // The room is 4m by 4m
+        let sensors = &workplanes[0].sensors;
+        assert_eq!(sensors.len(), 49);
+        for p in sensors.iter() {
+            assert_close!(p.z, 0.8);
+            assert!(p.x >= 0.5 - 1e-4 && p.x <= 3.5 + 1e-4);
+            assert!(p.y >= 0.5 - 1e-4 && p.y <= 3.5 + 1e-4);
+        }
+
+        let options = WorkplaneOptions {
+            spacing: 0.0,
+            ..WorkplaneOptions::default()
+        };
+        assert!(Workplane::from_model(&model, &options).is_err());
+    }
+
+    #[test]
+    fn test_floor_grid_margin() {
+        let (model, _) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let floor = model.surfaces.iter().find(|s| s.name == "floor").unwrap();
+        let options = WorkplaneOptions {
+            margin: 1.9,
+            ..WorkplaneOptions::default()
+        };
+        // Only the centre is 1.9m away from all the edges
+        let sensors = floor_grid(&floor.vertices, &options).unwrap();
+        assert_eq!(sensors.len(), 1);
+        assert_close!(sensors[0].x, 2.);
+        assert_close!(sensors[0].y, 2.);
+    }
+
+    #[test]
+    fn test_parse_sensors() {
+        // Radiance points file
+        let sensors = parse_sensors(
+            "2 0.5 0.8 0 0 1
+            # a comment
+
+            2 2.5 0.8 0 0 2",
+        )
+        .unwrap();
+        assert_eq!(sensors.len(), 2);
+        assert_close!(sensors[1].origin.y, 2.5);
+        assert_close!(sensors[1].direction.z, 1.0); // normalized
+
+        // CSV with a header
+        let sensors = parse_sensors("x,y,z,dx,dy,dz\n1,2,3,1,0,0\n4,5,6,0,1,0\n").unwrap();
+        assert_eq!(sensors.len(), 2);
+        assert_close!(sensors[0].origin.z, 3.);
+        assert_close!(sensors[1].direction.y, 1.);
+
+        // Errors
+        assert!(parse_sensors("1 2 3 0 0 1\n1 2 3 0 0").is_err());
+        assert!(parse_sensors("1 2 3 0 0 1\nx y z 0 0 1").is_err());
+        assert!(parse_sensors("1 2 3 0 0 0").is_err());
+    }
+
+    #[test]
+    fn test_write_matrix() {
+        let mut m = Matrix::new(0.0, 2, 3);
+        m.set(1, 2, 0.5).unwrap();
+        let mut out: Vec<u8> = Vec::new();
+        write_matrix(&mut out, &m).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "#?RADIANCE\nNROWS=2\nNCOLS=3\nNCOMP=1\nFORMAT=ascii\n\n0 0 0\n0 0 0.5\n"
+        );
+    }
+}
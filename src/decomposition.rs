@@ -0,0 +1,545 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Decomposition models, which split the global horizontal irradiance into
+//! its direct normal and diffuse horizontal components. These are needed when
+//! the weather data only provides the global horizontal irradiance.
+
+use crate::{Float, PI};
+use serde::{Deserialize, Serialize};
+
+/// The solar constant, in W/m2
+pub const SOLAR_CONSTANT: Float = 1367.;
+
+/// The cosine of the zenith angle below which the sun is considered too low for
+/// splitting the global horizontal irradiance (i.e., everything is diffuse). This
+/// corresponds to a zenith angle of roughly 86 degrees.
+pub(crate) const MIN_COS_ZENITH: Float = 0.065;
+
+/// The standard atmospheric pressure at sea level, in Pa
+const STANDARD_PRESSURE: Float = 101325.;
+
+/// Calculates the extraterrestrial normal irradiance, in W/m2, on a certain day of the year
+pub fn extraterrestrial_irradiance(day_of_year: Float) -> Float {
+    SOLAR_CONSTANT * (1. + 0.033 * (2. * PI * day_of_year / 365.).cos())
+}
+
+/// Calculates the equation of time (i.e., the difference between the apparent
+/// solar time and the mean solar time), in minutes, on a certain day of the
+/// year, following Spencer (1971).
+pub fn equation_of_time(day_of_year: Float) -> Float {
+    let b = 2. * PI * (day_of_year - 1.) / 365.;
+    229.2
+        * (0.000075 + 0.001868 * b.cos()
+            - 0.032077 * b.sin()
+            - 0.014615 * (2. * b).cos()
+            - 0.04089 * (2. * b).sin())
+}
+
+/// Calculates the relative humidity (between 0 and 1) from the dry bulb and dew
+/// point temperatures (in C), using the Magnus formula
+pub fn relative_humidity(dry_bulb: Float, dew_point: Float) -> Float {
+    let saturation = |t: Float| (17.625 * t / (243.04 + t)).exp();
+    (saturation(dew_point) / saturation(dry_bulb)).clamp(0.0, 1.0)
+}
+
+/// The information used by the decomposition models. Only the global horizontal
+/// irradiance, the solar zenith angle and the day of the year are needed by all
+/// of them. The rest are used by some models and, when missing, the
+/// models fall back to their simpler forms (see [`DecompositionModel`]).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DecompositionInput {
+    /// The global horizontal irradiance, in W/m2
+    pub global_horizontal: Float,
+
+    /// The cosine of the solar zenith angle
+    pub cos_zenith: Float,
+
+    /// The day of the year (i.e., between 1 and 365)
+    pub day_of_year: Float,
+
+    /// The apparent solar time, in hours (i.e., `12` is solar noon)
+    pub solar_time: Float,
+
+    /// The dry bulb temperature, in C
+    pub dry_bulb_temperature: Option<Float>,
+
+    /// The relative humidity, between 0 and 1
+    pub relative_humidity: Option<Float>,
+
+    /// The atmospheric pressure, in Pa
+    pub pressure: Option<Float>,
+
+    /// The clearness index of the whole day (i.e., the ratio between
+    /// the daily global horizontal and extraterrestrial horizontal irradiation)
+    pub daily_clearness_index: Option<Float>,
+
+    /// The average clearness index of the previous and next hours
+    pub persistence: Option<Float>,
+}
+
+impl DecompositionInput {
+    /// Calculates the clearness index (i.e., the ratio between the global
+    /// horizontal irradiance and the extraterrestrial horizontal irradiance),
+    /// limited between 0 and 1
+    pub fn clearness_index(&self) -> Float {
+        let extraterrestrial =
+            extraterrestrial_irradiance(self.day_of_year) * self.cos_zenith.max(MIN_COS_ZENITH);
+        (self.global_horizontal / extraterrestrial).clamp(0.0, 1.0)
+    }
+
+    /// The solar altitude, in degrees
+    fn altitude(&self) -> Float {
+        self.cos_zenith.clamp(-1.0, 1.0).asin().to_degrees()
+    }
+}
+
+/// A model for splitting the global horizontal irradiance
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DecompositionModel {
+    /// The correlation between the diffuse fraction and the clearness
+    /// index by Erbs, Klein and Duffie (1982).
+    #[default]
+    Erbs,
+
+    /// The correlation by Reindl, Beckman and Duffie (1990), which adds
+    /// the solar altitude, the dry bulb temperature and the relative humidity
+    /// to the clearness index. If the temperature or the humidity are
+    /// missing, their reduced correlation (i.e., without these) is used.
+    Reindl,
+
+    /// The logistic model by Ridley, Boland and Lauret (2010), which adds the
+    /// apparent solar time, the solar altitude, the daily clearness index
+    /// and its persistence to the clearness index. The hourly clearness index
+    /// is used in place of the daily one and the persistence when these are missing.
+    BolandRidleyLauret,
+
+    /// The DISC model by Maxwell (1987), which estimates the direct normal
+    /// irradiance from the clearness index and the air mass (corrected by the
+    /// pressure, when given).
+    ///
+    /// Note that this is not DIRINT (Perez et al., 1992). DIRINT multiplies
+    /// the DISC estimate by an empirical table of coefficients, indexed by the
+    /// zenith-independent clearness index, its change over the neighbouring
+    /// hours, the zenith angle and the precipitable water. That table is not
+    /// included in this crate, so DIRINT is not available (yet).
+    Disc,
+}
+
+impl DecompositionModel {
+    /// Calculates the diffuse fraction (i.e., the ratio between the diffuse
+    /// horizontal and the global horizontal irradiance)
+    pub fn diffuse_fraction(&self, input: &DecompositionInput) -> Float {
+        let kt = input.clearness_index();
+        match self {
+            Self::Erbs => {
+                if kt <= 0.22 {
+                    1. - 0.09 * kt
+                } else if kt <= 0.80 {
+                    0.9511 - 0.1604 * kt + 4.388 * kt.powi(2) - 16.638 * kt.powi(3)
+                        + 12.336 * kt.powi(4)
+                } else {
+                    0.165
+                }
+            }
+            Self::Reindl => {
+                let sin_altitude = input.cos_zenith.max(0.0);
+                match (input.dry_bulb_temperature, input.relative_humidity) {
+                    (Some(t), Some(rh)) => {
+                        if kt <= 0.3 {
+                            (1.0 - 0.232 * kt + 0.0239 * sin_altitude - 0.000682 * t + 0.0195 * rh)
+                                .min(1.0)
+                        } else if kt < 0.78 {
+                            (1.329 - 1.716 * kt + 0.267 * sin_altitude - 0.00357 * t + 0.106 * rh)
+                                .clamp(0.1, 0.97)
+                        } else {
+                            (0.426 * kt - 0.256 * sin_altitude + 0.00349 * t + 0.0734 * rh).max(0.1)
+                        }
+                    }
+                    _ => {
+                        if kt <= 0.3 {
+                            (1.020 - 0.254 * kt + 0.0123 * sin_altitude).min(1.0)
+                        } else if kt < 0.78 {
+                            (1.400 - 1.749 * kt + 0.177 * sin_altitude).clamp(0.1, 0.97)
+                        } else {
+                            (0.486 * kt - 0.182 * sin_altitude).max(0.1)
+                        }
+                    }
+                }
+            }
+            Self::BolandRidleyLauret => {
+                let daily = input.daily_clearness_index.unwrap_or(kt);
+                let persistence = input.persistence.unwrap_or(kt);
+                1. / (1.
+                    + (-5.38 + 6.63 * kt + 0.006 * input.solar_time - 0.007 * input.altitude()
+                        + 1.75 * daily
+                        + 1.31 * persistence)
+                        .exp())
+            }
+            Self::Disc => {
+                if input.global_horizontal <= 0.0 {
+                    return 1.0;
+                }
+                let (direct_normal, _) = self.decompose(input);
+                1. - direct_normal * input.cos_zenith / input.global_horizontal
+            }
+        }
+    }
+
+    /// Splits the global horizontal irradiance into the direct normal and
+    /// diffuse horizontal irradiance, in that order.
+    pub fn decompose(&self, input: &DecompositionInput) -> (Float, Float) {
+        let global = input.global_horizontal.max(0.0);
+        if input.cos_zenith < MIN_COS_ZENITH {
+            return (0.0, global);
+        }
+        let direct_horizontal = match self {
+            Self::Disc => disc_direct_normal(input) * input.cos_zenith,
+            _ => (1. - self.diffuse_fraction(input)) * global,
+        }
+        .clamp(0.0, global);
+        let diffuse = global - direct_horizontal;
+        (direct_horizontal / input.cos_zenith, diffuse)
+    }
+}
+
+/// Calculates the direct normal irradiance using the DISC model
+fn disc_direct_normal(input: &DecompositionInput) -> Float {
+    let kt = input.clearness_index();
+    let zenith = input.cos_zenith.clamp(-1.0, 1.0).acos().to_degrees();
+
+    // Relative air mass (Kasten, 1966), corrected by the pressure
+    let pressure = input.pressure.unwrap_or(STANDARD_PRESSURE);
+    let air_mass = (1. / (input.cos_zenith + 0.15 * (93.885 - zenith).powf(-1.253))).min(12.)
+        * pressure
+        / STANDARD_PRESSURE;
+
+    let (a, b, c) = if kt <= 0.6 {
+        (
+            0.512 - 1.56 * kt + 2.286 * kt.powi(2) - 2.222 * kt.powi(3),
+            0.37 + 0.962 * kt,
+            -0.28 + 0.932 * kt - 2.048 * kt.powi(2),
+        )
+    } else {
+        (
+            -5.743 + 21.77 * kt - 27.49 * kt.powi(2) + 11.56 * kt.powi(3),
+            41.4 - 118.5 * kt + 66.05 * kt.powi(2) + 31.9 * kt.powi(3),
+            -47.01 + 184.2 * kt - 222.0 * kt.powi(2) + 73.81 * kt.powi(3),
+        )
+    };
+    let delta_kn = a + b * (c * air_mass).exp();
+    let kn_clear = 0.866 - 0.122 * air_mass + 0.0121 * air_mass.powi(2)
+        - 0.000653 * air_mass.powi(3)
+        + 0.000014 * air_mass.powi(4);
+    ((kn_clear - delta_kn) * extraterrestrial_irradiance(input.day_of_year)).max(0.0)
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::epw;
+    use calendar::Date;
+    use solar::{Solar, Time};
+    use validate::assert_close;
+
+    #[test]
+    fn test_extraterrestrial_irradiance() {
+        // Closest to the sun in early January
+        assert_close!(extraterrestrial_irradiance(1.), 1412., 1.);
+        assert_close!(extraterrestrial_irradiance(183.), 1322., 1.);
+    }
+
+    #[test]
+    fn test_erbs() {
+        let model = DecompositionModel::Erbs;
+        let input = |global_horizontal: Float| DecompositionInput {
+            global_horizontal,
+            cos_zenith: 0.8,
+            day_of_year: 80.,
+            ..DecompositionInput::default()
+        };
+
+        // Overcast... mostly diffuse
+        let overcast = input(100.);
+        assert!(overcast.clearness_index() < 0.22);
+        assert_close!(
+            model.diffuse_fraction(&overcast),
+            1. - 0.09 * overcast.clearness_index()
+        );
+
+        // Clear... mostly direct
+        let clear = input(900.);
+        assert!(clear.clearness_index() > 0.8);
+        assert_close!(model.diffuse_fraction(&clear), 0.165);
+
+        // The components add up to the global horizontal
+        for ghi in [50., 300., 500., 700., 900.] {
+            let i = input(ghi);
+            let (dni, dhi) = model.decompose(&i);
+            assert!(dni >= 0.0 && dhi >= 0.0);
+            assert_close!(dni * i.cos_zenith + dhi, ghi, 1e-3);
+        }
+
+        // The sun is too low
+        let low = DecompositionInput {
+            global_horizontal: 20.,
+            cos_zenith: 0.01,
+            day_of_year: 80.,
+            ..DecompositionInput::default()
+        };
+        assert_eq!(model.decompose(&low), (0.0, 20.));
+    }
+
+    #[test]
+    fn test_equation_of_time() {
+        // Sundials are ahead in early November and behind in mid February
+        assert_close!(equation_of_time(307.), 16.4, 0.5);
+        assert_close!(equation_of_time(42.), -14.2, 0.5);
+    }
+
+    #[test]
+    fn test_relative_humidity() {
+        assert_close!(relative_humidity(20., 20.), 1.);
+        assert_close!(relative_humidity(20., 10.), 0.525, 0.01);
+        assert!(relative_humidity(20., 25.) <= 1.);
+    }
+
+    #[test]
+    fn test_reindl() {
+        let model = DecompositionModel::Reindl;
+        let input = |global_horizontal: Float| DecompositionInput {
+            global_horizontal,
+            cos_zenith: 0.8,
+            day_of_year: 80.,
+            ..DecompositionInput::default()
+        };
+
+        // Reduced correlation
+        let overcast = input(100.);
+        let kt = overcast.clearness_index();
+        assert_close!(
+            model.diffuse_fraction(&overcast),
+            1.020 - 0.254 * kt + 0.0123 * 0.8
+        );
+        let clear = input(900.);
+        let kt = clear.clearness_index();
+        assert_close!(
+            model.diffuse_fraction(&clear),
+            (0.486 * kt - 0.182 * 0.8).max(0.1)
+        );
+
+        // Full correlation
+        let humid = DecompositionInput {
+            dry_bulb_temperature: Some(20.),
+            relative_humidity: Some(0.5),
+            ..input(500.)
+        };
+        let kt = humid.clearness_index();
+        assert!(kt > 0.3 && kt < 0.78);
+        assert_close!(
+            model.diffuse_fraction(&humid),
+            (1.329 - 1.716 * kt + 0.267 * 0.8 - 0.00357 * 20. + 0.106 * 0.5).clamp(0.1, 0.97)
+        );
+
+        // Limits
+        for ghi in [10., 100., 300., 500., 700., 900., 1100.] {
+            let kd = model.diffuse_fraction(&input(ghi));
+            assert!((0.1..=1.0).contains(&kd), "kd = {}", kd);
+        }
+    }
+
+    #[test]
+    fn test_boland_ridley_lauret() {
+        let model = DecompositionModel::BolandRidleyLauret;
+        let input = DecompositionInput {
+            global_horizontal: 500.,
+            cos_zenith: 0.8,
+            day_of_year: 80.,
+            solar_time: 12.,
+            ..DecompositionInput::default()
+        };
+        let kt = input.clearness_index();
+        let altitude = (0.8 as Float).asin().to_degrees();
+        let exp = 1. / (1. + (-5.38 + 6.63 * kt + 0.072 - 0.007 * altitude + 3.06 * kt).exp());
+        assert_close!(model.diffuse_fraction(&input), exp, 1e-5);
+
+        // A cloudy day makes it more diffuse
+        let cloudy_day = DecompositionInput {
+            daily_clearness_index: Some(0.2),
+            persistence: Some(0.2),
+            ..input
+        };
+        assert!(model.diffuse_fraction(&cloudy_day) > model.diffuse_fraction(&input));
+    }
+
+    #[test]
+    fn test_disc() {
+        let model = DecompositionModel::Disc;
+        let input = |global_horizontal: Float| DecompositionInput {
+            global_horizontal,
+            cos_zenith: 0.8,
+            day_of_year: 172.,
+            ..DecompositionInput::default()
+        };
+
+        // Clear sky... strong direct normal irradiance
+        let (dni, dhi) = model.decompose(&input(800.));
+        assert!(dni > 650. && dni < 1000., "dni = {}", dni);
+        assert_close!(dni * 0.8 + dhi, 800., 1e-3);
+
+        // Overcast... little direct irradiance
+        let (dni, _) = model.decompose(&input(100.));
+        assert!(dni < 30., "dni = {}", dni);
+
+        // The standard pressure is assumed when missing
+        let standard = DecompositionInput {
+            pressure: Some(101325.),
+            ..input(600.)
+        };
+        assert_close!(
+            model.decompose(&standard).0,
+            model.decompose(&input(600.)).0
+        );
+        let high = DecompositionInput {
+            pressure: Some(70000.),
+            ..input(600.)
+        };
+        assert!((model.decompose(&high).0 - model.decompose(&input(600.)).0).abs() > 1.);
+    }
+
+    #[test]
+    fn test_barcelona() {
+        // The EPW file reports the global horizontal, direct normal and
+        // diffuse horizontal irradiance, so the decomposition models can
+        // be compared with the split data.
+        let path = "./tests/barcelona.epw";
+        let column = |i: usize, missing: Float| epw::read_column(path, i, missing, 0.0).unwrap();
+        let month = column(1, 99.);
+        let day = column(2, 99.);
+        let hour = column(3, 99.);
+        let dry_bulb = column(6, 99.9);
+        let dew_point = column(7, 99.9);
+        let pressure = column(9, 999999.);
+        let global = column(13, 9999.);
+        let direct_normal = column(14, 9999.);
+        let diffuse = column(15, 9999.);
+
+        // Barcelona, in UTC+1
+        let longitude: Float = 2.07;
+        let standard_meridian: Float = 15.;
+        let solar = Solar::new(
+            (41.28 as Float).to_radians(),
+            -longitude.to_radians(),
+            -standard_meridian.to_radians(),
+        );
+
+        // The inputs that do not depend on other hours
+        let mut inputs: Vec<DecompositionInput> = (0..global.len())
+            .map(|i| {
+                let date = Date {
+                    month: month[i] as u8,
+                    day: day[i] as u8,
+                    hour: hour[i] - 0.5,
+                };
+                let day_of_year = date.day_of_year();
+                let cos_zenith = solar
+                    .sun_position(Time::Standard(day_of_year))
+                    .map(|direction| direction.z)
+                    .unwrap_or(0.0);
+                DecompositionInput {
+                    global_horizontal: global[i],
+                    cos_zenith,
+                    day_of_year,
+                    solar_time: date.hour
+                        + (longitude - standard_meridian) / 15.
+                        + equation_of_time(day_of_year) / 60.,
+                    dry_bulb_temperature: Some(dry_bulb[i]),
+                    relative_humidity: Some(relative_humidity(dry_bulb[i], dew_point[i])),
+                    pressure: Some(pressure[i]),
+                    daily_clearness_index: None,
+                    persistence: None,
+                }
+            })
+            .collect();
+
+        // The daily clearness index and the persistence
+        let hourly_kt: Vec<Option<Float>> = inputs
+            .iter()
+            .map(|i| (i.cos_zenith >= MIN_COS_ZENITH).then(|| i.clearness_index()))
+            .collect();
+        for d in 0..inputs.len() / 24 {
+            let hours = d * 24..(d + 1) * 24;
+            let (mut global, mut extraterrestrial) = (0.0, 0.0);
+            for i in &inputs[hours.clone()] {
+                if i.cos_zenith > 0.0 {
+                    global += i.global_horizontal;
+                    extraterrestrial += extraterrestrial_irradiance(i.day_of_year) * i.cos_zenith;
+                }
+            }
+            for i in hours {
+                inputs[i].daily_clearness_index = Some(global / extraterrestrial);
+                let neighbours: Vec<Float> = [i.wrapping_sub(1), i + 1]
+                    .iter()
+                    .filter_map(|j| hourly_kt.get(*j).copied().flatten())
+                    .collect();
+                if !neighbours.is_empty() {
+                    inputs[i].persistence =
+                        Some(neighbours.iter().sum::<Float>() / neighbours.len() as Float);
+                }
+            }
+        }
+
+        // Compare the annual direct and diffuse horizontal irradiation
+        for model in [
+            DecompositionModel::Erbs,
+            DecompositionModel::Reindl,
+            DecompositionModel::BolandRidleyLauret,
+            DecompositionModel::Disc,
+        ] {
+            let (mut exp_direct, mut exp_diffuse) = (0.0, 0.0);
+            let (mut found_direct, mut found_diffuse) = (0.0, 0.0);
+            for (i, input) in inputs.iter().enumerate() {
+                if input.cos_zenith < MIN_COS_ZENITH || input.global_horizontal <= 0.0 {
+                    continue;
+                }
+                let (dni, dhi) = model.decompose(input);
+                assert_close!(dni * input.cos_zenith + dhi, input.global_horizontal, 1e-2);
+                exp_direct += direct_normal[i] * input.cos_zenith;
+                exp_diffuse += diffuse[i];
+                found_direct += dni * input.cos_zenith;
+                found_diffuse += dhi;
+            }
+            let direct_err = (found_direct - exp_direct) / exp_direct;
+            let diffuse_err = (found_diffuse - exp_diffuse) / exp_diffuse;
+            assert!(
+                direct_err.abs() < 0.15,
+                "{:?}: direct error {}",
+                model,
+                direct_err
+            );
+            assert!(
+                diffuse_err.abs() < 0.25,
+                "{:?}: diffuse error {}",
+                model,
+                diffuse_err
+            );
+        }
+    }
+}
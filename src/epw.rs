@@ -0,0 +1,213 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Reads the few bits of EPW files that the `weather` crate does not
+//! expose through `CurrentWeather`.
+
+use crate::Float;
+use calendar::Date;
+
+/// The number of header lines in an EPW file
+const N_HEADER_LINES: usize = 8;
+
+/// The (zero-based) index of the Albedo column in EPW data lines
+pub(crate) const ALBEDO_COLUMN: usize = 32;
+
+/// The value used by EPW files to flag missing albedo data
+pub(crate) const ALBEDO_MISSING: Float = 999.;
+
+/// Number of days before the first day of each month (non-leap year)
+const DAYS_BEFORE_MONTH: [usize; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+/// Gets the index of the EPW data line that contains a certain date.
+///
+/// EPW files report the hour that has just ended, so hour `1` corresponds
+/// to the period between 00:00 and 01:00. Midnight (i.e., hour `0`) closes
+/// the last hour of the previous day.
+pub(crate) fn hour_index(date: Date) -> usize {
+    let month = (date.month as usize).clamp(1, 12);
+    let day = (date.day as usize).max(1);
+    let hour = (date.hour.ceil() as usize).min(24);
+    ((DAYS_BEFORE_MONTH[month - 1] + day - 1) * 24 + hour + 8759) % 8760
+}
+
+/// Reads one column of the data section of an EPW file. Values equal to or
+/// greater than `missing` are replaced by `fallback`.
+pub(crate) fn read_column(
+    path: &str,
+    column: usize,
+    missing: Float,
+    fallback: Float,
+) -> Result<Vec<Float>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(_) => return Err(format!("Unable to read EPW file '{}'", path)),
+    };
+
+    let mut ret = Vec::with_capacity(8760);
+    for (line_n, line) in content.lines().enumerate().skip(N_HEADER_LINES) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let field = match line.split(',').nth(column) {
+            Some(v) => v.trim(),
+            None => {
+                return Err(format!(
+                    "Line {} of EPW file '{}' has no column {}",
+                    line_n + 1,
+                    path,
+                    column
+                ))
+            }
+        };
+        let v: Float = match field.parse() {
+            Ok(v) => v,
+            Err(_) => {
+                return Err(format!(
+                    "Unable to parse '{}' in line {} of EPW file '{}'",
+                    field,
+                    line_n + 1,
+                    path
+                ))
+            }
+        };
+        if v >= missing {
+            ret.push(fallback)
+        } else {
+            ret.push(v)
+        }
+    }
+    Ok(ret)
+}
+
+/// Reads the monthly ground temperatures (in C) in the `GROUND TEMPERATURES`
+/// header of an EPW file. EPW files report several depths (usually 0.5m,
+/// 2m and 4m) and `depth_index` selects one of them, starting from the shallowest.
+pub(crate) fn read_ground_temperatures(
+    path: &str,
+    depth_index: usize,
+) -> Result<[Float; 12], String> {
+    // Each depth has its depth, conductivity, density, specific heat and 12 temperatures
+    const FIELDS_PER_DEPTH: usize = 16;
+
+    let content = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(_) => return Err(format!("Unable to read EPW file '{}'", path)),
+    };
+    let line = match content
+        .lines()
+        .take(N_HEADER_LINES)
+        .find(|l| l.starts_with("GROUND TEMPERATURES"))
+    {
+        Some(v) => v,
+        None => {
+            return Err(format!(
+                "EPW file '{}' has no GROUND TEMPERATURES header",
+                path
+            ))
+        }
+    };
+
+    let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+    let n_depths: usize = match fields.get(1).map(|f| f.parse()) {
+        Some(Ok(v)) => v,
+        _ => {
+            return Err(format!(
+                "Unable to parse the number of ground temperature depths in EPW file '{}'",
+                path
+            ))
+        }
+    };
+    if depth_index >= n_depths {
+        return Err(format!(
+            "EPW file '{}' reports {} ground temperature depths... cannot get depth number {}",
+            path,
+            n_depths,
+            depth_index + 1
+        ));
+    }
+
+    let first = 2 + depth_index * FIELDS_PER_DEPTH + 4;
+    let mut ret = [0.0; 12];
+    for (month, v) in ret.iter_mut().enumerate() {
+        let field = fields.get(first + month).copied().unwrap_or("");
+        *v = match field.parse() {
+            Ok(v) => v,
+            Err(_) => {
+                return Err(format!(
+                    "Unable to parse ground temperature '{}' in EPW file '{}'",
+                    field, path
+                ))
+            }
+        };
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    #[test]
+    fn test_hour_index() {
+        let date = Date {
+            month: 1,
+            day: 1,
+            hour: 0.5,
+        };
+        assert_eq!(hour_index(date), 0);
+
+        let date = Date {
+            month: 1,
+            day: 2,
+            hour: 1.0,
+        };
+        assert_eq!(hour_index(date), 24);
+
+        let date = Date {
+            month: 12,
+            day: 31,
+            hour: 23.5,
+        };
+        assert_eq!(hour_index(date), 8759);
+
+        // Midnight closes the previous day
+        let date = Date {
+            month: 1,
+            day: 2,
+            hour: 0.0,
+        };
+        assert_eq!(hour_index(date), 23);
+    }
+
+    #[test]
+    fn test_read_ground_temperatures() {
+        let shallow = read_ground_temperatures("./tests/barcelona.epw", 0).unwrap();
+        assert_eq!(shallow[0], 8.93);
+        assert_eq!(shallow[11], 9.50);
+
+        let deep = read_ground_temperatures("./tests/barcelona.epw", 2).unwrap();
+        assert_eq!(deep[0], 12.32);
+        assert_eq!(deep[11], 13.37);
+
+        assert!(read_ground_temperatures("./tests/barcelona.epw", 3).is_err());
+        assert!(read_ground_temperatures("./tests/not_a_file.epw", 0).is_err());
+    }
+}
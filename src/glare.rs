@@ -0,0 +1,664 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Glare analysis from viewpoints inside the spaces.
+//!
+//! The vertical illuminance at the eye is calculated from a Daylight Coefficients
+//! matrix, and the glare sources are found in a fisheye (i.e., equidistant)
+//! image of the view. Each pixel is traced once into the scene, through the
+//! fenestrations it sees (using their visible transmittance). Pixels that end
+//! up seeing the sky or the ground take the luminance of that patch of the
+//! sky. Pixels that see a surface take the luminance
+//! $`L = \rho E / \pi`$ of a Lambertian reflector, where $`\rho`$ is the
+//! visible reflectance of the surface and its irradiance $`E`$ is calculated
+//! with Daylight Coefficients rendered by the `rendering` crate (so it
+//! includes inter-reflections). The sun is a source if the pixel in its
+//! direction sees the sky. These are then combined into the Daylight Glare
+//! Probability (DGP) and its simplified version (DGPs):
+//!
+//! $$ DGP = 5.87\times10^{-5} E_v + 9.18\times10^{-2} \log_{10}\left(1 + \sum_i \frac{L_i^2 \omega_i}{E_v^{1.87} P_i^2}\right) + 0.16 $$
+//!
+//! $$ DGP_s = 6.22\times10^{-5} E_v + 0.184 $$
+//!
+//! Pixels that see objects that are not part of the model (i.e., whose
+//! reflectance is unknown) are not considered glare sources.
+
+use crate::interior_radiation::{SpaceSide, SpaceSideKind};
+use crate::obstructions::Obstructions;
+use crate::solar_distribution::{angular_transmittance, SolarDistribution};
+use crate::{materials, Float, PI};
+use geometry3d::{Point3D, Ray3D, Vector3D};
+use matrix::Matrix;
+use rendering::{colour_matrix::*, DCFactory, Scene};
+use serde::{Deserialize, Serialize};
+use simple_model::SimpleModel;
+use solar::ReinhartSky;
+
+/// The solid angle of the solar disc, in sr
+pub const SUN_SOLID_ANGLE: Float = 6.8e-5;
+
+/// The distance travelled past a fenestration before checking for obstructions outside
+const FENESTRATION_OFFSET: Float = 1e-3;
+
+/// The distance between a surface seen by a pixel and the sensor that
+/// calculates its irradiance
+const SENSOR_OFFSET: Float = 1e-3;
+
+/// The maximum number of fenestrations a pixel can see through
+const MAX_FENESTRATIONS: usize = 4;
+
+/// A position and a direction of view inside a space
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Viewpoint {
+    /// The name of the viewpoint, for reporting
+    pub name: String,
+
+    /// The position of the eye
+    pub position: Point3D,
+
+    /// The direction of view
+    pub direction: Vector3D,
+}
+
+/// The viewpoints for glare analysis, and how they are evaluated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GlareOptions {
+    /// The viewpoints to evaluate
+    pub viewpoints: Vec<Viewpoint>,
+
+    /// The number of pixels along each side of the fisheye image
+    pub resolution: usize,
+
+    /// Pixels brighter than this factor times the average luminance of
+    /// the view are considered glare sources
+    pub source_factor: Float,
+
+    /// The number of bounces considered when calculating the vertical illuminance
+    pub max_depth: usize,
+}
+
+impl std::default::Default for GlareOptions {
+    fn default() -> Self {
+        Self {
+            viewpoints: Vec::new(),
+            resolution: 100,
+            source_factor: 5.,
+            max_depth: 3,
+        }
+    }
+}
+
+impl Viewpoint {
+    /// Finds the index of the space that contains the viewpoint, by looking
+    /// down at its floor. Returns `None` if what is below is not the side of
+    /// a surface or fenestration facing a space.
+    pub(crate) fn space(
+        &self,
+        model: &SimpleModel,
+        obstructions: &Obstructions,
+    ) -> Result<Option<usize>, String> {
+        let ray = Ray3D {
+            origin: self.position,
+            direction: Vector3D::new(0., 0., -1.),
+        };
+        let mut node_aux = Vec::with_capacity(2);
+        let side = match obstructions.cast(&ray, None, &mut node_aux)? {
+            Some((_, Some(side))) => side,
+            _ => return Ok(None),
+        };
+        let (front_boundary, back_boundary) = match side.kind {
+            SpaceSideKind::Surface => {
+                let s = &model.surfaces[side.index];
+                (&s.front_boundary, &s.back_boundary)
+            }
+            SpaceSideKind::Fenestration => {
+                let s = &model.fenestrations[side.index];
+                (&s.front_boundary, &s.back_boundary)
+            }
+        };
+        let boundary = if side.front {
+            front_boundary
+        } else {
+            back_boundary
+        };
+        SolarDistribution::space_index(model, boundary)
+    }
+}
+
+/// What a pixel of the fisheye image sees
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PixelTarget {
+    /// A bin of the Reinhart sky (`0` being the ground)
+    Sky(usize),
+
+    /// A surface (or an opaque fenestration) of the model
+    Surface {
+        /// The row of the `irradiance_dc` of the [`GlareView`] that gives
+        /// the irradiance on the surface
+        row: usize,
+
+        /// The visible reflectance of the side that is seen
+        reflectance: Float,
+    },
+}
+
+/// A pixel of the fisheye image that sees the sky, the ground or a surface of the model
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GlarePixel {
+    /// The index of the pixel in the image (i.e., `row * resolution + column`)
+    pub pixel: usize,
+
+    /// What the pixel sees
+    pub target: PixelTarget,
+
+    /// The visible transmittance of the fenestrations between the eye and
+    /// the target, in the direction of the pixel
+    pub transmittance: Float,
+
+    /// The solid angle of the pixel, in sr
+    pub solid_angle: Float,
+
+    /// The position index of the pixel
+    pub position_index: Float,
+}
+
+/// The glare results of a viewpoint during a timestep
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GlareResult {
+    /// The vertical illuminance at the eye, in lux
+    pub vertical_illuminance: Float,
+
+    /// The Daylight Glare Probability
+    pub dgp: Float,
+
+    /// The simplified Daylight Glare Probability, which only uses the vertical illuminance
+    pub dgps: Float,
+}
+
+/// The information needed for evaluating glare at a [`Viewpoint`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GlareView {
+    /// The viewpoint
+    pub viewpoint: Viewpoint,
+
+    /// The number of pixels along each side of the fisheye image
+    pub resolution: usize,
+
+    /// The Daylight Coefficients of the vertical illuminance at the eye (one row)
+    pub dc: Matrix,
+
+    /// The pixels that see the sky, the ground or a surface, sorted by `pixel`.
+    /// Missing in files written before format version 2, which are stale.
+    #[serde(default)]
+    pub pixels: Vec<GlarePixel>,
+
+    /// The Daylight Coefficients of the irradiance on the surfaces seen by
+    /// the pixels (one row per [`PixelTarget::Surface`])
+    #[serde(default = "Matrix::empty")]
+    pub irradiance_dc: Matrix,
+}
+
+/// Where the view from a pixel ends up
+enum Traced {
+    /// A bin of the Reinhart sky (`0` being the ground)
+    Sky(usize),
+
+    /// A side of a surface or fenestration, and the point where it is seen
+    Side(SpaceSide, Point3D),
+}
+
+/// Follows the view from a point in a certain direction, through the
+/// fenestrations, until it reaches the sky, the ground or a surface. Returns
+/// the visible transmittance of the fenestrations crossed and where the view
+/// ends, or `None` if it ends at an object that is not part of the model.
+fn trace(
+    model: &SimpleModel,
+    obstructions: &Obstructions,
+    transmittances: &[Float],
+    sky: &ReinhartSky,
+    mut ray: Ray3D,
+    node_aux: &mut Vec<usize>,
+) -> Result<Option<(Float, Traced)>, String> {
+    let mut transmittance = 1.0;
+    for _ in 0..=MAX_FENESTRATIONS {
+        let (t, side) = match obstructions.cast(&ray, None, node_aux)? {
+            None => {
+                let bin = if ray.direction.z < 0.0 {
+                    0
+                } else {
+                    sky.dir_to_bin(ray.direction)
+                };
+                return Ok(Some((transmittance, Traced::Sky(bin))));
+            }
+            Some((_, None)) => return Ok(None),
+            Some((t, Some(side))) => (t, side),
+        };
+        let point = ray.origin + ray.direction * t;
+        let normal_transmittance = match side.kind {
+            SpaceSideKind::Fenestration => transmittances[side.index],
+            SpaceSideKind::Surface => 0.0,
+        };
+        if normal_transmittance <= 0.0 {
+            return Ok(Some((transmittance, Traced::Side(side, point))));
+        }
+        // See through the fenestration
+        let normal = model.fenestrations[side.index].vertices.normal();
+        let cos = (normal * ray.direction).abs();
+        transmittance *= angular_transmittance(normal_transmittance, cos);
+        ray.origin = point + ray.direction * FENESTRATION_OFFSET;
+    }
+    Ok(None)
+}
+
+/// The right, up and forward axes of a view
+fn view_axes(direction: Vector3D) -> Result<(Vector3D, Vector3D, Vector3D), String> {
+    let forward = direction.get_normalized();
+    let vertical = Vector3D::new(0., 0., 1.);
+    let right = if forward.cross(vertical).length() < 1e-3 {
+        // Looking straight up or down
+        forward.get_perpendicular()?
+    } else {
+        forward.cross(vertical).get_normalized()
+    };
+    let up = right.cross(forward);
+    Ok((right, up, forward))
+}
+
+/// The position index of a direction, given in view coordinates (i.e., `x`
+/// to the right, `y` up and `z` forward). This uses Einhorn's version of
+/// Guth's position index above the line of sight, and Iwata's below it.
+pub fn position_index(x: Float, y: Float, z: Float) -> Float {
+    if y < 0.0 {
+        let r = (x * x + y * y).sqrt() / z.max(1e-3);
+        if r < 0.6 {
+            1. + 0.8 * r
+        } else {
+            1. + 1.2 * r
+        }
+    } else {
+        let sigma = z.clamp(-1., 1.).acos().to_degrees();
+        let tau = x.abs().atan2(y).to_degrees();
+        ((35.2 - 0.31889 * tau - 1.22 * (-2. * tau / 9.).exp()) * 1e-3 * sigma
+            + (21. + 0.26667 * tau - 0.002963 * tau * tau) * 1e-5 * sigma * sigma)
+            .exp()
+    }
+}
+
+/// The simplified Daylight Glare Probability
+pub fn simplified_dgp(vertical_illuminance: Float) -> Float {
+    (6.22e-5 * vertical_illuminance + 0.184).min(1.0)
+}
+
+/// The Daylight Glare Probability, from the vertical illuminance at the eye
+/// and the luminance (cd/m2), solid angle (sr) and position index of each source
+pub fn daylight_glare_probability(
+    vertical_illuminance: Float,
+    sources: &[(Float, Float, Float)],
+) -> Float {
+    let ev = vertical_illuminance;
+    if ev <= 0.0 {
+        return 0.0;
+    }
+    let sum: Float = sources
+        .iter()
+        .map(|(l, omega, p)| l * l * omega / (ev.powf(1.87) * p * p))
+        .sum();
+    let mut dgp = 5.87e-5 * ev + 9.18e-2 * (1. + sum).log10() + 0.16;
+    // Low-light correction
+    if ev < 1000. {
+        let c = (0.024 * ev - 4.).exp();
+        dgp *= c / (1. + c);
+    }
+    dgp.min(1.0)
+}
+
+impl GlareView {
+    /// Prepares a [`Viewpoint`] for glare analysis. The `scene` is meant to be
+    /// built with `Wavelengths::Visible`, and `mf` is the discretization of the
+    /// Reinhart sky used by the `factory`.
+    pub fn new(
+        viewpoint: Viewpoint,
+        options: &GlareOptions,
+        model: &SimpleModel,
+        obstructions: &Obstructions,
+        scene: &Scene,
+        factory: &DCFactory,
+        mf: usize,
+    ) -> Result<Self, String> {
+        let resolution = options.resolution;
+        if resolution == 0 {
+            return Err("The resolution of glare images needs to be at least 1".into());
+        }
+        let (right, up, forward) = view_axes(viewpoint.direction)?;
+
+        let ray = Ray3D {
+            origin: viewpoint.position,
+            direction: forward,
+        };
+        let dc = colour_matrix_to_radiance(&factory.calc_dc(&[ray], scene));
+
+        let sky = ReinhartSky::new(mf);
+        let mut transmittances = Vec::with_capacity(model.fenestrations.len());
+        for fen in model.fenestrations.iter() {
+            transmittances.push(materials::visible_transmittance(model, &fen.construction)?);
+        }
+
+        let pixel_size = 2. / resolution as Float;
+        let mut pixels = Vec::new();
+        let mut sensors = Vec::new();
+        let mut node_aux = Vec::with_capacity(2);
+        for row in 0..resolution {
+            let v = (row as Float + 0.5) * pixel_size - 1.;
+            for column in 0..resolution {
+                let u = (column as Float + 0.5) * pixel_size - 1.;
+                let r = (u * u + v * v).sqrt();
+                if r > 1.0 {
+                    continue;
+                }
+                let theta = r * PI / 2.;
+                let (x, y) = if r > 0.0 {
+                    (u / r * theta.sin(), v / r * theta.sin())
+                } else {
+                    (0.0, 0.0)
+                };
+                let z = theta.cos();
+                let direction = (right * x + up * y + forward * z).get_normalized();
+
+                let ray = Ray3D {
+                    origin: viewpoint.position,
+                    direction,
+                };
+                let (transmittance, traced) = match trace(
+                    model,
+                    obstructions,
+                    &transmittances,
+                    &sky,
+                    ray,
+                    &mut node_aux,
+                )? {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let target = match traced {
+                    Traced::Sky(bin) => PixelTarget::Sky(bin),
+                    Traced::Side(side, point) => {
+                        let (construction, polygon) = match side.kind {
+                            SpaceSideKind::Surface => {
+                                let s = &model.surfaces[side.index];
+                                (&s.construction, &s.vertices)
+                            }
+                            SpaceSideKind::Fenestration => {
+                                let s = &model.fenestrations[side.index];
+                                (&s.construction, &s.vertices)
+                            }
+                        };
+                        // The front side faces the direction of the normal
+                        let normal = if side.front {
+                            polygon.normal()
+                        } else {
+                            polygon.normal() * -1.
+                        };
+                        sensors.push(Ray3D {
+                            origin: point + normal * SENSOR_OFFSET,
+                            direction: normal,
+                        });
+                        PixelTarget::Surface {
+                            row: sensors.len() - 1,
+                            reflectance: materials::visible_reflectance(
+                                model,
+                                construction,
+                                side.front,
+                            )?,
+                        }
+                    }
+                };
+                let solid_angle = if theta > 0.0 {
+                    theta.sin() / theta
+                } else {
+                    1.0
+                } * (PI / 2.).powi(2)
+                    * pixel_size
+                    * pixel_size;
+                pixels.push(GlarePixel {
+                    pixel: row * resolution + column,
+                    target,
+                    transmittance,
+                    solid_angle,
+                    position_index: position_index(x, y, z),
+                });
+            }
+        }
+
+        // The surfaces seen are shaded with the same inter-reflections
+        // used for the vertical illuminance
+        let irradiance_dc = if sensors.is_empty() {
+            Matrix::empty()
+        } else {
+            colour_matrix_to_radiance(&factory.calc_dc(&sensors, scene))
+        };
+
+        Ok(Self {
+            viewpoint,
+            resolution,
+            dc,
+            pixels,
+            irradiance_dc,
+        })
+    }
+
+    /// Finds the pixel that sees in a certain direction, if it sees
+    /// the sky, the ground or a surface of the model
+    pub fn pixel(&self, direction: Vector3D) -> Result<Option<&GlarePixel>, String> {
+        let (right, up, forward) = view_axes(self.viewpoint.direction)?;
+        let direction = direction.get_normalized();
+        let z = direction * forward;
+        if z <= 0.0 {
+            return Ok(None);
+        }
+        let (x, y) = (direction * right, direction * up);
+        let theta = z.clamp(-1., 1.).acos();
+        let r = theta / (PI / 2.);
+        let phi = y.atan2(x);
+        let u = r * phi.cos();
+        let v = r * phi.sin();
+        let n = self.resolution as Float;
+        let column = (((u + 1.) / 2. * n) as usize).min(self.resolution - 1);
+        let row = (((v + 1.) / 2. * n) as usize).min(self.resolution - 1);
+        let pixel = row * self.resolution + column;
+        Ok(self
+            .pixels
+            .binary_search_by_key(&pixel, |p| p.pixel)
+            .ok()
+            .map(|i| &self.pixels[i]))
+    }
+
+    /// Evaluates glare, given the vertical illuminance at the eye (in lux), the
+    /// visible luminance of each bin of the sky without the sun (in cd/m2), the
+    /// illuminance on each of the surfaces seen (in lux, following the rows of
+    /// the `irradiance_dc`), and the direction and normal illuminance (in lux)
+    /// of the sun, if it is up.
+    pub fn evaluate(
+        &self,
+        vertical_illuminance: Float,
+        sky_luminance: &[Float],
+        surface_illuminance: &[Float],
+        sun: Option<(Vector3D, Float)>,
+        source_factor: Float,
+    ) -> Result<GlareResult, String> {
+        let threshold = source_factor * vertical_illuminance / PI;
+        let mut sources = Vec::new();
+        for p in self.pixels.iter() {
+            let luminance = match p.target {
+                PixelTarget::Sky(bin) => sky_luminance.get(bin).copied().unwrap_or(0.0),
+                PixelTarget::Surface { row, reflectance } => {
+                    reflectance * surface_illuminance.get(row).copied().unwrap_or(0.0) / PI
+                }
+            } * p.transmittance;
+            if luminance > threshold && luminance > 0.0 {
+                sources.push((luminance, p.solid_angle, p.position_index));
+            }
+        }
+        if let Some((direction, normal_illuminance)) = sun {
+            if let Some(p) = self.pixel(direction)? {
+                if matches!(p.target, PixelTarget::Sky(bin) if bin > 0) {
+                    let luminance = normal_illuminance * p.transmittance / SUN_SOLID_ANGLE;
+                    if luminance > 0.0 {
+                        sources.push((luminance, SUN_SOLID_ANGLE, p.position_index));
+                    }
+                }
+            }
+        }
+        Ok(GlareResult {
+            vertical_illuminance,
+            dgp: daylight_glare_probability(vertical_illuminance, &sources),
+            dgps: simplified_dgp(vertical_illuminance),
+        })
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use validate::assert_close;
+
+    #[test]
+    fn test_dgp() {
+        assert_close!(simplified_dgp(2000.), 0.3084, 1e-4);
+        assert_close!(daylight_glare_probability(2000., &[]), 0.2774, 1e-4);
+        assert_close!(
+            daylight_glare_probability(2000., &[(10000., 0.01, 1.)]),
+            0.29788,
+            1e-4
+        );
+        // Low-light correction
+        assert_close!(daylight_glare_probability(500., &[]), 0.18929, 1e-4);
+        assert_close!(daylight_glare_probability(0., &[]), 0.0);
+    }
+
+    #[test]
+    fn test_position_index() {
+        // Straight ahead
+        assert_close!(position_index(0., 0., 1.), 1.0);
+        // 30 degrees above
+        let a = (30. as Float).to_radians();
+        assert_close!(position_index(0., a.sin(), a.cos()), 3.3481, 1e-3);
+        // Below the line of sight
+        assert_close!(position_index(0., -0.4, 1.), 1.32, 1e-4);
+        assert_close!(position_index(0., -1., 1.), 2.2, 1e-4);
+    }
+
+    #[test]
+    fn test_glare_view() {
+        let (model, _) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut scene = Scene::from_simple_model(&model, rendering::Wavelengths::Visible).unwrap();
+        scene.build_accelerator();
+        let obstructions = Obstructions::new(&model, &scene).unwrap();
+        let factory = DCFactory {
+            max_depth: 0,
+            n_ambient_samples: 300,
+            reinhart: ReinhartSky::new(1),
+            ..DCFactory::default()
+        };
+        let options = GlareOptions {
+            resolution: 40,
+            ..GlareOptions::default()
+        };
+
+        // Looking at the window, in the south wall
+        let towards_window = Viewpoint {
+            name: "towards window".into(),
+            position: Point3D::new(2., 2., 1.2),
+            direction: Vector3D::new(0., -1., 0.),
+        };
+        let view = GlareView::new(
+            towards_window,
+            &options,
+            &model,
+            &obstructions,
+            &scene,
+            &factory,
+            1,
+        )
+        .unwrap();
+        assert_eq!(view.dc.size().0, 1);
+        let is_sky = |p: &&GlarePixel| matches!(p.target, PixelTarget::Sky(_));
+        let sky_pixels: Vec<&GlarePixel> = view.pixels.iter().filter(is_sky).collect();
+        assert!(!sky_pixels.is_empty());
+        assert!(sky_pixels
+            .iter()
+            .all(|p| p.transmittance > 0.0 && p.transmittance <= 0.8));
+        // The walls around the window are seen too, without glazing in between
+        let n_surfaces = view.pixels.len() - sky_pixels.len();
+        assert!(n_surfaces > 0);
+        assert_eq!(view.irradiance_dc.size().0, n_surfaces);
+        for p in view.pixels.iter() {
+            if let PixelTarget::Surface { reflectance, .. } = p.target {
+                assert_close!(reflectance, 0.4, 1e-6);
+                assert_close!(p.transmittance, 1.0);
+            }
+        }
+        let pixel = view.pixel(Vector3D::new(0., -1., 0.)).unwrap().unwrap();
+        assert!(matches!(pixel.target, PixelTarget::Sky(_)));
+
+        // A bright sky is a glare source... and the sun more so
+        let n_bins = ReinhartSky::n_bins(1);
+        let sky = vec![5000.; n_bins];
+        let dark = vec![0.0; n_surfaces];
+        let no_sun = view.evaluate(1000., &sky, &dark, None, 5.).unwrap();
+        assert!(no_sun.dgp > daylight_glare_probability(1000., &[]));
+        let sun = Some((Vector3D::new(0., -1., 0.1), 80000.));
+        let with_sun = view.evaluate(1000., &sky, &dark, sun, 5.).unwrap();
+        assert!(with_sun.dgp > no_sun.dgp);
+        assert_close!(with_sun.dgps, simplified_dgp(1000.));
+
+        // Brightly lit walls are glare sources as well
+        let bright = vec![1e6; n_surfaces];
+        let bright_walls = view.evaluate(1000., &sky, &bright, None, 5.).unwrap();
+        assert!(bright_walls.dgp > no_sun.dgp);
+
+        // Looking at the back wall, there is no sky to be seen
+        let towards_wall = Viewpoint {
+            name: "towards wall".into(),
+            position: Point3D::new(2., 2., 1.2),
+            direction: Vector3D::new(0., 1., 0.),
+        };
+        let view = GlareView::new(
+            towards_wall,
+            &options,
+            &model,
+            &obstructions,
+            &scene,
+            &factory,
+            1,
+        )
+        .unwrap();
+        assert!(view
+            .pixels
+            .iter()
+            .all(|p| matches!(p.target, PixelTarget::Surface { .. })));
+        assert_eq!(view.irradiance_dc.size().0, view.pixels.len());
+
+        // Dimly lit walls are not glare sources
+        let dim = vec![100.; view.pixels.len()];
+        let result = view.evaluate(1000., &sky, &dim, sun, 5.).unwrap();
+        assert_close!(result.dgp, daylight_glare_probability(1000., &[]));
+    }
+}
@@ -0,0 +1,476 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Calculates the longwave (i.e., infrared) radiation exchanged between the
+//! surfaces and fenestrations that bound the same space.
+//!
+//! The view factors between the sides are ray-traced: directions are sampled
+//! from the hemisphere of points over each side with a cosine-weighted
+//! distribution, and cast into the [`rendering::Scene`] of the model. The
+//! fraction of the rays leaving side $`i`$ that hit side $`j`$ is the view
+//! factor $`F_{ij}`$, so obstructions (e.g., in L-shaped spaces or behind
+//! internal partitions) are accounted for.
+//!
+//! The view factors are then corrected so that they are reciprocal (i.e.,
+//! $`A_i F_{ij} = A_j F_{ji}`$) and add up to one. The exchange
+//! $`G_{ij} = A_i F_{ij}`$ is made symmetric, and then scaled as
+//! $`s_i G_{ij} s_j`$, iterating over the factors $`s_i`$ until every
+//! row adds up to $`A_i`$. This scaling keeps the symmetry, so the
+//! corrected view factors are still reciprocal.
+
+use crate::model::black_body;
+use crate::obstructions::Obstructions;
+use crate::solar_distribution::SolarDistribution;
+use crate::solar_surface::SolarSurface;
+use crate::view_factors::cosine_weighted_direction;
+use crate::Float;
+use geometry3d::{Polygon3D, Ray3D};
+use serde::{Deserialize, Serialize};
+use simple_model::{Boundary, SimpleModel};
+
+/// The maximum number of iterations when solving the radiosity within a space
+const MAX_RADIOSITY_ITERATIONS: usize = 200;
+
+/// The (relative) change in radiosity below which the iterations stop
+const RADIOSITY_TOLERANCE: Float = 1e-6;
+
+/// The maximum number of iterations when correcting the view factors
+const MAX_CLOSURE_ITERATIONS: usize = 1000;
+
+/// How far from one can the sum of the view factors of a side be, after
+/// the correction
+const CLOSURE_TOLERANCE: Float = 1e-6;
+
+/// The kind of object that bounds a space (or obstructs the view of another)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpaceSideKind {
+    /// A `Surface`
+    Surface,
+    /// A `Fenestration`
+    Fenestration,
+}
+
+/// A side of a surface or fenestration (e.g., one that faces a space)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpaceSide {
+    /// Whether this is a surface or a fenestration
+    pub kind: SpaceSideKind,
+
+    /// The index of the surface (or fenestration) in the model
+    pub index: usize,
+
+    /// Whether this is the front side
+    pub front: bool,
+}
+
+/// The view factors between the sides of the surfaces and fenestrations
+/// bounding a space.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InteriorViewFactors {
+    /// The name of the space
+    pub space: String,
+
+    /// The sides that bound the space
+    pub sides: Vec<SpaceSide>,
+
+    /// The element `[i][j]` is the fraction of the radiation leaving
+    /// `sides[i]` that reaches `sides[j]`
+    pub view_factors: Vec<Vec<Float>>,
+}
+
+/// A side facing a space, with the data needed for calculating view factors
+struct SampledSide {
+    side: SpaceSide,
+    area: Float,
+    rays: Vec<Ray3D>,
+}
+
+impl SampledSide {
+    fn new(side: SpaceSide, polygon: &Polygon3D, n_points: usize) -> Result<Self, String> {
+        let surface = SolarSurface::new(n_points, polygon, false, false)?;
+        let rays = if side.front {
+            surface.front_rays()
+        } else {
+            surface.back_rays()
+        };
+        Ok(Self {
+            side,
+            area: polygon.area(),
+            rays,
+        })
+    }
+}
+
+/// Makes the view factors of a space reciprocal and adds up to one, by
+/// scaling the (symmetric) exchange between each pair of sides. Sides that
+/// see nothing are left as they are.
+fn reciprocal_closure(areas: &[Float], view_factors: &[Vec<Float>]) -> Vec<Vec<Float>> {
+    let n = areas.len();
+    let mut exchange = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..n {
+            exchange[i][j] = 0.5 * (areas[i] * view_factors[i][j] + areas[j] * view_factors[j][i]);
+        }
+    }
+
+    let mut scale = vec![1.0; n];
+    for _ in 0..MAX_CLOSURE_ITERATIONS {
+        let mut max_error: Float = 0.0;
+        for i in 0..n {
+            let total: Float = (0..n).map(|j| scale[i] * exchange[i][j] * scale[j]).sum();
+            if total <= 0.0 {
+                continue;
+            }
+            max_error = max_error.max((total / areas[i] - 1.).abs());
+            scale[i] *= (areas[i] / total).sqrt();
+        }
+        if max_error <= CLOSURE_TOLERANCE {
+            break;
+        }
+    }
+
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| scale[i] * exchange[i][j] * scale[j] / areas[i])
+                .collect()
+        })
+        .collect()
+}
+
+impl InteriorViewFactors {
+    /// Calculates the view factors within each space of a model, casting
+    /// `n_samples` rays from each of `n_points` points sampled over each side.
+    /// The `obstructions` tell which side each ray hits.
+    ///
+    /// The view factors given in `reuse` (one element per space) are used
+    /// instead of being calculated, if they are bounded by the same sides.
+    pub(crate) fn calc(
+        model: &SimpleModel,
+        obstructions: &Obstructions,
+        n_points: usize,
+        n_samples: usize,
+        reuse: &[Option<Self>],
+    ) -> Result<Vec<Self>, String> {
+        let mut sides: Vec<Vec<SampledSide>> = model.spaces.iter().map(|_| Vec::new()).collect();
+
+        let mut add_sides = |kind: SpaceSideKind,
+                             index: usize,
+                             polygon: &Polygon3D,
+                             front_boundary: &Boundary,
+                             back_boundary: &Boundary|
+         -> Result<(), String> {
+            for (front, boundary) in [(true, front_boundary), (false, back_boundary)] {
+                if let Some(space) = SolarDistribution::space_index(model, boundary)? {
+                    let side = SpaceSide { kind, index, front };
+                    sides[space].push(SampledSide::new(side, polygon, n_points)?);
+                }
+            }
+            Ok(())
+        };
+        for (index, s) in model.surfaces.iter().enumerate() {
+            add_sides(
+                SpaceSideKind::Surface,
+                index,
+                &s.vertices,
+                &s.front_boundary,
+                &s.back_boundary,
+            )?;
+        }
+        for (index, s) in model.fenestrations.iter().enumerate() {
+            add_sides(
+                SpaceSideKind::Fenestration,
+                index,
+                &s.vertices,
+                &s.front_boundary,
+                &s.back_boundary,
+            )?;
+        }
+
+        let mut rng = rendering::rand::get_rng();
+        let mut node_aux = Vec::with_capacity(2);
+        let mut ret = Vec::with_capacity(model.spaces.len());
+        for (i, (space, sides)) in model.spaces.iter().zip(sides.iter()).enumerate() {
+            if let Some(Some(previous)) = reuse.get(i) {
+                let same_sides = previous.space == space.name
+                    && previous.sides.len() == sides.len()
+                    && previous
+                        .sides
+                        .iter()
+                        .zip(sides.iter())
+                        .all(|(a, b)| *a == b.side);
+                if same_sides {
+                    ret.push(previous.clone());
+                    continue;
+                }
+            }
+            let n = sides.len();
+            let mut view_factors = vec![vec![0.0; n]; n];
+            for (from, row) in sides.iter().zip(view_factors.iter_mut()) {
+                let own = Some((from.side.kind, from.side.index));
+                for r in from.rays.iter() {
+                    let normal = r.direction;
+                    let e1 = normal.get_perpendicular()?;
+                    let e2 = normal.cross(e1);
+                    for _ in 0..n_samples {
+                        let ray = Ray3D {
+                            origin: r.origin,
+                            direction: cosine_weighted_direction(&mut rng, e1, e2, normal),
+                        };
+                        if let Some((_, Some(hit))) = obstructions.cast(&ray, own, &mut node_aux)? {
+                            if let Some(j) = sides.iter().position(|s| s.side == hit) {
+                                row[j] += 1.;
+                            }
+                        }
+                    }
+                }
+                let total = (from.rays.len() * n_samples) as Float;
+                if total > 0.0 {
+                    row.iter_mut().for_each(|v| *v /= total);
+                }
+            }
+
+            let areas: Vec<Float> = sides.iter().map(|s| s.area).collect();
+            ret.push(Self {
+                space: space.name.clone(),
+                sides: sides.iter().map(|s| s.side).collect(),
+                view_factors: reciprocal_closure(&areas, &view_factors),
+            })
+        }
+        Ok(ret)
+    }
+
+    /// Calculates the infrared irradiance incident on each side of the space,
+    /// given their temperatures (in C) and emissivities.
+    ///
+    /// The radiosity $`J`$ of each side is solved iteratively, as
+    /// $`J_i = \varepsilon_i \sigma T_i^4 + (1 - \varepsilon_i) \sum_j F_{ij} J_j`$
+    pub(crate) fn incident_irradiance(
+        &self,
+        temperatures: &[Float],
+        emissivities: &[Float],
+    ) -> Result<Vec<Float>, String> {
+        let n = self.sides.len();
+        if temperatures.len() != n || emissivities.len() != n {
+            return Err(format!(
+                "Expecting temperatures and emissivities for the {} sides of space '{}'... found {} and {}",
+                n,
+                self.space,
+                temperatures.len(),
+                emissivities.len()
+            ));
+        }
+
+        let emitted: Vec<Float> = temperatures
+            .iter()
+            .zip(emissivities.iter())
+            .map(|(t, e)| e * black_body(*t))
+            .collect();
+        let mut radiosity = emitted.clone();
+        let mut incident = vec![0.0; n];
+        for _ in 0..MAX_RADIOSITY_ITERATIONS {
+            for (i, row) in self.view_factors.iter().enumerate() {
+                incident[i] = row.iter().zip(radiosity.iter()).map(|(f, j)| f * j).sum();
+            }
+            let mut max_change: Float = 0.0;
+            let mut max_value: Float = 0.0;
+            for i in 0..n {
+                let v = emitted[i] + (1. - emissivities[i]) * incident[i];
+                max_change = max_change.max((v - radiosity[i]).abs());
+                max_value = max_value.max(v.abs());
+                radiosity[i] = v;
+            }
+            if max_change <= RADIOSITY_TOLERANCE * max_value {
+                break;
+            }
+        }
+
+        // Sides that see nothing (e.g., a space bounded by a single surface)
+        // exchange nothing.
+        for (i, row) in self.view_factors.iter().enumerate() {
+            if row.iter().sum::<Float>() <= 0.0 {
+                incident[i] = black_body(temperatures[i]);
+            }
+        }
+
+        Ok(incident)
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::analytical::parallel_rectangles;
+    use crate::model::SIGMA;
+    use rendering::{Scene, Wavelengths};
+    use validate::assert_close;
+
+    /// Calculates the interior view factors of a model
+    fn interior_view_factors(file: &str) -> Vec<InteriorViewFactors> {
+        let (model, _) = SimpleModel::from_file(file).unwrap();
+        let mut scene = Scene::from_simple_model(&model, Wavelengths::Solar).unwrap();
+        scene.build_accelerator();
+        let obstructions = Obstructions::new(&model, &scene).unwrap();
+        InteriorViewFactors::calc(&model, &obstructions, 30, 1000, &[]).unwrap()
+    }
+
+    /// Checks that the view factors of a space are reciprocal and add up to one
+    fn assert_reciprocal_closure(views: &InteriorViewFactors, areas: &[Float]) {
+        for (i, row) in views.view_factors.iter().enumerate() {
+            assert_close!(row.iter().sum::<Float>(), 1.0, 1e-4);
+            assert_close!(row[i], 0.0);
+            for (j, v) in row.iter().enumerate() {
+                assert_close!(areas[i] * v, areas[j] * views.view_factors[j][i], 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cube() {
+        let views = interior_view_factors("./tests/cube/cube.spl");
+        assert_eq!(views.len(), 1);
+        let views = &views[0];
+        assert_eq!(views.space, "the box");
+        assert_eq!(views.sides.len(), 6);
+        assert!(views.sides.iter().all(|s| !s.front));
+        assert_reciprocal_closure(views, &[1.; 6]);
+
+        let opposite = parallel_rectangles(1., 1., 1.);
+        let adjacent = (1. - opposite) / 4.;
+        // Surfaces are floor, ceiling, south, east, north and west
+        assert_close!(views.view_factors[0][1], opposite, 0.05);
+        assert_close!(views.view_factors[1][0], opposite, 0.05);
+        assert_close!(views.view_factors[0][2], adjacent, 0.05);
+        assert_close!(views.view_factors[2][4], opposite, 0.05);
+    }
+
+    #[test]
+    fn test_partition() {
+        let views = interior_view_factors("./tests/partition/partition.spl");
+        let views = &views[0];
+
+        // Both sides of the partition face the space
+        assert_eq!(views.sides.len(), 8);
+        let side = |index: usize, front: bool| {
+            views
+                .sides
+                .iter()
+                .position(|s| s.index == index && s.front == front)
+                .unwrap()
+        };
+        // Floor, ceiling, south, east, north, west and partition
+        let surface_areas = [2., 2., 2., 1., 2., 1., 1.];
+        let areas: Vec<Float> = views.sides.iter().map(|s| surface_areas[s.index]).collect();
+        assert_reciprocal_closure(views, &areas);
+
+        // The east and west walls cannot see each other... only the
+        // side of the partition that faces them
+        let (east, west) = (side(3, false), side(5, false));
+        let (partition_front, partition_back) = (side(6, true), side(6, false));
+        assert_close!(views.view_factors[east][west], 0.0);
+        assert_close!(views.view_factors[west][east], 0.0);
+        let facing = parallel_rectangles(1., 1., 1.);
+        let (towards_east, towards_west) = if views.view_factors[east][partition_front] > 0.0 {
+            (partition_front, partition_back)
+        } else {
+            (partition_back, partition_front)
+        };
+        assert_close!(views.view_factors[east][towards_west], 0.0);
+        assert_close!(views.view_factors[west][towards_east], 0.0);
+        assert_close!(views.view_factors[east][towards_east], facing, 0.05);
+        assert_close!(views.view_factors[west][towards_west], facing, 0.05);
+    }
+
+    #[test]
+    fn test_reciprocal_closure() {
+        // Reciprocal but not closed, with different areas
+        let areas = [1., 2., 3.];
+        let view_factors = vec![
+            vec![0.0, 0.4, 0.3],
+            vec![0.2, 0.0, 0.6],
+            vec![0.1, 0.4, 0.0],
+        ];
+        let views = InteriorViewFactors {
+            space: "space".into(),
+            sides: Vec::new(),
+            view_factors: reciprocal_closure(&areas, &view_factors),
+        };
+        assert_reciprocal_closure(&views, &areas);
+    }
+
+    #[test]
+    fn test_parallel_plates_exchange() {
+        // Two infinite parallel plates
+        let views = InteriorViewFactors {
+            space: "plates".into(),
+            sides: vec![
+                SpaceSide {
+                    kind: SpaceSideKind::Surface,
+                    index: 0,
+                    front: true,
+                },
+                SpaceSide {
+                    kind: SpaceSideKind::Surface,
+                    index: 1,
+                    front: true,
+                },
+            ],
+            view_factors: vec![vec![0., 1.], vec![1., 0.]],
+        };
+        let temperatures = [30., 10.];
+        let emissivities = [0.8, 0.6];
+        let incident = views
+            .incident_irradiance(&temperatures, &emissivities)
+            .unwrap();
+
+        let expected = (black_body(30.) - black_body(10.))
+            / (1. / emissivities[0] + 1. / emissivities[1] - 1.);
+        let gain_1 = emissivities[1] * (incident[1] - black_body(10.));
+        let gain_0 = emissivities[0] * (incident[0] - black_body(30.));
+        assert_close!(gain_1, expected, 1e-3);
+        assert_close!(gain_0, -expected, 1e-3);
+
+        // Same temperature, no exchange
+        let incident = views
+            .incident_irradiance(&[20., 20.], &emissivities)
+            .unwrap();
+        let t = 20. + 273.15 as Float;
+        assert_close!(incident[0], SIGMA * t.powi(4), 1e-3);
+        assert_close!(incident[1], SIGMA * t.powi(4), 1e-3);
+
+        // Wrong input
+        assert!(views.incident_irradiance(&[20.], &emissivities).is_err());
+    }
+
+    #[test]
+    fn test_lonely_side() {
+        let views = InteriorViewFactors {
+            space: "lonely".into(),
+            sides: vec![SpaceSide {
+                kind: SpaceSideKind::Fenestration,
+                index: 0,
+                front: false,
+            }],
+            view_factors: vec![vec![0.]],
+        };
+        let incident = views.incident_irradiance(&[25.], &[0.9]).unwrap();
+        assert_close!(incident[0], black_body(25.));
+    }
+}
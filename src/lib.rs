@@ -23,9 +23,15 @@ SOFTWARE.
 //! This is [SIMPLE's](https://www.simplesim.tools) Light and Solar calculation module. It is responsible for:
 //!
 //! * **Calculating Incident Solar Radiation in each surface**: Contrary to EnergyPlus (and probably other tools I am less familiar with), this module uses Daylight Coefficients for performing this simulation. This method was stolen from the
-//! daylighting simulation world, and has the advantage of being extremely robust, and therefore capable of handling complex geometries. Perhaps the main drawback is that—because the concept of Thermal Zone does not fit within Lighting calculations (it is quite artificial for radiation purposes, actually)—reporting the "Solar Heat Gains" in a zone needs significant post-processing.
-//! * **Calculating view factors for Infrared calculations**: This is in development... for now, it does nothing.
-//! * **Daylighting Calculations**: Because this module is based on ray-tracing, it can perform daylight calculations. It is unclear, however, which climate based daylight metrics to include and how... if you have any idea, let me know.
+//! daylighting simulation world, and has the advantage of being extremely robust, and therefore capable of handling complex geometries. Perhaps the main drawback is that—because the concept of Thermal Zone does not fit within Lighting calculations (it is quite artificial for radiation purposes, actually)—reporting the "Solar Heat Gains" in a zone needs some post-processing. This is done by the [`solar_gains`] module, which sums the solar radiation transmitted into and absorbed by each space.
+//! * **Calculating view factors for Infrared calculations**: The view of each side of surfaces and fenestrations is split into sky, ground, air and (optionally) the other surfaces of the model, by sampling cosine-weighted rays.
+//! * **Daylighting Calculations**: Because this module is based on ray-tracing, it can perform daylight calculations. The illuminance is calculated at grids of sensors laid over the floors of each space (see the [`daylight`] module), and climate-based daylight metrics are calculated from it (see the [`metrics`] module). Glare can also be evaluated from viewpoints inside the spaces (see the [`glare`] module).
+//!
+//! Each call to `march()` simulates the timestep that ends at the given date. How the
+//! irradiance represents that timestep is set by the [`TimestepAveraging`] option. Its default
+//! (i.e., [`TimestepAveraging::TrailingAverage`]) averages each timestep with the previous one,
+//! for both the solar and the infrared irradiance. The first timestep has no previous one, so it
+//! is not averaged. Use [`TimestepAveraging::Instantaneous`] for the old infrared behaviour.
 
 /// The kind of Floating point number used in the
 /// library... the `"float"` feature means it becomes `f32`
@@ -50,6 +56,52 @@ pub const PI: Float = std::f64::consts::PI;
 /// calculating solar and lighting factors.
 pub mod model;
 pub use model::SolarModel;
+#[cfg(test)]
+mod analytical;
+/// Illuminance sensors and daylighting calculations
+pub mod daylight;
+/// Decomposition of the global horizontal irradiance
+pub mod decomposition;
+mod epw;
+/// Glare analysis from viewpoints inside the spaces
+pub mod glare;
+/// Longwave radiation exchange between the surfaces bounding each space
+pub mod interior_radiation;
+/// Daylight-linked control of the electric lighting
+pub mod lighting_control;
+mod materials;
+/// Climate-based daylight metrics
+pub mod metrics;
+mod obstructions;
+mod optical_file;
 mod optical_info;
+/// Options for the [`SolarModel`] that complement those in
+/// `simple_model::SolarOptions`
+pub mod options;
+mod row_cache;
+/// Sky models other than the Perez sky
+pub mod sky;
+mod solar_distribution;
+/// Reporting of the solar heat gains of each space
+pub mod solar_gains;
 mod solar_surface;
-pub use optical_info::{IRViewFactorSet, OpticalInfo};
+mod view_factors;
+pub use daylight::Workplane;
+pub use decomposition::{DecompositionInput, DecompositionModel};
+pub use glare::{GlareOptions, GlareResult, Viewpoint};
+pub use interior_radiation::InteriorViewFactors;
+pub use lighting_control::{LightingControl, LightingControlOptions, ReferenceIlluminance};
+pub use metrics::{
+    DaylightMetricsAccumulator, DaylightMetricsOptions, DaylightReport, Occupancy,
+    SpaceDaylightMetrics,
+};
+pub use optical_info::{
+    IRViewFactorSet, OpticalInfo, OpticalInfoSignature, OPTICAL_INFO_FORMAT_VERSION,
+};
+pub use options::{
+    Albedo, GroundTemperature, MissingSolarData, SolarModelOptions, StaleOpticalData,
+    TimestepAveraging, ViewFactorMode, WorkplaneOptions,
+};
+pub use row_cache::{RowKey, RowKeys};
+pub use sky::SkyModel;
+pub use solar_gains::{SolarGainsReport, SpaceSolarGains};
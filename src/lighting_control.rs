@@ -0,0 +1,268 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Daylight-linked control of the electric lighting of each space. The
+//! illuminance calculated at the [`crate::Workplane`] of a space is compared
+//! with a setpoint, and the luminaires are dimmed (or switched off) accordingly.
+
+use crate::Float;
+use serde::{Deserialize, Serialize};
+use simple_model::SimpleModel;
+
+/// How the luminaires of a space respond to daylight. In all cases, the
+/// daylight illuminance is compared with the setpoint of the
+/// [`LightingControlOptions`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum LightingControl {
+    /// The lights are switched off when daylight alone reaches the
+    /// setpoint, and are at full power otherwise
+    OnOff,
+
+    /// The lights are switched off in `steps` equal steps, using as
+    /// many as needed to complement daylight
+    Stepped {
+        /// The number of steps (e.g., `3` for 0, 1/3, 2/3 and 1)
+        steps: usize,
+    },
+
+    /// The light output is dimmed continuously to complement daylight,
+    /// down to `min_light`. The power decreases linearly with the light
+    /// output, from `1` down to `min_power`.
+    ContinuousDimming {
+        /// The fraction of the light output at the lowest dimming level
+        min_light: Float,
+
+        /// The fraction of the power used at the lowest dimming level
+        min_power: Float,
+
+        /// Whether the lights are switched off (instead of staying at the lowest
+        /// dimming level) when daylight alone reaches the setpoint
+        #[serde(default)]
+        switch_off: bool,
+    },
+}
+
+impl std::default::Default for LightingControl {
+    fn default() -> Self {
+        Self::ContinuousDimming {
+            min_light: 0.2,
+            min_power: 0.3,
+            switch_off: false,
+        }
+    }
+}
+
+impl LightingControl {
+    /// Calculates the fraction of the full power that luminaires use, given the
+    /// daylight illuminance and the setpoint
+    pub fn power_fraction(&self, daylight: Float, setpoint: Float) -> Float {
+        if setpoint <= 0.0 {
+            return 0.0;
+        }
+        // The fraction of the light output needed to reach the setpoint
+        let needed = (1. - daylight / setpoint).clamp(0.0, 1.0);
+        match self {
+            Self::OnOff => {
+                if needed > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Stepped { steps } => {
+                let steps = (*steps).max(1) as Float;
+                (needed * steps).ceil() / steps
+            }
+            Self::ContinuousDimming {
+                min_light,
+                min_power,
+                switch_off,
+            } => {
+                if needed <= 0.0 && *switch_off {
+                    0.0
+                } else if needed <= *min_light {
+                    *min_power
+                } else {
+                    *min_power + (1. - min_power) * (needed - min_light) / (1. - min_light)
+                }
+            }
+        }
+    }
+}
+
+/// The illuminance of a [`crate::Workplane`] that is compared with the setpoint
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReferenceIlluminance {
+    /// The average illuminance over the sensors
+    #[default]
+    Average,
+
+    /// The illuminance at the darkest sensor
+    Minimum,
+}
+
+/// The lighting control applied to every space with sensors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightingControlOptions {
+    /// The illuminance that the luminaires and daylight need to provide together, in lux
+    pub setpoint: Float,
+
+    /// How the luminaires respond to daylight
+    pub control: LightingControl,
+
+    /// Which illuminance is compared with the setpoint
+    pub reference: ReferenceIlluminance,
+}
+
+impl std::default::Default for LightingControlOptions {
+    fn default() -> Self {
+        Self {
+            setpoint: 500.,
+            control: LightingControl::default(),
+            reference: ReferenceIlluminance::default(),
+        }
+    }
+}
+
+impl LightingControlOptions {
+    /// Calculates the fraction of the full power used by the luminaires of a space,
+    /// given the illuminance at its sensors. Spaces without sensors get no
+    /// daylight, so their luminaires are at full power.
+    pub fn power_fraction(&self, illuminance: &[Float]) -> Float {
+        if illuminance.is_empty() {
+            return 1.0;
+        }
+        let daylight = match self.reference {
+            ReferenceIlluminance::Average => {
+                illuminance.iter().sum::<Float>() / illuminance.len() as Float
+            }
+            ReferenceIlluminance::Minimum => {
+                illuminance.iter().copied().fold(Float::MAX, Float::min)
+            }
+        };
+        self.control.power_fraction(daylight, self.setpoint)
+    }
+}
+
+/// A luminaire that lights a space, and the power it uses at full output
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ControlledLuminaire {
+    /// The index of the luminaire in the model
+    pub index: usize,
+
+    /// The index of the space it lights
+    pub space: usize,
+
+    /// The power it uses at full output, in W
+    pub max_power: Float,
+}
+
+impl ControlledLuminaire {
+    /// Finds the luminaires of a model that target a space and have a maximum power
+    pub fn from_model(model: &SimpleModel) -> Result<Vec<Self>, String> {
+        let mut ret = Vec::new();
+        for (index, luminaire) in model.luminaires.iter().enumerate() {
+            let (space_name, max_power) = match (luminaire.target_space(), luminaire.max_power()) {
+                (Ok(s), Ok(p)) => (s, *p),
+                _ => continue,
+            };
+            match model.spaces.iter().position(|s| &s.name == space_name) {
+                Some(space) => ret.push(Self {
+                    index,
+                    space,
+                    max_power,
+                }),
+                None => {
+                    return Err(format!(
+                        "Could not find space '{}', targeted by luminaire '{}'",
+                        space_name, luminaire.name
+                    ))
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use validate::assert_close;
+
+    #[test]
+    fn test_on_off() {
+        let control = LightingControl::OnOff;
+        assert_close!(control.power_fraction(0., 500.), 1.);
+        assert_close!(control.power_fraction(499., 500.), 1.);
+        assert_close!(control.power_fraction(500., 500.), 0.);
+        assert_close!(control.power_fraction(2000., 500.), 0.);
+    }
+
+    #[test]
+    fn test_stepped() {
+        let control = LightingControl::Stepped { steps: 3 };
+        assert_close!(control.power_fraction(0., 300.), 1.);
+        assert_close!(control.power_fraction(50., 300.), 1.);
+        assert_close!(control.power_fraction(150., 300.), 2. / 3.);
+        assert_close!(control.power_fraction(250., 300.), 1. / 3.);
+        assert_close!(control.power_fraction(300., 300.), 0.);
+    }
+
+    #[test]
+    fn test_continuous_dimming() {
+        let control = LightingControl::ContinuousDimming {
+            min_light: 0.2,
+            min_power: 0.3,
+            switch_off: false,
+        };
+        assert_close!(control.power_fraction(0., 500.), 1.);
+        // Half of the light is needed
+        assert_close!(control.power_fraction(250., 500.), 0.3 + 0.7 * 0.3 / 0.8);
+        // Below the minimum light output
+        assert_close!(control.power_fraction(450., 500.), 0.3);
+        assert_close!(control.power_fraction(1000., 500.), 0.3);
+
+        let control = LightingControl::ContinuousDimming {
+            min_light: 0.2,
+            min_power: 0.3,
+            switch_off: true,
+        };
+        assert_close!(control.power_fraction(450., 500.), 0.3);
+        assert_close!(control.power_fraction(1000., 500.), 0.);
+    }
+
+    #[test]
+    fn test_reference() {
+        let mut options = LightingControlOptions {
+            setpoint: 400.,
+            control: LightingControl::OnOff,
+            reference: ReferenceIlluminance::Average,
+        };
+        let illuminance = [200., 800.];
+        assert_close!(options.power_fraction(&illuminance), 0.);
+        options.reference = ReferenceIlluminance::Minimum;
+        assert_close!(options.power_fraction(&illuminance), 1.);
+
+        // No sensors, no daylight
+        assert_close!(options.power_fraction(&[]), 1.);
+    }
+}
@@ -0,0 +1,167 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Reads the optical properties of the `Construction` objects in a `SimpleModel`.
+
+use crate::model::MODULE_NAME;
+use crate::Float;
+use simple_model::{substance::Normal, SimpleModel, Substance};
+
+/// The solar absorbtance assumed when the model does not define one
+pub(crate) const DEFAULT_SOLAR_ABSORBTANCE: Float = 0.7;
+
+/// The thermal absorbtance (i.e., emissivity) assumed when the model does not define one
+pub(crate) const DEFAULT_THERMAL_ABSORBTANCE: Float = 0.9;
+
+/// Collects a value from each of the `Normal` substances in the layers of a
+/// construction, from front to back. Gases are ignored.
+fn collect_layers<T, F: Fn(&Normal) -> T>(
+    model: &SimpleModel,
+    construction: &str,
+    f: F,
+) -> Result<Vec<T>, String> {
+    let construction = model.get_construction(construction)?;
+    let mut ret = Vec::with_capacity(construction.materials.len());
+    for material in construction.materials.iter() {
+        let material = model.get_material(material)?;
+        if let Substance::Normal(s) = model.get_substance(&material.substance)? {
+            ret.push(f(&s))
+        }
+    }
+    Ok(ret)
+}
+
+/// The solar transmittance at normal incidence of a construction, calculated as
+/// the product of the solar transmittance of its layers.
+pub(crate) fn solar_transmittance(
+    model: &SimpleModel,
+    construction: &str,
+) -> Result<Float, String> {
+    let layers = collect_layers(model, construction, |s| {
+        s.solar_transmittance_or(MODULE_NAME, 0.0)
+    })?;
+    if layers.is_empty() {
+        return Ok(0.0);
+    }
+    Ok(layers.iter().product())
+}
+
+/// The visible transmittance at normal incidence of a construction, calculated as
+/// the product of the visible transmittance of its layers. Layers that do not
+/// define it are assumed to transmit as much as they do in the solar range.
+pub(crate) fn visible_transmittance(
+    model: &SimpleModel,
+    construction: &str,
+) -> Result<Float, String> {
+    let layers = collect_layers(model, construction, |s| {
+        let solar = s.solar_transmittance_or(MODULE_NAME, 0.0);
+        s.visible_transmittance_or(MODULE_NAME, solar)
+    })?;
+    if layers.is_empty() {
+        return Ok(0.0);
+    }
+    Ok(layers.iter().product())
+}
+
+/// Gets a property of the front (i.e., first layer) or back (i.e., last layer)
+/// of a construction, or `default` if it has no `Normal` layers.
+fn side_property<F: Fn(&Normal) -> Float, B: Fn(&Normal) -> Float>(
+    model: &SimpleModel,
+    construction: &str,
+    front: bool,
+    front_value: F,
+    back_value: B,
+    default: Float,
+) -> Result<Float, String> {
+    let v = if front {
+        collect_layers(model, construction, front_value)?
+            .first()
+            .copied()
+    } else {
+        collect_layers(model, construction, back_value)?
+            .last()
+            .copied()
+    };
+    Ok(v.unwrap_or(default))
+}
+
+/// The solar absorbtance of the front (i.e., first layer) or back (i.e., last layer)
+/// of a construction.
+pub(crate) fn solar_absorbtance(
+    model: &SimpleModel,
+    construction: &str,
+    front: bool,
+) -> Result<Float, String> {
+    side_property(
+        model,
+        construction,
+        front,
+        |s| s.front_solar_absorbtance_or(MODULE_NAME, DEFAULT_SOLAR_ABSORBTANCE),
+        |s| s.back_solar_absorbtance_or(MODULE_NAME, DEFAULT_SOLAR_ABSORBTANCE),
+        DEFAULT_SOLAR_ABSORBTANCE,
+    )
+}
+
+/// The thermal (i.e., infrared) absorbtance of the front (i.e., first layer) or
+/// back (i.e., last layer) of a construction. Following Kirchhoff's law, this is
+/// also its emissivity.
+pub(crate) fn thermal_absorbtance(
+    model: &SimpleModel,
+    construction: &str,
+    front: bool,
+) -> Result<Float, String> {
+    side_property(
+        model,
+        construction,
+        front,
+        |s| s.front_thermal_absorbtance_or(MODULE_NAME, DEFAULT_THERMAL_ABSORBTANCE),
+        |s| s.back_thermal_absorbtance_or(MODULE_NAME, DEFAULT_THERMAL_ABSORBTANCE),
+        DEFAULT_THERMAL_ABSORBTANCE,
+    )
+}
+
+/// The visible reflectance of the front (i.e., first layer) or back (i.e., last
+/// layer) of a construction. Layers that do not define it are assumed to reflect
+/// the solar radiation they neither absorb nor transmit.
+pub(crate) fn visible_reflectance(
+    model: &SimpleModel,
+    construction: &str,
+    front: bool,
+) -> Result<Float, String> {
+    let default = 1. - DEFAULT_SOLAR_ABSORBTANCE;
+    side_property(
+        model,
+        construction,
+        front,
+        |s| {
+            let solar = 1.
+                - s.front_solar_absorbtance_or(MODULE_NAME, DEFAULT_SOLAR_ABSORBTANCE)
+                - s.solar_transmittance_or(MODULE_NAME, 0.0);
+            s.front_visible_reflectance_or(MODULE_NAME, solar.max(0.0))
+        },
+        |s| {
+            let solar = 1.
+                - s.back_solar_absorbtance_or(MODULE_NAME, DEFAULT_SOLAR_ABSORBTANCE)
+                - s.solar_transmittance_or(MODULE_NAME, 0.0);
+            s.back_visible_reflectance_or(MODULE_NAME, solar.max(0.0))
+        },
+        default,
+    )
+}
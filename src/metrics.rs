@@ -0,0 +1,462 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Climate-based daylight metrics, calculated from the illuminance at the
+//! sensors of each [`Workplane`] over a year:
+//!
+//! * **Daylight Autonomy (DA)**: the fraction of the occupied time during which
+//!   the illuminance at a sensor reaches a threshold (e.g., 300 lux)
+//! * **Spatial Daylight Autonomy (sDA)**: the fraction of the sensors of a space
+//!   whose DA reaches a certain fraction (e.g., 50%)
+//! * **Useful Daylight Illuminance (UDI)**: the fraction of the occupied time
+//!   during which the illuminance at a sensor is below (fell-short), within
+//!   (useful) or above (exceeded) a useful range (e.g., 100 to 3000 lux)
+//! * **Annual Sunlight Exposure (ASE)**: the fraction of the sensors of a space
+//!   that receive more than a certain illuminance from the direct sun alone
+//!   (e.g., 1000 lux) for more than a certain number of occupied hours (e.g., 250)
+
+use crate::daylight::Workplane;
+use crate::epw::hour_index;
+use crate::model::SolarModelMemory;
+use crate::Float;
+use calendar::Date;
+use serde::{Deserialize, Serialize};
+
+/// The date of the first hour of an annual hourly simulation. Like in EPW
+/// files, each date is the end of the hour it represents, so this is the
+/// end of the first hour of the year.
+pub fn first_hour() -> Date {
+    Date {
+        month: 1,
+        day: 1,
+        hour: 1.0,
+    }
+}
+
+/// The times at which spaces are occupied. Daylight metrics consider
+/// only occupied times. Dates are the end of the timestep they represent
+/// (see [`first_hour`]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Occupancy {
+    /// Spaces are always occupied
+    Always,
+
+    /// Spaces are occupied every day between `start` and `end`, in hours
+    /// (e.g., 8 and 18, which is the default). A timestep is occupied if it
+    /// ends after `start` and no later than `end`.
+    Daily {
+        /// The hour at which occupancy starts
+        start: Float,
+        /// The hour at which occupancy ends
+        end: Float,
+    },
+
+    /// Spaces are occupied during the hours of the year (8760 of them, in
+    /// the same order as an EPW file) whose value is `true`
+    Hourly {
+        /// Whether each hour of the year is occupied
+        mask: Vec<bool>,
+    },
+}
+
+impl std::default::Default for Occupancy {
+    fn default() -> Self {
+        Self::Daily {
+            start: 8.,
+            end: 18.,
+        }
+    }
+}
+
+impl Occupancy {
+    /// Checks whether a certain date is occupied
+    pub fn is_occupied(&self, date: Date) -> Result<bool, String> {
+        match self {
+            Self::Always => Ok(true),
+            Self::Daily { start, end } => {
+                let hour = date.hour as Float;
+                Ok(hour > *start && hour <= *end)
+            }
+            Self::Hourly { mask } => {
+                let i = hour_index(date);
+                match mask.get(i) {
+                    Some(v) => Ok(*v),
+                    None => Err(format!(
+                        "The occupancy mask has {} hours... hour {} was requested",
+                        mask.len(),
+                        i
+                    )),
+                }
+            }
+        }
+    }
+}
+
+/// The thresholds used for calculating daylight metrics. The defaults
+/// follow the usual definitions (e.g., IES LM-83 for sDA and ASE).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaylightMetricsOptions {
+    /// The times at which spaces are occupied
+    pub occupancy: Occupancy,
+
+    /// The illuminance a sensor needs to reach for counting as daylit in the
+    /// Daylight Autonomy, in lux
+    pub da_threshold: Float,
+
+    /// The Daylight Autonomy a sensor needs to reach for counting towards
+    /// the Spatial Daylight Autonomy
+    pub sda_fraction: Float,
+
+    /// The lower limit of the Useful Daylight Illuminance, in lux
+    pub udi_lower: Float,
+
+    /// The upper limit of the Useful Daylight Illuminance, in lux
+    pub udi_upper: Float,
+
+    /// The illuminance from the direct sun that counts towards the Annual
+    /// Sunlight Exposure, in lux
+    pub ase_threshold: Float,
+
+    /// The number of occupied hours a sensor needs to be exposed to the
+    /// direct sun for counting towards the Annual Sunlight Exposure
+    pub ase_hours: Float,
+}
+
+impl std::default::Default for DaylightMetricsOptions {
+    fn default() -> Self {
+        Self {
+            occupancy: Occupancy::default(),
+            da_threshold: 300.,
+            sda_fraction: 0.5,
+            udi_lower: 100.,
+            udi_upper: 3000.,
+            ase_threshold: 1000.,
+            ase_hours: 250.,
+        }
+    }
+}
+
+/// The daylight metrics of a single space. The values for each
+/// sensor are in the same order as the sensors of its [`Workplane`].
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpaceDaylightMetrics {
+    /// The name of the space
+    pub space: String,
+
+    /// The Daylight Autonomy of each sensor
+    pub daylight_autonomy: Vec<Float>,
+
+    /// The fraction of the occupied time during which the illuminance
+    /// at each sensor is below the useful range
+    pub udi_fell_short: Vec<Float>,
+
+    /// The fraction of the occupied time during which the illuminance
+    /// at each sensor is within the useful range
+    pub udi_useful: Vec<Float>,
+
+    /// The fraction of the occupied time during which the illuminance
+    /// at each sensor is above the useful range
+    pub udi_exceeded: Vec<Float>,
+
+    /// The number of occupied hours during which each sensor receives more than
+    /// the `ase_threshold` from the direct sun. It is empty if the Annual
+    /// Sunlight Exposure was not calculated.
+    pub sunlit_hours: Vec<Float>,
+
+    /// The Spatial Daylight Autonomy of the space
+    pub spatial_daylight_autonomy: Float,
+
+    /// The Annual Sunlight Exposure of the space, if calculated (see
+    /// [`crate::SolarModel::daylight_metrics`])
+    pub annual_sunlight_exposure: Option<Float>,
+}
+
+/// The daylight metrics of every space in the model
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DaylightReport {
+    /// The options used for calculating the metrics
+    pub options: DaylightMetricsOptions,
+
+    /// The number of occupied hours considered
+    pub occupied_hours: Float,
+
+    /// The metrics of each space, in the same order as the
+    /// spaces in the `SimpleModel`
+    pub spaces: Vec<SpaceDaylightMetrics>,
+}
+
+impl DaylightReport {
+    /// Gets the metrics of a space, by name
+    pub fn get(&self, space: &str) -> Option<&SpaceDaylightMetrics> {
+        self.spaces.iter().find(|s| s.space == space)
+    }
+}
+
+/// The hours accumulated by each sensor of a [`Workplane`]
+#[derive(Debug, Clone)]
+struct SensorHours {
+    /// The name of the space
+    space: String,
+
+    /// Hours reaching the `da_threshold`
+    daylit: Vec<Float>,
+
+    /// Hours below the useful range
+    fell_short: Vec<Float>,
+
+    /// Hours within the useful range
+    useful: Vec<Float>,
+
+    /// Hours above the useful range
+    exceeded: Vec<Float>,
+
+    /// Hours with direct sun above the `ase_threshold`
+    sunlit: Vec<Float>,
+}
+
+/// Accumulates the illuminance calculated during a simulation, and
+/// calculates the daylight metrics from it.
+///
+/// [`crate::SolarModel::daylight_metrics`] runs a whole year by itself, but
+/// this can also be fed with the memory of an ongoing simulation.
+#[derive(Debug, Clone)]
+pub struct DaylightMetricsAccumulator {
+    /// The options used for calculating the metrics
+    options: DaylightMetricsOptions,
+
+    /// The number of occupied hours so far
+    occupied_hours: Float,
+
+    /// The hours accumulated by the sensors of each space
+    spaces: Vec<SensorHours>,
+}
+
+impl DaylightMetricsAccumulator {
+    /// Creates a new accumulator for some workplanes
+    pub fn new(workplanes: &[Workplane], options: DaylightMetricsOptions) -> Self {
+        let spaces = workplanes
+            .iter()
+            .map(|w| {
+                let n = w.sensors.len();
+                SensorHours {
+                    space: w.space.clone(),
+                    daylit: vec![0.0; n],
+                    fell_short: vec![0.0; n],
+                    useful: vec![0.0; n],
+                    exceeded: vec![0.0; n],
+                    sunlit: vec![0.0; n],
+                }
+            })
+            .collect();
+        Self {
+            options,
+            occupied_hours: 0.0,
+            spaces,
+        }
+    }
+
+    /// Adds a timestep of `hours` hours (e.g., `1.` in an hourly simulation),
+    /// reading the illuminance from the `memory`.
+    pub fn add(
+        &mut self,
+        date: Date,
+        hours: Float,
+        memory: &SolarModelMemory,
+    ) -> Result<(), String> {
+        if !self.options.occupancy.is_occupied(date)? {
+            return Ok(());
+        }
+        if memory.workplane_illuminance.len() != self.spaces.len()
+            || memory.workplane_sun_illuminance.len() != self.spaces.len()
+        {
+            return Err(format!(
+                "Expecting the illuminance of {} workplanes... found {}",
+                self.spaces.len(),
+                memory.workplane_illuminance.len()
+            ));
+        }
+        self.occupied_hours += hours;
+
+        let options = &self.options;
+        for ((space, illuminance), sun_illuminance) in self
+            .spaces
+            .iter_mut()
+            .zip(memory.workplane_illuminance.iter())
+            .zip(memory.workplane_sun_illuminance.iter())
+        {
+            for (i, e) in illuminance.iter().enumerate() {
+                if *e >= options.da_threshold {
+                    space.daylit[i] += hours;
+                }
+                if *e < options.udi_lower {
+                    space.fell_short[i] += hours;
+                } else if *e > options.udi_upper {
+                    space.exceeded[i] += hours;
+                } else {
+                    space.useful[i] += hours;
+                }
+            }
+            for (i, e) in sun_illuminance.iter().enumerate() {
+                if *e >= options.ase_threshold {
+                    space.sunlit[i] += hours;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Calculates the daylight metrics from the hours accumulated so far
+    pub fn report(&self) -> DaylightReport {
+        let options = &self.options;
+        let total = self.occupied_hours;
+        let fraction = |v: &Vec<Float>| -> Vec<Float> {
+            v.iter()
+                .map(|h| if total > 0.0 { h / total } else { 0.0 })
+                .collect()
+        };
+        let share = |v: &Vec<Float>, f: &dyn Fn(Float) -> bool| -> Float {
+            if v.is_empty() {
+                0.0
+            } else {
+                v.iter().filter(|x| f(**x)).count() as Float / v.len() as Float
+            }
+        };
+
+        let spaces = self
+            .spaces
+            .iter()
+            .map(|s| {
+                let daylight_autonomy = fraction(&s.daylit);
+                let spatial_daylight_autonomy =
+                    share(&daylight_autonomy, &|da| da >= options.sda_fraction);
+                let annual_sunlight_exposure = share(&s.sunlit, &|h| h > options.ase_hours);
+                SpaceDaylightMetrics {
+                    space: s.space.clone(),
+                    udi_fell_short: fraction(&s.fell_short),
+                    udi_useful: fraction(&s.useful),
+                    udi_exceeded: fraction(&s.exceeded),
+                    sunlit_hours: s.sunlit.clone(),
+                    daylight_autonomy,
+                    spatial_daylight_autonomy,
+                    annual_sunlight_exposure: Some(annual_sunlight_exposure),
+                }
+            })
+            .collect();
+
+        DaylightReport {
+            options: self.options.clone(),
+            occupied_hours: total,
+            spaces,
+        }
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::SolarGainsReport;
+    use geometry3d::Point3D;
+    use validate::assert_close;
+
+    fn date(hour: f64) -> Date {
+        Date {
+            month: 1,
+            day: 1,
+            hour,
+        }
+    }
+
+    #[test]
+    fn test_occupancy() {
+        assert!(Occupancy::Always.is_occupied(date(3.5)).unwrap());
+
+        // The hour from 7 to 8 is not occupied, but the one from 17 to 18 is
+        let office = Occupancy::default();
+        assert!(!office.is_occupied(date(8.)).unwrap());
+        assert!(office.is_occupied(date(9.)).unwrap());
+        assert!(office.is_occupied(date(18.)).unwrap());
+        assert!(!office.is_occupied(date(19.)).unwrap());
+        assert!(office.is_occupied(date(8.5)).unwrap());
+
+        let mut mask = vec![false; 8760];
+        mask[3] = true;
+        let hourly = Occupancy::Hourly { mask };
+        assert!(!hourly.is_occupied(date(2.5)).unwrap());
+        assert!(hourly.is_occupied(date(3.5)).unwrap());
+
+        let short = Occupancy::Hourly { mask: vec![true] };
+        assert!(short.is_occupied(date(3.5)).is_err());
+    }
+
+    #[test]
+    fn test_accumulator() {
+        let workplanes = vec![Workplane {
+            space: "the room".into(),
+            sensors: vec![Point3D::new(0., 0., 0.8), Point3D::new(1., 0., 0.8)],
+        }];
+        let options = DaylightMetricsOptions {
+            occupancy: Occupancy::Always,
+            ase_hours: 1.,
+            ..DaylightMetricsOptions::default()
+        };
+        let mut accumulator = DaylightMetricsAccumulator::new(&workplanes, options);
+        let mut memory = SolarModelMemory::new(0, 0, SolarGainsReport::default());
+
+        // A bright hour, with sun on the first sensor...
+        memory.workplane_illuminance = vec![vec![5000., 500.]];
+        memory.workplane_sun_illuminance = vec![vec![2000., 0.]];
+        accumulator.add(date(10.5), 1., &memory).unwrap();
+        accumulator.add(date(11.5), 1., &memory).unwrap();
+
+        // ... and a dark one
+        memory.workplane_illuminance = vec![vec![50., 50.]];
+        memory.workplane_sun_illuminance = vec![vec![0., 0.]];
+        accumulator.add(date(20.5), 1., &memory).unwrap();
+
+        let report = accumulator.report();
+        assert_close!(report.occupied_hours, 3.);
+        let room = report.get("the room").unwrap();
+        assert_close!(room.daylight_autonomy[0], 2. / 3.);
+        assert_close!(room.daylight_autonomy[1], 2. / 3.);
+        assert_close!(room.spatial_daylight_autonomy, 1.);
+        assert_close!(room.udi_exceeded[0], 2. / 3.);
+        assert_close!(room.udi_useful[1], 2. / 3.);
+        assert_close!(room.udi_fell_short[1], 1. / 3.);
+        assert_close!(room.sunlit_hours[0], 2.);
+        assert_close!(room.annual_sunlight_exposure.unwrap(), 0.5);
+
+        // It can be serialized
+        let json = serde_json::to_string(&report).unwrap();
+        let back: DaylightReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, report);
+
+        // Unoccupied hours are ignored
+        let options = DaylightMetricsOptions::default();
+        let mut accumulator = DaylightMetricsAccumulator::new(&workplanes, options);
+        accumulator.add(date(20.5), 1., &memory).unwrap();
+        assert_close!(accumulator.report().occupied_hours, 0.);
+
+        // The memory needs to match the workplanes
+        memory.workplane_illuminance = Vec::new();
+        assert!(accumulator.add(date(10.5), 1., &memory).is_err());
+    }
+}
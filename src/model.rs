@@ -17,25 +17,262 @@ LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
-use crate::{solar_surface::SolarSurface, Float};
+use crate::daylight::{Workplane, WHITE_EFFICACY};
+use crate::decomposition::{self, DecompositionInput, DecompositionModel, MIN_COS_ZENITH};
+use crate::glare::GlareResult;
+use crate::interior_radiation::{SpaceSide, SpaceSideKind};
+use crate::lighting_control::ControlledLuminaire;
+use crate::metrics::{
+    first_hour, DaylightMetricsAccumulator, DaylightMetricsOptions, DaylightReport,
+};
+use crate::sky::{SkyModel, SkyPatches};
+use crate::solar_distribution::SolarDistribution;
+use crate::solar_gains::{SolarGainsAggregator, SolarGainsReport};
+use crate::{
+    materials, solar_surface::SolarSurface, Float, MissingSolarData, SolarModelOptions,
+    StaleOpticalData, TimestepAveraging,
+};
 use calendar::Date;
 use communication_protocols::{ErrorHandling, MetaOptions, SimulationModel};
+use geometry3d::Vector3D;
 use matrix::Matrix;
 use simple_model::{Boundary, SimpleModel, SimulationState, SimulationStateHeader, SolarOptions};
-use solar::{PerezSky, SkyUnits, Solar};
+use solar::{PerezSky, SkyUnits, Solar, Time};
 use std::borrow::Borrow;
-use std::fs::File;
-use std::io::Write;
 use std::path::Path;
 use weather::{CurrentWeather, Weather};
 
-use crate::optical_info::OpticalInfo;
+use crate::optical_info::{IRViewFactorSet, OpticalInfo, OpticalInfoSignature};
 
 /// The name of the module
 pub(crate) const MODULE_NAME: &'static str = "Solar Model";
 
-/// The memory used by this module during simulation
-pub type SolarModelMemory = ();
+/// The Stefan-Boltzmann constant, in W/m2.K4
+pub(crate) const SIGMA: Float = 5.670374419e-8;
+
+/// The radiation emitted by a black body at a certain temperature (in C), in W/m2
+pub(crate) fn black_body(temp: Float) -> Float {
+    SIGMA * (temp + 273.15).powi(4)
+}
+
+/// The conditions of the environment that exchanges infrared radiation
+/// with the surfaces that are not facing a space
+struct IREnvironment {
+    /// The outdoor dry bulb temperature, in C
+    dry_bulb: Float,
+
+    /// The horizontal infrared radiation from the sky, in W/m2
+    horizontal_ir: Float,
+
+    /// The temperature of the ground in contact with surfaces, in C
+    ground_temperature: Float,
+
+    /// The temperature of the front and back sides of each surface, in C
+    surface_temperatures: Vec<(Float, Float)>,
+
+    /// The temperature of the front and back sides of each fenestration, in C
+    fenestration_temperatures: Vec<(Float, Float)>,
+}
+
+impl IREnvironment {
+    /// Gets the temperature of a side of a surface or fenestration
+    fn side_temperature(&self, side: &SpaceSide) -> Float {
+        let (front, back) = match side.kind {
+            SpaceSideKind::Surface => self.surface_temperatures[side.index],
+            SpaceSideKind::Fenestration => self.fenestration_temperatures[side.index],
+        };
+        if side.front {
+            front
+        } else {
+            back
+        }
+    }
+}
+
+/// The memory used by this module during simulation. It also holds the
+/// results that do not (yet) have a place in the `SimulationState`.
+///
+/// The per-space solar gains and the workplane illuminance, in particular,
+/// are only reported here. The `SimulationStateElement` enum is defined in
+/// `simple_model`, so this crate cannot add variants for them. Modules that
+/// need these results (e.g., a thermal model reading the solar gains of
+/// each zone) should read them from the memory passed to
+/// `march()`, which the caller owns, after each timestep.
+#[derive(Debug, Default, Clone)]
+pub struct SolarModelMemory {
+    /// The beam solar radiation transmitted through each fenestration, in W
+    pub fenestration_transmitted_beam: Vec<Float>,
+
+    /// The diffuse solar radiation transmitted through each fenestration, in W
+    pub fenestration_transmitted_diffuse: Vec<Float>,
+
+    /// The solar radiation transmitted through fenestrations that is absorbed by
+    /// the front side of each surface, in W/m2
+    pub surface_front_transmitted_solar: Vec<Float>,
+
+    /// The solar radiation transmitted through fenestrations that is absorbed by
+    /// the back side of each surface, in W/m2
+    pub surface_back_transmitted_solar: Vec<Float>,
+
+    /// The solar gains of each space. These are not in the
+    /// `SimulationState` (see above), so read them from here.
+    pub solar_gains: SolarGainsReport,
+
+    /// The net infrared radiation absorbed by the front side of each
+    /// surface (i.e., absorbed minus emitted), in W/m2
+    pub surface_front_net_ir: Vec<Float>,
+
+    /// The net infrared radiation absorbed by the back side of each
+    /// surface (i.e., absorbed minus emitted), in W/m2
+    pub surface_back_net_ir: Vec<Float>,
+
+    /// The net infrared radiation absorbed by the front side of each
+    /// fenestration (i.e., absorbed minus emitted), in W/m2
+    pub fenestration_front_net_ir: Vec<Float>,
+
+    /// The net infrared radiation absorbed by the back side of each
+    /// fenestration (i.e., absorbed minus emitted), in W/m2
+    pub fenestration_back_net_ir: Vec<Float>,
+
+    /// The illuminance at the sensors of each [`Workplane`], in lux. This is
+    /// not in the `SimulationState` (see above), so read it from here.
+    pub workplane_illuminance: Vec<Vec<Float>>,
+
+    /// The illuminance from the direct sun alone at the sensors of
+    /// each [`Workplane`], in lux
+    pub workplane_sun_illuminance: Vec<Vec<Float>>,
+
+    /// The fraction of the full power used by the luminaires of each space,
+    /// when `lighting_control` is set in the [`SolarModelOptions`]
+    pub lighting_power_fraction: Vec<Float>,
+
+    /// The glare evaluated at each viewpoint, when `glare` is set
+    /// in the [`SolarModelOptions`]
+    pub glare: Vec<GlareResult>,
+
+    /// Whether a timestep has been simulated with this memory. The
+    /// trailing average needs a previous timestep, so the first one is
+    /// instantaneous.
+    marched: bool,
+}
+
+impl SolarModelMemory {
+    /// Creates a new memory for a model with `n_surfaces` surfaces and
+    /// `n_fenestrations` fenestrations. The `solar_gains` report is
+    /// expected to contain one (empty) element per space.
+    pub fn new(n_surfaces: usize, n_fenestrations: usize, solar_gains: SolarGainsReport) -> Self {
+        Self {
+            fenestration_transmitted_beam: vec![0.0; n_fenestrations],
+            fenestration_transmitted_diffuse: vec![0.0; n_fenestrations],
+            surface_front_transmitted_solar: vec![0.0; n_surfaces],
+            surface_back_transmitted_solar: vec![0.0; n_surfaces],
+            solar_gains,
+            surface_front_net_ir: vec![0.0; n_surfaces],
+            surface_back_net_ir: vec![0.0; n_surfaces],
+            fenestration_front_net_ir: vec![0.0; n_fenestrations],
+            fenestration_back_net_ir: vec![0.0; n_fenestrations],
+            workplane_illuminance: Vec::new(),
+            workplane_sun_illuminance: Vec::new(),
+            lighting_power_fraction: Vec::new(),
+            glare: Vec::new(),
+            marched: false,
+        }
+    }
+
+    /// Sets the transmitted solar radiation to zero
+    fn reset_transmitted_solar(&mut self) {
+        self.fenestration_transmitted_beam.fill(0.0);
+        self.fenestration_transmitted_diffuse.fill(0.0);
+        self.surface_front_transmitted_solar.fill(0.0);
+        self.surface_back_transmitted_solar.fill(0.0);
+    }
+}
+
+/// The sky vectors used during a timestep
+pub(crate) struct SkyVectors {
+    /// The sky vector, including the sun unless `sun` is given
+    pub sky: Matrix,
+
+    /// A vector containing only the sun, discretized for the direct-sun
+    /// Daylight Coefficients matrices.
+    pub sun: Option<Matrix>,
+
+    /// A vector containing only the sun, discretized like `sky` and without
+    /// the ground. It is used for telling the beam radiation transmitted
+    /// through fenestrations from the diffuse one, so it is only built
+    /// when `sun` is not given and some fenestration transmits solar radiation.
+    pub beam: Option<Matrix>,
+}
+
+/// The sky vectors at one of the instants sampled within a timestep
+struct SkySample {
+    /// The share of the timestep represented by this instant
+    weight: Float,
+
+    /// The instant
+    date: Date,
+
+    /// The sky vectors at that instant
+    vectors: SkyVectors,
+}
+
+/// Adds `weight` times `other` to `acc`, element by element
+fn add_weighted(acc: &mut Matrix, other: &Matrix, weight: Float) -> Result<(), String> {
+    let (nrows, ncols) = acc.size();
+    for r in 0..nrows {
+        for c in 0..ncols {
+            acc.set(r, c, acc.get(r, c)? + weight * other.get(r, c)?)?;
+        }
+    }
+    Ok(())
+}
+
+impl SkyVectors {
+    /// Creates sky vectors full of zeroes, with the same shape as `other`
+    fn zeros_like(other: &Self) -> Self {
+        let zeros = |m: &Matrix| {
+            let (nrows, ncols) = m.size();
+            Matrix::new(0.0, nrows, ncols)
+        };
+        Self {
+            sky: zeros(&other.sky),
+            sun: other.sun.as_ref().map(zeros),
+            beam: other.beam.as_ref().map(zeros),
+        }
+    }
+
+    /// Adds `weight` times `other` to these vectors
+    fn add_weighted(&mut self, other: &Self, weight: Float) -> Result<(), String> {
+        add_weighted(&mut self.sky, &other.sky, weight)?;
+        if let (Some(acc), Some(v)) = (&mut self.sun, &other.sun) {
+            add_weighted(acc, v, weight)?;
+        }
+        if let (Some(acc), Some(v)) = (&mut self.beam, &other.beam) {
+            add_weighted(acc, v, weight)?;
+        }
+        Ok(())
+    }
+}
+
+/// The visible sky vectors used during a timestep, for daylighting and glare
+pub(crate) struct VisibleSkyVectors {
+    /// The sky and the sun together
+    pub all: Matrix,
+
+    /// The sky without the sun
+    pub sky: Matrix,
+
+    /// The sun without the sky
+    pub sun: Matrix,
+
+    /// The sun without the sky nor the ground, with the MF of the
+    /// `workplanes_sun_dc` (i.e., the `direct_sun_sky_discretization`, if set)
+    pub direct_sun: Matrix,
+
+    /// The direction of the sun, if it is up. When several instants are
+    /// sampled within a timestep, this is their weighted average.
+    pub sun_direction: Option<Vector3D>,
+}
 
 /// The main model
 pub struct SolarModel {
@@ -55,338 +292,208 @@ pub struct SolarModel {
     /// The calculator for solar position and other solar variables
     solar: Solar,
 
+    /// The difference between the local longitude and the standard meridian,
+    /// in hours (east-positive). Used for calculating the apparent solar time.
+    solar_time_offset: Float,
+
+    /// The length of the timestep, in hours
+    timestep_hours: Float,
+
     /// The MF discretization scheme for the sky.
     solar_sky_discretization: usize,
-}
 
-impl SolarModel {
-    /// This function makes the IR heat transfer Zero... we will try to fix this soon enough,
-    /// just not now    
-    fn update_ir_radiation(
-        &self,
-        weather_data: &CurrentWeather,
-        model: &SimpleModel,
-        state: &mut SimulationState,
-    ) -> Result<(), String> {
-        pub const SIGMA: crate::Float = 5.670374419e-8;
+    /// The MF discretization scheme for the direct sun, if the direct
+    /// sun and the diffuse sky are calculated separately
+    sun_sky_discretization: Option<usize>,
 
-        fn ir(temp: Float, emissivity: Float) -> Float {
-            emissivity * SIGMA * (temp + 273.15).powi(4)
-        }
+    /// The sampled patches of the skies, for sky models other than Perez
+    sky_patches: Vec<SkyPatches>,
 
-        let db = match weather_data.dry_bulb_temperature {
-            Some(v) => v,
-            None => return Err("Cannot calculate IR radiation without Dry Bulb temperature".into()),
-        };
-        let horizontal_ir = match weather_data.horizontal_infrared_radiation_intensity {
-            Some(v) => v,
-            None => weather_data.derive_horizontal_ir()?,
-        };
+    /// The solid angle of each patch of the sky (the first one being the
+    /// ground), when glare is evaluated
+    sky_solid_angles: Vec<Float>,
 
-        let iter = model.surfaces.iter().enumerate();
+    /// The options that are not part of the `SolarOptions`
+    options: SolarModelOptions,
 
-        for (index, surface) in iter {
-            // Deal with front
-            match &surface.front_boundary {
-                Boundary::Space { .. } => {
-                    // Zero net IR exchange
-                    let temp = surface.first_node_temperature(state).unwrap_or(22.);
-                    surface.set_front_ir_irradiance(state, ir(temp, 1.0))?;
-                }
-                Boundary::AmbientTemperature { temperature } => {
-                    // It depends on the ambient tempearture
-                    surface.set_front_ir_irradiance(state, ir(*temperature, 1.0))?;
-                }
-                Boundary::Ground => {
-                    // ignore ground
-                }
-                Boundary::Outdoor => {
-                    // outdoor
-                    let view_factors = &self.optical_info.front_surfaces_view_factors[index];
-                    let ground_other = (view_factors.ground + view_factors.air) * ir(db, 1.0);
-                    let sky = view_factors.sky * horizontal_ir;
-                    surface.set_front_ir_irradiance(state, ground_other + sky)?;
-                }
-            }
+    /// Hourly albedo data, for albedo sources that need it
+    albedo_data: Vec<Float>,
 
-            // Deal with Back
-            match &surface.back_boundary {
-                    Boundary::Space { .. } => {
-                        // Zero net IR exchange
-                        let temp = surface.last_node_temperature(state).unwrap_or(22.);
-                        surface.set_back_ir_irradiance(state, ir(temp, 1.0))?;
-                    }
-                    Boundary::AmbientTemperature { temperature } => {
-                        surface.set_back_ir_irradiance(state, ir(*temperature, 1.0))?;
-                    }
-                    Boundary::Ground => {
-                        // ignore ground
-                    }
-                    Boundary::Outdoor => {
-                        // outdoor
-                        let view_factors = &self.optical_info.back_surfaces_view_factors[index];
-                        let ground_other = (view_factors.ground + view_factors.air) * ir(db, 1.0);
-                        let sky = view_factors.sky * horizontal_ir;
-                        surface.set_back_ir_irradiance(state, ground_other + sky)?;
-                    }
-                }
-        }
+    /// Monthly ground temperature data, for ground temperature sources that need it
+    ground_temperature_data: Vec<Float>,
 
-        let iter = model.fenestrations.iter().enumerate();
-        for (index, surface) in iter {
-            // Deal with front
-            match &surface.front_boundary {
-                Boundary::Space { .. } => {
-                    // Zero net IR exchange
-                    let temp = surface.first_node_temperature(state).unwrap_or(22.);
-                    surface.set_front_ir_irradiance(state, ir(temp, 1.0))?;
-                }
-                Boundary::AmbientTemperature { temperature } => {
-                    surface.set_front_ir_irradiance(state, ir(*temperature, 1.0))?;
-                }
-                Boundary::Ground => {}
-                Boundary::Outdoor => {
-                    let view_factors =
-                        &self.optical_info.front_fenestrations_view_factors[index];
-                    let ground_other = (view_factors.ground + view_factors.air) * ir(db, 1.0);
-                    let sky = view_factors.sky * horizontal_ir;
-                    surface.set_front_ir_irradiance(state, ground_other + sky)?;
-                }
-            }
+    /// Information for distributing the solar radiation that enters
+    /// the spaces through fenestrations
+    distribution: SolarDistribution,
 
-            // Deal with Back
-            match &surface.back_boundary {
-                Boundary::Space { .. } => {
-                    // Zero net IR exchange
-                    let temp = surface.last_node_temperature(state).unwrap_or(22.);
-                    surface.set_back_ir_irradiance(state, ir(temp, 1.0))?;
-                }
-                Boundary::AmbientTemperature { temperature } => {
-                    surface.set_back_ir_irradiance(state, ir(*temperature, 1.0))?;
-                }
-                Boundary::Ground => {}
-                Boundary::Outdoor => {
-                    // outdoor
-                    let view_factors =
-                        &self.optical_info.back_fenestrations_view_factors[index];
-                    let ground_other = (view_factors.ground + view_factors.air) * ir(db, 1.0);
-                    let sky = view_factors.sky * horizontal_ir;
-                    surface.set_back_ir_irradiance(state, ground_other + sky)?;
-                }
-            }
-        }
+    /// Information for summing the solar gains of each space
+    gains_aggregator: SolarGainsAggregator,
 
-        Ok(())
-    }
+    /// An empty solar gains report, with the spaces of the model
+    empty_solar_gains: SolarGainsReport,
 
-    fn update_solar_radiation(
-        &self,
-        date: Date,
-        weather_data: CurrentWeather,
-        model: &SimpleModel,
-        state: &mut SimulationState,
-    ) -> Result<(), String> {
-        let direct_normal_irrad = weather_data
-            .direct_normal_radiation
-            .expect("Missing data for direct normal irradiance");
-        let diffuse_horizontal_irrad = weather_data
-            .diffuse_horizontal_radiation
-            .expect("Missing data for diffuse horizontal");
+    /// The emissivity of the front and back sides of each surface
+    surface_emissivities: Vec<(Float, Float)>,
 
-        let is_day = direct_normal_irrad + diffuse_horizontal_irrad >= 1e-4;
-        let vec = if is_day {
-            // Build sky vector
-            let albedo = 0.2;
-            let add_sky = true;
-            let add_sun = true;
-            let units = SkyUnits::Solar;
-            PerezSky::gen_sky_vec(
-                self.solar_sky_discretization,
-                &self.solar,
-                date,
-                weather_data,
-                units,
-                albedo,
-                add_sky,
-                add_sun,
-            )?
-        } else {
-            Matrix::empty()
-        };
+    /// The emissivity of the front and back sides of each fenestration
+    fenestration_emissivities: Vec<(Float, Float)>,
 
-        // Process Solar Irradiance in Surfaces
-        if !self.optical_info.front_surfaces_dc.is_empty() {
-            if is_day {
-                let solar_irradiance = &self.optical_info.front_surfaces_dc * &vec;
-                let mut i = 0;
-                for s in model.surfaces.iter() {
-                    if !SolarSurface::boundary_receives_sun(&s.front_boundary) {
-                        continue;
-                    }
-                    // Average of the period
-                    let mut v = solar_irradiance.get(i, 0)?;
-                    if v < 0.0 {
-                        v = 0.0
-                    }
-                    let old_v = s.front_incident_solar_irradiance(state).ok_or(
-                        "Could not get previous front incident solar irradiance (surface)",
-                    )?;
-                    s.set_front_incident_solar_irradiance(state, (v + old_v) / 2.)?;
-                    i += 1;
-                }
-            } else {
-                for s in model.surfaces.iter() {
-                    s.set_front_incident_solar_irradiance(state, 0.0)?;
-                }
-            }
-        }
-        if !self.optical_info.back_surfaces_dc.is_empty() {
-            if is_day {
-                let solar_irradiance = &self.optical_info.back_surfaces_dc * &vec;
-                let mut i = 0;
-                for s in model.surfaces.iter() {
-                    if !SolarSurface::boundary_receives_sun(&s.back_boundary) {
-                        continue;
-                    }
-                    // Average of the period
-                    let mut v = solar_irradiance.get(i, 0)?;
-                    if v < 0.0 {
-                        v = 0.0
-                    }
-                    let old_v = s
-                        .back_incident_solar_irradiance(state)
-                        .ok_or("Could not get previous back incident solar irradiance (surface)")?;
-                    s.set_back_incident_solar_irradiance(state, (v + old_v) / 2.)?;
-                    i += 1;
-                }
-            } else {
-                for s in model.surfaces.iter() {
-                    s.set_front_incident_solar_irradiance(state, 0.0)?;
-                }
-            }
-        }
+    /// The luminaires dimmed by the lighting control
+    luminaires: Vec<ControlledLuminaire>,
 
-        // Process Solar Irradiance in Fenestration
-        if !self.optical_info.front_fenestrations_dc.is_empty() {
-            if is_day {
-                let solar_irradiance = &self.optical_info.front_fenestrations_dc * &vec;
-                let mut i = 0;
-                for s in model.fenestrations.iter() {
-                    if !SolarSurface::boundary_receives_sun(&s.front_boundary) {
-                        continue;
-                    }
-                    // Average of the period
-                    let v = solar_irradiance.get(i, 0)?;
-                    let old_v = s.front_incident_solar_irradiance(state).ok_or(
-                        "Could not get previous front incident solar irradiance (fenestration)",
-                    )?;
-                    s.set_front_incident_solar_irradiance(state, (v + old_v) / 2.)?;
-                    i += 1;
-                }
-            } else {
-                for s in model.fenestrations.iter() {
-                    s.set_front_incident_solar_irradiance(state, 0.0)?;
-                }
-            }
-        }
-        if !self.optical_info.back_fenestrations_dc.is_empty() {
-            if is_day {
-                let solar_irradiance = &self.optical_info.back_fenestrations_dc * &vec;
-                let mut i = 0;
-                for s in model.fenestrations.iter() {
-                    if !SolarSurface::boundary_receives_sun(&s.back_boundary) {
-                        continue;
-                    }
-                    // Average of the period
-                    let v = solar_irradiance.get(i, 0)?;
-                    let old_v = s.back_incident_solar_irradiance(state).ok_or(
-                        "Could not get previous front incident solar irradiance (fenestration)",
-                    )?;
-                    s.set_back_incident_solar_irradiance(state, (v + old_v) / 2.)?;
-                    i += 1;
-                }
-            } else {
-                for s in model.fenestrations.iter() {
-                    s.set_front_incident_solar_irradiance(state, 0.0)?;
-                }
-            }
-        }
-        Ok(())
-    }
+    /// The number of surfaces in the model
+    n_surfaces: usize,
+
+    /// The number of fenestrations in the model
+    n_fenestrations: usize,
 }
 
-impl ErrorHandling for SolarModel {
-    fn module_name() -> &'static str {
-        MODULE_NAME
-    }
+/// Describes a date, for error messages
+fn date_str(date: Date) -> String {
+    format!(
+        "on month {}, day {}, hour {:.2}",
+        date.month, date.day, date.hour
+    )
 }
 
-impl SimulationModel for SolarModel {
-    type OutputType = Self;
-    type OptionType = SolarOptions;
-    type AllocType = SolarModelMemory;
+/// Gets the direct normal and diffuse horizontal irradiance from
+/// the weather data, or an error reporting which one is missing
+fn solar_irradiance_data(
+    date: Date,
+    weather_data: &CurrentWeather,
+) -> Result<(Float, Float), String> {
+    let direct_normal = match weather_data.direct_normal_radiation {
+        Some(v) => v,
+        None => {
+            return Err(format!(
+                "Missing direct normal irradiance {}",
+                date_str(date)
+            ))
+        }
+    };
+    let diffuse_horizontal = match weather_data.diffuse_horizontal_radiation {
+        Some(v) => v,
+        None => {
+            return Err(format!(
+                "Missing diffuse horizontal irradiance {}",
+                date_str(date)
+            ))
+        }
+    };
+    Ok((direct_normal, diffuse_horizontal))
+}
 
-    fn allocate_memory(&self) -> Result<Self::AllocType, String> {
-        Ok(())
-    }
+/// Interpolates linearly a weather variable between the closest earlier and
+/// later hours (up to `max_hours` away) that have data
+fn interpolate_weather<W: Weather, F: Fn(&CurrentWeather) -> Option<Float>>(
+    date: Date,
+    weather: &W,
+    max_hours: usize,
+    get: F,
+) -> Option<Float> {
+    let closest = |direction: Float| {
+        (1..=max_hours).find_map(|hours| {
+            let mut d = date;
+            d.add_hours(direction * hours as Float);
+            get(&weather.get_weather_data(d)).map(|v| (hours as Float, v))
+        })
+    };
+    let (before_hours, before) = closest(-1.)?;
+    let (after_hours, after) = closest(1.)?;
+    Some(before + (after - before) * before_hours / (before_hours + after_hours))
+}
 
-    fn new<M: Borrow<SimpleModel>>(
+impl SolarModel {
+    /// Creates a new `SolarModel`, like `SimulationModel::new()` does, but
+    /// using some [`SolarModelOptions`] instead of reading them from the
+    /// options file that goes with the `SolarOptions` (see
+    /// [`SolarModelOptions::from_solar_options`]).
+    ///
+    /// If the `SolarOptions` have an `optical_data_path`, the optical information
+    /// stored there is only reused if it was calculated for the same model and
+    /// options (see [`OpticalInfoSignature`]). Otherwise, it is recalculated (reusing
+    /// whatever did not change) or an error is returned, depending on the
+    /// `stale_optical_data` option.
+    ///
+    /// `n` is the number of timesteps per hour. Each call to `march()` simulates
+    /// the timestep that ends at the given date.
+    pub fn new_with_options<M: Borrow<SimpleModel>>(
         meta_options: &MetaOptions,
         options: SolarOptions,
+        model_options: SolarModelOptions,
         model: M,
         state: &mut SimulationStateHeader,
-        _n: usize,
-    ) -> Result<Self::OutputType, String> {
+        n: usize,
+    ) -> Result<Self, String> {
         let model = model.borrow();
+        if n == 0 {
+            return Err("The number of timesteps per hour must be greater than zero".into());
+        }
+        if model_options.timestep_averaging == (TimestepAveraging::Integrated { samples: 0 }) {
+            return Err("The number of samples per timestep must be greater than zero".into());
+        }
+        model_options.sky_model.validate()?;
+
         // Make OpticalInfo, or read, as needed
         let optical_info = if let Ok(path_str) = options.optical_data_path() {
             let path = Path::new(path_str);
+            let signature = OpticalInfoSignature::new(&options, &model_options, model);
+            // read from file, if it matches the model and options
+            let mut stored = None;
+            let mut previous = None;
             if path.exists() {
-                // read from file
-                if !path.is_file() {
-                    return Err(format!(
-                        "Path '{}' is not a file",
-                        path.to_str()
-                            .expect("When !path.is_file... could not convert path into string")
-                    ));
-                }
-
-                let data = match std::fs::read_to_string(path) {
-                    Ok(v) => v,
-                    Err(_) => {
-                        return Err(format!("Unable to read optical_info file '{}'", path_str))
-                    }
+                let problem = match OpticalInfo::from_file(path) {
+                    Ok(info) => match info.signature.check(&signature) {
+                        Ok(()) => {
+                            stored = Some(info);
+                            None
+                        }
+                        Err(e) => {
+                            previous = Some(info);
+                            Some(e)
+                        }
+                    },
+                    Err(e) => Some(e),
                 };
-                let info: OpticalInfo = match serde_json::from_str(&data) {
-                    Ok(v) => v,
-                    Err(_) => {
+                if let Some(e) = problem {
+                    if model_options.stale_optical_data == StaleOpticalData::Error {
                         return Err(format!(
-                            "Unable to patse optical_info object in file '{}'",
-                            path_str
-                        ))
+                            "Optical data in '{}' cannot be used: {}",
+                            path_str, e
+                        ));
                     }
-                };
-
-                info
-            } else {
-                // write into file
-                let info = OpticalInfo::new(&options, model, state)?;
-                let s = match serde_json::to_value(&info) {
-                    Ok(v) => v,
-                    Err(e) => return Err(format!("{}", e)),
-                };
-                let mut file = match File::create(path) {
-                    Ok(v) => v,
-                    Err(e) => return Err(format!("{}", e)),
-                };
-                if let Err(e) = writeln!(&mut file, "{}", s) {
-                    return Err(format!("{}", e));
                 }
-                info
+            }
+            match stored {
+                Some(mut info) => {
+                    // The options that do not affect the optical information
+                    // (e.g., the albedo) may have changed, so the ones used
+                    // by this run are recorded instead
+                    if info.options != model_options {
+                        info.options = model_options.clone();
+                        info.to_file(path)?;
+                    }
+                    info
+                }
+                None => {
+                    // calculate (reusing what did not change) and write into file
+                    let info = match &previous {
+                        Some(previous) => OpticalInfo::new_from_previous(
+                            previous,
+                            &options,
+                            &model_options,
+                            model,
+                            state,
+                        )?,
+                        None => OpticalInfo::new(&options, &model_options, model, state)?,
+                    };
+                    info.to_file(path)?;
+                    info
+                }
             }
         } else {
             // Forced calculation... not store
-            OpticalInfo::new(&options, model, state)?
+            OpticalInfo::new(&options, &model_options, model, state)?
         };
 
         // Create the Solar object
@@ -394,6 +501,8 @@ impl SimulationModel for SolarModel {
         let longitude = -meta_options.longitude;
         let standard_meridian = -meta_options.standard_meridian;
         let solar = Solar::new(latitude, longitude, standard_meridian);
+        let solar_time_offset =
+            (meta_options.longitude - meta_options.standard_meridian).to_degrees() / 15.;
 
         // derive MF
         let (.., ncols) = optical_info.back_surfaces_dc.size();
@@ -403,278 +512,3089 @@ impl SimulationModel for SolarModel {
                     .to_string(),
             );
         }
+        let mf = Self::mf_from_ncols(ncols)?;
+
+        // The direct-sun matrices were built with the options stored in the optical info
+        let sun_sky_discretization = optical_info.options.direct_sun_sky_discretization;
+
+        // Load the albedo data. The options that affect the optical info
+        // are the same ones used for building it.
+        let options = model_options;
+        let albedo_data = options.albedo.load()?;
+        let ground_temperature_data = options.ground_temperature.load()?;
+
+        // The skies are sampled once, for sky models other than Perez
+        let sky_patches = if options.sky_model == SkyModel::Perez {
+            Vec::new()
+        } else {
+            std::iter::once(mf)
+                .chain(sun_sky_discretization)
+                .map(SkyPatches::new)
+                .collect()
+        };
+
+        let sky_solid_angles = match &options.glare {
+            Some(_) => SkyPatches::new(mf).solid_angles().to_vec(),
+            None => Vec::new(),
+        };
+
+        let luminaires = match &options.lighting_control {
+            Some(_) if optical_info.workplanes.is_empty() => {
+                return Err(
+                    "Lighting control needs workplanes... set the `workplanes` in the SolarModelOptions"
+                        .into(),
+                )
+            }
+            Some(_) => ControlledLuminaire::from_model(model)?,
+            None => Vec::new(),
+        };
+
+        let distribution = SolarDistribution::new(model)?;
+        let gains_aggregator = SolarGainsAggregator::new(model)?;
+
+        let mut surface_emissivities = Vec::with_capacity(model.surfaces.len());
+        for s in model.surfaces.iter() {
+            surface_emissivities.push((
+                materials::thermal_absorbtance(model, &s.construction, true)?,
+                materials::thermal_absorbtance(model, &s.construction, false)?,
+            ))
+        }
+        let mut fenestration_emissivities = Vec::with_capacity(model.fenestrations.len());
+        for s in model.fenestrations.iter() {
+            fenestration_emissivities.push((
+                materials::thermal_absorbtance(model, &s.construction, true)?,
+                materials::thermal_absorbtance(model, &s.construction, false)?,
+            ))
+        }
+
+        Ok(Self {
+            optical_info,
+            solar,
+            solar_time_offset,
+            timestep_hours: 1. / n as Float,
+            solar_sky_discretization: mf,
+            sun_sky_discretization,
+            sky_patches,
+            sky_solid_angles,
+            options,
+            albedo_data,
+            ground_temperature_data,
+            distribution,
+            gains_aggregator,
+            empty_solar_gains: SolarGainsReport::new(model),
+            surface_emissivities,
+            fenestration_emissivities,
+            luminaires,
+            n_surfaces: model.surfaces.len(),
+            n_fenestrations: model.fenestrations.len(),
+        })
+    }
+
+    /// Derives the MF of a Reinhart sky from the number of columns in
+    /// a Daylight Coefficient matrix
+    fn mf_from_ncols(ncols: usize) -> Result<usize, String> {
         let mut mf = 1;
         loop {
             if mf >= 9 || ncols == 0 {
                 return Err(format!("sky discretization seems to be too high ({mf}... If this is a bug, please report it!"));
             }
             if solar::ReinhartSky::n_bins(mf) == ncols {
-                break;
+                return Ok(mf);
             } else {
                 mf += 1;
             }
         }
+    }
 
-        Ok(Self {
-            optical_info,
-            solar,
-            solar_sky_discretization: mf,
-        })
+    /// Gets the illuminance sensors of each space, in the same order as
+    /// the `workplane_illuminance` of the [`SolarModelMemory`]
+    pub fn workplanes(&self) -> &[Workplane] {
+        &self.optical_info.workplanes
     }
 
-    fn march<W: Weather, M: Borrow<SimpleModel>>(
+    /// Gets the emissivity of the front (`front == true`) or back side of a
+    /// surface, read from the thermal absorbtance of its outermost layer
+    pub fn surface_emissivity(&self, index: usize, front: bool) -> Option<Float> {
+        self.surface_emissivities
+            .get(index)
+            .map(|(f, b)| if front { *f } else { *b })
+    }
+
+    /// Gets the emissivity of the front (`front == true`) or back side of a
+    /// fenestration, read from the thermal absorbtance of its outermost layer
+    pub fn fenestration_emissivity(&self, index: usize, front: bool) -> Option<Float> {
+        self.fenestration_emissivities
+            .get(index)
+            .map(|(f, b)| if front { *f } else { *b })
+    }
+
+    /// Calculates the infrared irradiance incident on one side of a surface,
+    /// and the net infrared radiation it absorbs (i.e., absorbed minus emitted).
+    fn ir_exchange(
+        boundary: &Boundary,
+        surface_temp: Float,
+        emissivity: Float,
+        view_factors: &IRViewFactorSet,
+        env: &IREnvironment,
+    ) -> (Float, Float) {
+        let surface_emission = black_body(surface_temp);
+        let incident = match boundary {
+            Boundary::Space { .. } => {
+                // Zero net IR exchange: what the surface absorbs is what it emits,
+                // whatever its emissivity is. This is overwritten by the exchange
+                // with the other surfaces in the space, if their view factors are known.
+                surface_emission
+            }
+            Boundary::AmbientTemperature { temperature } => {
+                // The surface faces a large parallel gray surface at the ambient
+                // temperature and with the same emissivity. The exchange between
+                // them is σ(Ta^4 - Ts^4)/(2/ε - 1), which is expressed as an
+                // incident irradiance that is absorbed with emissivity ε
+                let env_emission = black_body(*temperature);
+                surface_emission + (env_emission - surface_emission) / (2. - emissivity)
+            }
+            Boundary::Ground => {
+                // The side is in contact with the ground. This is not really radiation,
+                // but exchanging IR with a black body at the ground temperature
+                // couples the side with the ground.
+                black_body(env.ground_temperature)
+            }
+            Boundary::Outdoor => {
+                // The air and the ground are assumed to be black bodies at dry bulb temperature
+                let ground_other =
+                    (view_factors.ground + view_factors.air) * black_body(env.dry_bulb);
+                let sky = view_factors.sky * env.horizontal_ir;
+                // Obstructing surfaces are black bodies at their own temperature
+                let obstructions: Float = view_factors
+                    .obstructions
+                    .iter()
+                    .map(|(side, v)| v * black_body(env.side_temperature(side)))
+                    .sum();
+                ground_other + sky + obstructions
+            }
+        };
+        let net = emissivity * (incident - surface_emission);
+        (incident, net)
+    }
+
+    /// Calculates the infrared irradiance incident on each side of the surfaces
+    /// and fenestrations, using their emissivities for calculating the net
+    /// exchange (stored in the `memory`).
+    ///
+    /// The outdoor conditions are averaged over the instants sampled by the
+    /// `timestep_averaging` of the [`SolarModelOptions`]. The trailing average
+    /// applies to every side, including those that face a space.
+    fn update_ir_radiation<W: Weather>(
         &self,
         date: Date,
         weather: &W,
-        model: M,
+        model: &SimpleModel,
         state: &mut SimulationState,
-        _alloc: &mut SolarModelMemory,
+        memory: &mut SolarModelMemory,
     ) -> Result<(), String> {
-        let model = model.borrow();
-        // Handle the solar part
+        let mut env = IREnvironment {
+            dry_bulb: 0.0,
+            horizontal_ir: 0.0,
+            ground_temperature: 0.0,
+            surface_temperatures: model
+                .surfaces
+                .iter()
+                .map(|s| {
+                    (
+                        s.first_node_temperature(state).unwrap_or(22.),
+                        s.last_node_temperature(state).unwrap_or(22.),
+                    )
+                })
+                .collect(),
+            fenestration_temperatures: model
+                .fenestrations
+                .iter()
+                .map(|s| {
+                    (
+                        s.first_node_temperature(state).unwrap_or(22.),
+                        s.last_node_temperature(state).unwrap_or(22.),
+                    )
+                })
+                .collect(),
+        };
 
-        let weather_data = weather.get_weather_data(date);
+        // The incident IR of the front and back sides, averaged over the timestep
+        let mut surface_incident = vec![(0.0, 0.0); model.surfaces.len()];
+        let mut fenestration_incident = vec![(0.0, 0.0); model.fenestrations.len()];
+        for (fraction, weight) in self.options.timestep_averaging.samples() {
+            let d = self.timestep_instant(date, fraction);
+            let weather_data = weather.get_weather_data(d);
+            env.dry_bulb = match weather_data.dry_bulb_temperature {
+                Some(v) => v,
+                None => {
+                    return Err("Cannot calculate IR radiation without Dry Bulb temperature".into())
+                }
+            };
+            env.horizontal_ir = match weather_data.horizontal_infrared_radiation_intensity {
+                Some(v) => v,
+                None => weather_data.derive_horizontal_ir()?,
+            };
+            env.ground_temperature =
+                self.options
+                    .ground_temperature
+                    .get(d, &self.ground_temperature_data, env.dry_bulb);
 
-        self.update_ir_radiation(&weather_data, model, state)?;
-        self.update_solar_radiation(date, weather_data, model, state)?;
+            for (index, surface) in model.surfaces.iter().enumerate() {
+                let (front_emissivity, back_emissivity) = self.surface_emissivities[index];
+                let (front_temp, back_temp) = env.surface_temperatures[index];
+                let (front, _) = Self::ir_exchange(
+                    &surface.front_boundary,
+                    front_temp,
+                    front_emissivity,
+                    &self.optical_info.front_surfaces_view_factors[index],
+                    &env,
+                );
+                let (back, _) = Self::ir_exchange(
+                    &surface.back_boundary,
+                    back_temp,
+                    back_emissivity,
+                    &self.optical_info.back_surfaces_view_factors[index],
+                    &env,
+                );
+                surface_incident[index].0 += weight * front;
+                surface_incident[index].1 += weight * back;
+            }
+            for (index, fenestration) in model.fenestrations.iter().enumerate() {
+                let (front_emissivity, back_emissivity) = self.fenestration_emissivities[index];
+                let (front_temp, back_temp) = env.fenestration_temperatures[index];
+                let (front, _) = Self::ir_exchange(
+                    &fenestration.front_boundary,
+                    front_temp,
+                    front_emissivity,
+                    &self.optical_info.front_fenestrations_view_factors[index],
+                    &env,
+                );
+                let (back, _) = Self::ir_exchange(
+                    &fenestration.back_boundary,
+                    back_temp,
+                    back_emissivity,
+                    &self.optical_info.back_fenestrations_view_factors[index],
+                    &env,
+                );
+                fenestration_incident[index].0 += weight * front;
+                fenestration_incident[index].1 += weight * back;
+            }
+        }
 
-        Ok(())
-    }
-}
+        // The sides that face a space exchange IR with the other sides of that
+        // space. Their temperatures do not change within the timestep, so every
+        // sampled instant gets the same value.
+        let (surface_interior, fenestration_interior) = self.interior_ir_irradiance(model, &env)?;
+        for (incident, interior) in surface_incident
+            .iter_mut()
+            .chain(fenestration_incident.iter_mut())
+            .zip(surface_interior.iter().chain(fenestration_interior.iter()))
+        {
+            if let Some(v) = interior.0 {
+                incident.0 = v;
+            }
+            if let Some(v) = interior.1 {
+                incident.1 = v;
+            }
+        }
 
-#[cfg(test)]
-mod testing {
-    use super::*;
-    use schedule::ScheduleConstant;
-    use simple_model::{substance::Normal, Construction, Fenestration, Material, Surface};
-    use weather::SyntheticWeather;
+        // Averages with the previous timestep, if needed, and calculates the
+        // net exchange (i.e., absorbed minus emitted)
+        let trailing = self.trailing_average(memory);
+        let finish = |incident: Float, old: Option<Float>, temp: Float, emissivity: Float| {
+            let incident = match (trailing, old) {
+                (true, Some(old)) => (incident + old) / 2.,
+                (true, None) => return Err("Could not get previous incident IR irradiance"),
+                (false, _) => incident,
+            };
+            Ok((incident, emissivity * (incident - black_body(temp))))
+        };
+
+        for (index, surface) in model.surfaces.iter().enumerate() {
+            let (front_emissivity, back_emissivity) = self.surface_emissivities[index];
+            let (front_temp, back_temp) = env.surface_temperatures[index];
+            let (front, back) = surface_incident[index];
+
+            // Deal with front
+            let (incident, net) = finish(
+                front,
+                surface.front_ir_irradiance(state),
+                front_temp,
+                front_emissivity,
+            )?;
+            surface.set_front_ir_irradiance(state, incident)?;
+            memory.surface_front_net_ir[index] = net;
+
+            // Deal with Back
+            let (incident, net) = finish(
+                back,
+                surface.back_ir_irradiance(state),
+                back_temp,
+                back_emissivity,
+            )?;
+            surface.set_back_ir_irradiance(state, incident)?;
+            memory.surface_back_net_ir[index] = net;
+        }
+
+        for (index, fenestration) in model.fenestrations.iter().enumerate() {
+            let (front_emissivity, back_emissivity) = self.fenestration_emissivities[index];
+            let (front_temp, back_temp) = env.fenestration_temperatures[index];
+            let (front, back) = fenestration_incident[index];
+
+            // Deal with front
+            let (incident, net) = finish(
+                front,
+                fenestration.front_ir_irradiance(state),
+                front_temp,
+                front_emissivity,
+            )?;
+            fenestration.set_front_ir_irradiance(state, incident)?;
+            memory.fenestration_front_net_ir[index] = net;
+
+            // Deal with Back
+            let (incident, net) = finish(
+                back,
+                fenestration.back_ir_irradiance(state),
+                back_temp,
+                back_emissivity,
+            )?;
+            fenestration.set_back_ir_irradiance(state, incident)?;
+            memory.fenestration_back_net_ir[index] = net;
+        }
+
+        Ok(())
+    }
+
+    /// Calculates the infrared irradiance exchanged between the surfaces and
+    /// fenestrations that bound the same space, using their temperatures.
+    ///
+    /// Returns the irradiance incident on the front and back side of each
+    /// surface and fenestration, which is `None` for the sides that do not
+    /// bound a space.
+    #[allow(clippy::type_complexity)]
+    fn interior_ir_irradiance(
+        &self,
+        model: &SimpleModel,
+        env: &IREnvironment,
+    ) -> Result<
+        (
+            Vec<(Option<Float>, Option<Float>)>,
+            Vec<(Option<Float>, Option<Float>)>,
+        ),
+        String,
+    > {
+        let mut surfaces = vec![(None, None); model.surfaces.len()];
+        let mut fenestrations = vec![(None, None); model.fenestrations.len()];
+        for space in self.optical_info.interior_view_factors.iter() {
+            let mut temperatures = Vec::with_capacity(space.sides.len());
+            let mut emissivities = Vec::with_capacity(space.sides.len());
+            for side in space.sides.iter() {
+                let (temps, emissivities_pair) = match side.kind {
+                    SpaceSideKind::Surface => (
+                        env.surface_temperatures[side.index],
+                        self.surface_emissivities[side.index],
+                    ),
+                    SpaceSideKind::Fenestration => (
+                        env.fenestration_temperatures[side.index],
+                        self.fenestration_emissivities[side.index],
+                    ),
+                };
+                if side.front {
+                    temperatures.push(temps.0);
+                    emissivities.push(emissivities_pair.0);
+                } else {
+                    temperatures.push(temps.1);
+                    emissivities.push(emissivities_pair.1);
+                }
+            }
+
+            let incident = space.incident_irradiance(&temperatures, &emissivities)?;
+
+            for (side, incident) in space.sides.iter().zip(incident.into_iter()) {
+                let pair = match side.kind {
+                    SpaceSideKind::Surface => &mut surfaces[side.index],
+                    SpaceSideKind::Fenestration => &mut fenestrations[side.index],
+                };
+                if side.front {
+                    pair.0 = Some(incident);
+                } else {
+                    pair.1 = Some(incident);
+                }
+            }
+        }
+        Ok((surfaces, fenestrations))
+    }
+
+    /// Multiplies a Daylight Coefficient matrix by a sky vector. If there is a
+    /// vector containing only the sun, the contribution of the direct-sun
+    /// coefficient matrix is added.
+    fn calc_irradiance(
+        dc: &Matrix,
+        vec: &Matrix,
+        sun_dc: &Matrix,
+        sun_vec: &Option<Matrix>,
+    ) -> Result<Matrix, String> {
+        let mut ret = dc * vec;
+        if let Some(sun_vec) = sun_vec {
+            let direct = sun_dc * sun_vec;
+            let (nrows, ..) = ret.size();
+            for i in 0..nrows {
+                let v = ret.get(i, 0)? + direct.get(i, 0)?;
+                ret.set(i, 0, v)?;
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Gets the cosine of the solar zenith angle at a certain date
+    fn cos_zenith(&self, date: Date) -> Float {
+        self.solar
+            .sun_position(Time::Standard(date.day_of_year()))
+            .map(|direction| direction.z)
+            .unwrap_or(0.0)
+    }
+
+    /// Gets the clearness index of the hour centred at a certain date, if the
+    /// global horizontal irradiance is known and the sun is high enough
+    fn hourly_clearness_index<W: Weather>(&self, date: Date, weather: &W) -> Option<Float> {
+        let global_horizontal = weather.get_weather_data(date).global_horizontal_radiation?;
+        let cos_zenith = self.cos_zenith(date);
+        if cos_zenith < MIN_COS_ZENITH {
+            return None;
+        }
+        let input = DecompositionInput {
+            global_horizontal,
+            cos_zenith,
+            day_of_year: date.day_of_year(),
+            ..DecompositionInput::default()
+        };
+        Some(input.clearness_index())
+    }
+
+    /// Gathers the information needed by a decomposition model for
+    /// splitting the global horizontal irradiance at a certain date.
+    ///
+    /// The daily clearness index and the persistence, which need the
+    /// weather data of other hours, are only calculated for the models that use them.
+    fn decomposition_input<W: Weather>(
+        &self,
+        model: DecompositionModel,
+        date: Date,
+        weather: &W,
+        data: &CurrentWeather,
+        global_horizontal: Float,
+    ) -> DecompositionInput {
+        let day_of_year = date.day_of_year();
+        let relative_humidity = match (data.dry_bulb_temperature, data.dew_point_temperature) {
+            (Some(dry_bulb), Some(dew_point)) => {
+                Some(decomposition::relative_humidity(dry_bulb, dew_point))
+            }
+            _ => None,
+        };
+        let mut input = DecompositionInput {
+            global_horizontal,
+            cos_zenith: self.cos_zenith(date),
+            day_of_year,
+            solar_time: date.hour
+                + self.solar_time_offset
+                + decomposition::equation_of_time(day_of_year) / 60.,
+            dry_bulb_temperature: data.dry_bulb_temperature,
+            relative_humidity,
+            pressure: None,
+            daily_clearness_index: None,
+            persistence: None,
+        };
+
+        if model == DecompositionModel::BolandRidleyLauret {
+            // Hours of the same day
+            let (mut global, mut extraterrestrial) = (0.0, 0.0);
+            for hour in 0..24 {
+                let d = Date {
+                    hour: hour as Float + 0.5,
+                    ..date
+                };
+                let cos_zenith = self.cos_zenith(d);
+                if cos_zenith <= 0.0 {
+                    continue;
+                }
+                if let Some(v) = weather.get_weather_data(d).global_horizontal_radiation {
+                    global += v;
+                    extraterrestrial +=
+                        decomposition::extraterrestrial_irradiance(d.day_of_year()) * cos_zenith;
+                }
+            }
+            if extraterrestrial > 0.0 {
+                input.daily_clearness_index = Some((global / extraterrestrial).clamp(0.0, 1.0));
+            }
+
+            // Previous and next hours
+            let neighbours: Vec<Float> = [-1., 1.]
+                .iter()
+                .filter_map(|hours| {
+                    let mut d = date;
+                    d.add_hours(*hours);
+                    self.hourly_clearness_index(d, weather)
+                })
+                .collect();
+            if !neighbours.is_empty() {
+                input.persistence =
+                    Some(neighbours.iter().sum::<Float>() / neighbours.len() as Float);
+            }
+        }
+        input
+    }
+
+    /// Gets the weather data for a certain date. If the direct normal or the
+    /// diffuse horizontal irradiance are missing, they are filled according
+    /// to the `missing_solar_data` option. If there is a `decomposition`
+    /// model, they are always derived from the global horizontal irradiance.
+    fn solar_weather_data<W: Weather>(
+        &self,
+        date: Date,
+        weather: &W,
+    ) -> Result<CurrentWeather, String> {
+        let mut data = weather.get_weather_data(date);
+        if let Some(model) = self.options.decomposition {
+            let global_horizontal = data.global_horizontal_radiation.ok_or_else(|| {
+                format!(
+                    "Missing global horizontal irradiance {}, which is needed by the decomposition model",
+                    date_str(date)
+                )
+            })?;
+            let input = self.decomposition_input(model, date, weather, &data, global_horizontal);
+            let (direct_normal, diffuse_horizontal) = model.decompose(&input);
+            data.direct_normal_radiation = Some(direct_normal);
+            data.diffuse_horizontal_radiation = Some(diffuse_horizontal);
+            return Ok(data);
+        }
+        if data.direct_normal_radiation.is_some() && data.diffuse_horizontal_radiation.is_some() {
+            return Ok(data);
+        }
+        match self.options.missing_solar_data {
+            MissingSolarData::Error => {
+                // This reports what is missing
+                solar_irradiance_data(date, &data)?;
+            }
+            MissingSolarData::Zero => {
+                data.direct_normal_radiation.get_or_insert(0.0);
+                data.diffuse_horizontal_radiation.get_or_insert(0.0);
+            }
+            MissingSolarData::Interpolate { max_hours } => {
+                if data.direct_normal_radiation.is_none() {
+                    let v = interpolate_weather(date, weather, max_hours, |d| {
+                        d.direct_normal_radiation
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "Could not interpolate the missing direct normal irradiance {}",
+                            date_str(date)
+                        )
+                    })?;
+                    data.direct_normal_radiation = Some(v);
+                }
+                if data.diffuse_horizontal_radiation.is_none() {
+                    let v = interpolate_weather(date, weather, max_hours, |d| {
+                        d.diffuse_horizontal_radiation
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "Could not interpolate the missing diffuse horizontal irradiance {}",
+                            date_str(date)
+                        )
+                    })?;
+                    data.diffuse_horizontal_radiation = Some(v);
+                }
+            }
+            MissingSolarData::Decompose { model } => {
+                let global_horizontal = match data.global_horizontal_radiation {
+                    Some(v) => v,
+                    None => {
+                        return Err(format!(
+                            "Cannot derive the missing direct normal and diffuse horizontal irradiance {}, because the global horizontal irradiance is missing as well",
+                            date_str(date)
+                        ))
+                    }
+                };
+                let input =
+                    self.decomposition_input(model, date, weather, &data, global_horizontal);
+                let (direct_normal, diffuse_horizontal) = model.decompose(&input);
+                data.direct_normal_radiation = Some(direct_normal);
+                data.diffuse_horizontal_radiation = Some(diffuse_horizontal);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Builds a sky vector like `PerezSky::gen_sky_vec()` does, but using the
+    /// `sky_model` of the [`SolarModelOptions`]
+    #[allow(clippy::too_many_arguments)]
+    fn gen_sky_vec(
+        &self,
+        mf: usize,
+        date: Date,
+        weather_data: &CurrentWeather,
+        units: SkyUnits,
+        albedo: Float,
+        add_sky: bool,
+        add_sun: bool,
+    ) -> Result<Matrix, String> {
+        if self.options.sky_model == SkyModel::Perez {
+            return PerezSky::gen_sky_vec(
+                mf,
+                &self.solar,
+                date,
+                weather_data.clone(),
+                units,
+                albedo,
+                add_sky,
+                add_sun,
+            );
+        }
+        let patches = self
+            .sky_patches
+            .iter()
+            .find(|p| p.mf == mf)
+            .ok_or_else(|| format!("The sky with MF {} has not been sampled", mf))?;
+        patches.gen_sky_vec(
+            self.options.sky_model,
+            &self.solar,
+            date,
+            weather_data,
+            units,
+            albedo,
+            add_sky,
+            add_sun,
+        )
+    }
+
+    /// Builds the sky vectors for a certain date. Returns `None` during the night.
+    fn sky_vectors(
+        &self,
+        date: Date,
+        weather_data: &CurrentWeather,
+    ) -> Result<Option<SkyVectors>, String> {
+        let (direct_normal_irrad, diffuse_horizontal_irrad) =
+            solar_irradiance_data(date, weather_data)?;
+
+        let is_day = direct_normal_irrad + diffuse_horizontal_irrad >= 1e-4;
+        if !is_day {
+            return Ok(None);
+        }
+
+        // Build sky vector
+        let albedo = self.options.albedo.get(date, &self.albedo_data)?;
+        let vectors = match self.sun_sky_discretization {
+            None => {
+                let sky = self.gen_sky_vec(
+                    self.solar_sky_discretization,
+                    date,
+                    weather_data,
+                    SkyUnits::Solar,
+                    albedo,
+                    true, // add sky
+                    true, // add sun
+                )?;
+                let beam = if self.distribution.fenestrations.is_empty() {
+                    None
+                } else {
+                    let mut beam = self.gen_sky_vec(
+                        self.solar_sky_discretization,
+                        date,
+                        weather_data,
+                        SkyUnits::Solar,
+                        albedo,
+                        false, // add sky
+                        true,  // add sun
+                    )?;
+                    beam.set(0, 0, 0.0)?; // the ground is not beam radiation
+                    Some(beam)
+                };
+                SkyVectors {
+                    sky,
+                    sun: None,
+                    beam,
+                }
+            }
+            Some(sun_mf) => {
+                // The diffuse sky goes into the coarse vector...
+                let sky = self.gen_sky_vec(
+                    self.solar_sky_discretization,
+                    date,
+                    weather_data,
+                    SkyUnits::Solar,
+                    albedo,
+                    true,  // add sky
+                    false, // add sun
+                )?;
+                // ... and the sun into the fine one.
+                let mut sun = self.gen_sky_vec(
+                    sun_mf,
+                    date,
+                    weather_data,
+                    SkyUnits::Solar,
+                    albedo,
+                    false, // add sky
+                    true,  // add sun
+                )?;
+                // The ground (bin 0) is already accounted for in the diffuse vector
+                sun.set(0, 0, 0.0)?;
+                SkyVectors {
+                    sky,
+                    sun: Some(sun),
+                    beam: None,
+                }
+            }
+        };
+        Ok(Some(vectors))
+    }
+
+    /// Builds the sky vectors at the instants sampled by the `timestep_averaging`
+    /// of the [`SolarModelOptions`] within the timestep that ends at a certain
+    /// date. The instants during the night are skipped.
+    fn timestep_sky_samples<W: Weather>(
+        &self,
+        date: Date,
+        weather_data: &CurrentWeather,
+        weather: &W,
+    ) -> Result<Vec<SkySample>, String> {
+        let mut ret = Vec::new();
+        for (fraction, weight) in self.options.timestep_averaging.samples() {
+            let d = self.timestep_instant(date, fraction);
+            let vectors = if fraction == 0.0 {
+                self.sky_vectors(d, weather_data)?
+            } else {
+                let data = self.solar_weather_data(d, weather)?;
+                self.sky_vectors(d, &data)?
+            };
+            if let Some(vectors) = vectors {
+                ret.push(SkySample {
+                    weight,
+                    date: d,
+                    vectors,
+                });
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Averages the sky vectors sampled within a timestep. Returns `None`
+    /// if it is night during the whole timestep.
+    fn timestep_sky_vectors(samples: &[SkySample]) -> Result<Option<SkyVectors>, String> {
+        let mut ret: Option<SkyVectors> = None;
+        for sample in samples {
+            ret.get_or_insert_with(|| SkyVectors::zeros_like(&sample.vectors))
+                .add_weighted(&sample.vectors, sample.weight)?;
+        }
+        Ok(ret)
+    }
+
+    /// Gets the instant that is a `fraction` of the timestep before its end
+    fn timestep_instant(&self, end: Date, fraction: Float) -> Date {
+        let mut date = end;
+        if fraction != 0.0 {
+            date.add_hours(-fraction * self.timestep_hours);
+        }
+        date
+    }
+
+    /// Checks whether the irradiance of this timestep has to be averaged
+    /// with the one set on the previous timestep. The first timestep
+    /// simulated with a `memory` has no previous one, so it is not averaged.
+    fn trailing_average(&self, memory: &SolarModelMemory) -> bool {
+        self.options.timestep_averaging == TimestepAveraging::TrailingAverage && memory.marched
+    }
+
+    /// Builds the visible sky vectors for a certain date. Returns `None` during the night.
+    fn visible_sky_vectors(
+        &self,
+        date: Date,
+        weather_data: &CurrentWeather,
+    ) -> Result<Option<VisibleSkyVectors>, String> {
+        let (direct_normal_irrad, diffuse_horizontal_irrad) =
+            solar_irradiance_data(date, weather_data)?;
+        if direct_normal_irrad + diffuse_horizontal_irrad < 1e-4 {
+            return Ok(None);
+        }
+
+        let albedo = self.options.albedo.get(date, &self.albedo_data)?;
+        let sky = self.gen_sky_vec(
+            self.solar_sky_discretization,
+            date,
+            weather_data,
+            SkyUnits::Visible,
+            albedo,
+            true,  // add sky
+            false, // add sun
+        )?;
+        let sun = self.gen_sky_vec(
+            self.solar_sky_discretization,
+            date,
+            weather_data,
+            SkyUnits::Visible,
+            albedo,
+            false, // add sky
+            true,  // add sun
+        )?;
+        let mut all = sky.clone();
+        let (nrows, ..) = all.size();
+        for i in 0..nrows {
+            all.set(i, 0, sky.get(i, 0)? + sun.get(i, 0)?)?;
+        }
+        let mut direct_sun = match self.options.direct_sun_sky_discretization {
+            Some(sun_mf) => self.gen_sky_vec(
+                sun_mf,
+                date,
+                weather_data,
+                SkyUnits::Visible,
+                albedo,
+                false, // add sky
+                true,  // add sun
+            )?,
+            None => sun.clone(),
+        };
+        // The light reflected by the ground is not direct sun
+        direct_sun.set(0, 0, 0.0)?;
+        let sun_direction = self.solar.sun_position(Time::Standard(date.day_of_year()));
+        Ok(Some(VisibleSkyVectors {
+            all,
+            sky,
+            sun,
+            direct_sun,
+            sun_direction,
+        }))
+    }
+
+    /// Builds the visible sky vectors of the timestep that ends at a certain
+    /// date, averaging the instants sampled by the `timestep_averaging` of the
+    /// [`SolarModelOptions`] (like `timestep_sky_samples()` does). Returns `None`
+    /// if it is night during the whole timestep.
+    fn timestep_visible_sky_vectors<W: Weather>(
+        &self,
+        date: Date,
+        weather_data: &CurrentWeather,
+        weather: &W,
+    ) -> Result<Option<VisibleSkyVectors>, String> {
+        let samples = self.options.timestep_averaging.samples();
+        if let [(fraction, _)] = samples.as_slice() {
+            if *fraction == 0.0 {
+                return self.visible_sky_vectors(date, weather_data);
+            }
+        }
+        let mut ret: Option<VisibleSkyVectors> = None;
+        let mut sun_direction = Vector3D::new(0., 0., 0.);
+        for (fraction, weight) in samples {
+            let d = self.timestep_instant(date, fraction);
+            let data = self.solar_weather_data(d, weather)?;
+            if let Some(v) = self.visible_sky_vectors(d, &data)? {
+                let acc = ret.get_or_insert_with(|| {
+                    let zeros = |m: &Matrix| {
+                        let (nrows, ncols) = m.size();
+                        Matrix::new(0.0, nrows, ncols)
+                    };
+                    VisibleSkyVectors {
+                        all: zeros(&v.all),
+                        sky: zeros(&v.sky),
+                        sun: zeros(&v.sun),
+                        direct_sun: zeros(&v.direct_sun),
+                        sun_direction: None,
+                    }
+                });
+                add_weighted(&mut acc.all, &v.all, weight)?;
+                add_weighted(&mut acc.sky, &v.sky, weight)?;
+                add_weighted(&mut acc.sun, &v.sun, weight)?;
+                add_weighted(&mut acc.direct_sun, &v.direct_sun, weight)?;
+                if let Some(dir) = v.sun_direction {
+                    sun_direction = sun_direction + dir * weight;
+                }
+            }
+        }
+        if let Some(v) = &mut ret {
+            if sun_direction.length() > 1e-6 {
+                v.sun_direction = Some(sun_direction.get_normalized());
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Calculates the illuminance at the sensors of each [`Workplane`]
+    fn update_illuminance(
+        &self,
+        sky: &Option<VisibleSkyVectors>,
+        memory: &mut SolarModelMemory,
+    ) -> Result<(), String> {
+        if self.optical_info.workplanes.is_empty() {
+            return Ok(());
+        }
+        let sky = match sky {
+            Some(v) => v,
+            None => {
+                memory
+                    .workplane_illuminance
+                    .iter_mut()
+                    .chain(memory.workplane_sun_illuminance.iter_mut())
+                    .for_each(|e| e.fill(0.0));
+                return Ok(());
+            }
+        };
+        Self::calc_illuminance(
+            &self.optical_info.workplanes_dc,
+            &sky.all,
+            &mut memory.workplane_illuminance,
+        )?;
+        Self::calc_illuminance(
+            &self.optical_info.workplanes_sun_dc,
+            &sky.direct_sun,
+            &mut memory.workplane_sun_illuminance,
+        )?;
+        Ok(())
+    }
+
+    /// Evaluates glare at each viewpoint, if `glare` is set in the [`SolarModelOptions`]
+    fn update_glare(
+        &self,
+        sky: &Option<VisibleSkyVectors>,
+        memory: &mut SolarModelMemory,
+    ) -> Result<(), String> {
+        let source_factor = match &self.options.glare {
+            Some(options) => options.source_factor,
+            None => return Ok(()),
+        };
+        let sky = match sky {
+            Some(v) => v,
+            None => {
+                memory.glare.fill(GlareResult::default());
+                return Ok(());
+            }
+        };
+
+        // Luminance of the sky, and illuminance of the sun (i.e., the
+        // radiance of the patches it is in, times their solid angle)
+        let (n_bins, ..) = sky.sky.size();
+        if self.sky_solid_angles.len() != n_bins {
+            return Err(format!(
+                "Expecting a sky vector with {} bins for glare analysis... found {}",
+                self.sky_solid_angles.len(),
+                n_bins
+            ));
+        }
+        let mut sky_luminance = Vec::with_capacity(n_bins);
+        let mut sun_illuminance = 0.0;
+        for (i, solid_angle) in self.sky_solid_angles.iter().enumerate() {
+            sky_luminance.push(WHITE_EFFICACY * sky.sky.get(i, 0)?);
+            // The ground (with no solid angle) only reflects the sun
+            sun_illuminance += WHITE_EFFICACY * sky.sun.get(i, 0)? * solid_angle;
+        }
+        let sun = sky
+            .sun_direction
+            .map(|direction| (direction, sun_illuminance));
+
+        for (view, result) in self
+            .optical_info
+            .glare_views
+            .iter()
+            .zip(memory.glare.iter_mut())
+        {
+            let vertical_illuminance = WHITE_EFFICACY * (&view.dc * &sky.all).get(0, 0)?.max(0.0);
+            let surface_illuminance = if view.irradiance_dc.is_empty() {
+                Vec::new()
+            } else {
+                let irradiance = &view.irradiance_dc * &sky.all;
+                let (n_surfaces, ..) = irradiance.size();
+                let mut v = Vec::with_capacity(n_surfaces);
+                for i in 0..n_surfaces {
+                    v.push(WHITE_EFFICACY * irradiance.get(i, 0)?.max(0.0));
+                }
+                v
+            };
+            *result = view.evaluate(
+                vertical_illuminance,
+                &sky_luminance,
+                &surface_illuminance,
+                sun,
+                source_factor,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Dims the luminaires of each space according to the illuminance at its
+    /// [`Workplane`], if `lighting_control` is set in the [`SolarModelOptions`]
+    fn update_lighting(
+        &self,
+        model: &SimpleModel,
+        state: &mut SimulationState,
+        memory: &mut SolarModelMemory,
+    ) -> Result<(), String> {
+        let control = match &self.options.lighting_control {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        for (fraction, illuminance) in memory
+            .lighting_power_fraction
+            .iter_mut()
+            .zip(memory.workplane_illuminance.iter())
+        {
+            *fraction = control.power_fraction(illuminance);
+        }
+        for luminaire in self.luminaires.iter() {
+            let fraction = memory.lighting_power_fraction[luminaire.space];
+            model.luminaires[luminaire.index]
+                .set_power_consumption(state, luminaire.max_power * fraction)?;
+        }
+        Ok(())
+    }
+
+    /// Multiplies the Daylight Coefficients matrix of each [`Workplane`] by
+    /// a sky vector, writing the resulting illuminance
+    fn calc_illuminance(
+        dcs: &[Matrix],
+        vec: &Matrix,
+        illuminance: &mut [Vec<Float>],
+    ) -> Result<(), String> {
+        for (dc, illuminance) in dcs.iter().zip(illuminance.iter_mut()) {
+            if dc.is_empty() {
+                continue;
+            }
+            let irradiance = dc * vec;
+            for (i, e) in illuminance.iter_mut().enumerate() {
+                *e = WHITE_EFFICACY * irradiance.get(i, 0)?.max(0.0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Calculates the climate-based daylight metrics of each space by running an
+    /// hourly simulation of a whole year (e.g., using an EPW file as `weather`).
+    ///
+    /// This needs the `workplanes` of the [`SolarModelOptions`] to be set. The
+    /// Annual Sunlight Exposure also needs the `direct_sun_sky_discretization`, as
+    /// a coarse sky smears the sun over large patches, making its threshold on the
+    /// direct sun unreliable. Without it, the Annual Sunlight Exposure is `None`
+    /// (and the `sunlit_hours` are empty), but the other metrics are calculated.
+    pub fn daylight_metrics<W: Weather>(
+        &self,
+        weather: &W,
+        options: DaylightMetricsOptions,
+    ) -> Result<DaylightReport, String> {
+        if self.optical_info.workplanes.is_empty() {
+            return Err(
+                "Daylight metrics need workplanes... set the `workplanes` in the SolarModelOptions"
+                    .into(),
+            );
+        }
+        let mut memory = self.allocate_memory()?;
+        let mut accumulator =
+            DaylightMetricsAccumulator::new(&self.optical_info.workplanes, options);
+        let mut date = first_hour();
+        for _ in 0..8760 {
+            let weather_data = self.solar_weather_data(date, weather)?;
+            let sky = self.visible_sky_vectors(date, &weather_data)?;
+            self.update_illuminance(&sky, &mut memory)?;
+            accumulator.add(date, 1., &memory)?;
+            date.add_hours(1.);
+        }
+        let mut report = accumulator.report();
+        if self.options.direct_sun_sky_discretization.is_none() {
+            for space in report.spaces.iter_mut() {
+                space.sunlit_hours.clear();
+                space.annual_sunlight_exposure = None;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Sets the solar irradiance incident on both sides of the surfaces and
+    /// fenestrations that receive sun, averaging it with the previous
+    /// timestep if `trailing`
+    fn update_solar_radiation(
+        &self,
+        sky: &Option<SkyVectors>,
+        model: &SimpleModel,
+        state: &mut SimulationState,
+        trailing: bool,
+    ) -> Result<(), String> {
+        let info = &self.optical_info;
+
+        // Process Solar Irradiance in Surfaces
+        let surfaces = &model.surfaces;
+        self.update_incident_solar(
+            sky,
+            trailing,
+            (&info.front_surfaces_dc, &info.front_surfaces_sun_dc),
+            state,
+            surfaces.len(),
+            |i| SolarSurface::boundary_receives_sun(&surfaces[i].front_boundary),
+            |state, i| surfaces[i].front_incident_solar_irradiance(state),
+            |state, i, v| surfaces[i].set_front_incident_solar_irradiance(state, v),
+        )?;
+        self.update_incident_solar(
+            sky,
+            trailing,
+            (&info.back_surfaces_dc, &info.back_surfaces_sun_dc),
+            state,
+            surfaces.len(),
+            |i| SolarSurface::boundary_receives_sun(&surfaces[i].back_boundary),
+            |state, i| surfaces[i].back_incident_solar_irradiance(state),
+            |state, i, v| surfaces[i].set_back_incident_solar_irradiance(state, v),
+        )?;
+
+        // Process Solar Irradiance in Fenestration
+        let fenestrations = &model.fenestrations;
+        self.update_incident_solar(
+            sky,
+            trailing,
+            (
+                &info.front_fenestrations_dc,
+                &info.front_fenestrations_sun_dc,
+            ),
+            state,
+            fenestrations.len(),
+            |i| SolarSurface::boundary_receives_sun(&fenestrations[i].front_boundary),
+            |state, i| fenestrations[i].front_incident_solar_irradiance(state),
+            |state, i, v| fenestrations[i].set_front_incident_solar_irradiance(state, v),
+        )?;
+        self.update_incident_solar(
+            sky,
+            trailing,
+            (&info.back_fenestrations_dc, &info.back_fenestrations_sun_dc),
+            state,
+            fenestrations.len(),
+            |i| SolarSurface::boundary_receives_sun(&fenestrations[i].back_boundary),
+            |state, i| fenestrations[i].back_incident_solar_irradiance(state),
+            |state, i, v| fenestrations[i].set_back_incident_solar_irradiance(state, v),
+        )
+    }
+
+    /// Sets the solar irradiance incident on one side of `n` surfaces or
+    /// fenestrations. Only those that `receive_sun` have a row in the
+    /// `(dc, sun_dc)` matrices. During the night (i.e., when `sky` is `None`)
+    /// the irradiance is zero. If `trailing`, the result is averaged with the
+    /// irradiance set on the previous timestep.
+    ///
+    /// The `get` and `set` functions read and write the irradiance in the state.
+    #[allow(clippy::too_many_arguments)]
+    fn update_incident_solar<R, G, S>(
+        &self,
+        sky: &Option<SkyVectors>,
+        trailing: bool,
+        (dc, sun_dc): (&Matrix, &Matrix),
+        state: &mut SimulationState,
+        n: usize,
+        receives_sun: R,
+        get: G,
+        set: S,
+    ) -> Result<(), String>
+    where
+        R: Fn(usize) -> bool,
+        G: Fn(&SimulationState, usize) -> Option<Float>,
+        S: Fn(&mut SimulationState, usize, Float) -> Result<(), String>,
+    {
+        if dc.is_empty() {
+            return Ok(());
+        }
+        let irradiance = match sky {
+            Some(sky) => Some(Self::calc_irradiance(dc, &sky.sky, sun_dc, &sky.sun)?),
+            None => None,
+        };
+        let mut row = 0;
+        for i in 0..n {
+            if !receives_sun(i) {
+                continue;
+            }
+            let mut v = match &irradiance {
+                Some(irradiance) => irradiance.get(row, 0)?.max(0.0),
+                None => 0.0,
+            };
+            row += 1;
+            if trailing {
+                let old_v =
+                    get(state, i).ok_or("Could not get previous incident solar irradiance")?;
+                v = (v + old_v) / 2.;
+            }
+            set(state, i, v)?;
+        }
+        Ok(())
+    }
+
+    /// Calculates the solar radiation transmitted through the fenestrations
+    /// and distributes it over the surfaces of the spaces they face.
+    fn update_transmitted_solar(
+        &self,
+        samples: &[SkySample],
+        memory: &mut SolarModelMemory,
+    ) -> Result<(), String> {
+        memory.reset_transmitted_solar();
+        if self.distribution.fenestrations.is_empty() {
+            return Ok(());
+        }
+        // The transmittance of the beam depends on the position of the sun,
+        // so each instant is distributed separately
+        for sample in samples {
+            self.distribute_transmitted_solar(sample, memory)?;
+        }
+        Ok(())
+    }
+
+    /// Distributes the solar radiation transmitted at one of the instants
+    /// sampled within the timestep, weighted by its share of the timestep
+    fn distribute_transmitted_solar(
+        &self,
+        sample: &SkySample,
+        memory: &mut SolarModelMemory,
+    ) -> Result<(), String> {
+        let sky = &sample.vectors;
+
+        // Multiplies a matrix by a vector, unless the matrix is empty (i.e.,
+        // there are no fenestrations receiving sun on that side)
+        let mul = |m: &Matrix, v: &Matrix| -> Matrix {
+            if m.is_empty() {
+                Matrix::empty()
+            } else {
+                m * v
+            }
+        };
+        let info = &self.optical_info;
+
+        // Irradiance from the sky vector
+        let front_sky = mul(&info.front_fenestrations_dc, &sky.sky);
+        let back_sky = mul(&info.back_fenestrations_dc, &sky.sky);
+
+        // Beam irradiance
+        let (front_beam, back_beam) = match (&sky.sun, &sky.beam) {
+            (Some(sun), _) => (
+                mul(&info.front_fenestrations_sun_dc, sun),
+                mul(&info.back_fenestrations_sun_dc, sun),
+            ),
+            (None, Some(beam)) => (
+                mul(&info.front_fenestrations_dc, beam),
+                mul(&info.back_fenestrations_dc, beam),
+            ),
+            (None, None) => {
+                return Err("The sky vectors lack the beam radiation".into());
+            }
+        };
+
+        let mut incident = Vec::with_capacity(self.distribution.fenestrations.len());
+        for fen in self.distribution.fenestrations.iter() {
+            let (sky_v, beam) = if fen.outdoor_front {
+                (
+                    front_sky.get(fen.dc_row, 0)?,
+                    front_beam.get(fen.dc_row, 0)?,
+                )
+            } else {
+                (back_sky.get(fen.dc_row, 0)?, back_beam.get(fen.dc_row, 0)?)
+            };
+            // When the sun is calculated separately, the sky vector contains no sun
+            let total = if sky.sun.is_some() {
+                sky_v + beam
+            } else {
+                sky_v
+            };
+            let total = total.max(0.0);
+            incident.push((total, beam.clamp(0.0, total)));
+        }
+
+        let sun_direction = self
+            .solar
+            .sun_position(Time::Standard(sample.date.day_of_year()));
+        self.distribution
+            .distribute(&incident, sun_direction, sample.weight, memory)
+    }
+}
+
+impl ErrorHandling for SolarModel {
+    fn module_name() -> &'static str {
+        MODULE_NAME
+    }
+}
+
+impl SimulationModel for SolarModel {
+    type OutputType = Self;
+    type OptionType = SolarOptions;
+    type AllocType = SolarModelMemory;
+
+    fn allocate_memory(&self) -> Result<Self::AllocType, String> {
+        let mut memory = SolarModelMemory::new(
+            self.n_surfaces,
+            self.n_fenestrations,
+            self.empty_solar_gains.clone(),
+        );
+        memory.workplane_illuminance = self
+            .optical_info
+            .workplanes
+            .iter()
+            .map(|w| vec![0.0; w.sensors.len()])
+            .collect();
+        memory.workplane_sun_illuminance = memory.workplane_illuminance.clone();
+        if self.options.lighting_control.is_some() {
+            memory.lighting_power_fraction = vec![1.0; self.optical_info.workplanes.len()];
+        }
+        memory.glare = vec![GlareResult::default(); self.optical_info.glare_views.len()];
+        Ok(memory)
+    }
+
+    /// Creates a new `SolarModel`. Its [`SolarModelOptions`] are read from the
+    /// options file next to the `optical_data_path` of the `options` (e.g.,
+    /// `optical_data.options.json` for `optical_data.json`), or are the
+    /// default ones if there is no such file.
+    fn new<M: Borrow<SimpleModel>>(
+        meta_options: &MetaOptions,
+        options: SolarOptions,
+        model: M,
+        state: &mut SimulationStateHeader,
+        n: usize,
+    ) -> Result<Self::OutputType, String> {
+        let model_options = SolarModelOptions::from_solar_options(&options)?;
+        Self::new_with_options(meta_options, options, model_options, model, state, n)
+    }
+
+    fn march<W: Weather, M: Borrow<SimpleModel>>(
+        &self,
+        date: Date,
+        weather: &W,
+        model: M,
+        state: &mut SimulationState,
+        alloc: &mut SolarModelMemory,
+    ) -> Result<(), String> {
+        let model = model.borrow();
+        // Handle the solar part
+
+        let weather_data = self.solar_weather_data(date, weather)?;
+
+        self.update_ir_radiation(date, weather, model, state, alloc)?;
+        let sky_samples = self.timestep_sky_samples(date, &weather_data, weather)?;
+        let sky = Self::timestep_sky_vectors(&sky_samples)?;
+        self.update_solar_radiation(&sky, model, state, self.trailing_average(alloc))?;
+        self.update_transmitted_solar(&sky_samples, alloc)?;
+        self.gains_aggregator
+            .aggregate(model, &self.distribution, state, alloc)?;
+        let visible_sky = if self.optical_info.workplanes.is_empty()
+            && self.optical_info.glare_views.is_empty()
+        {
+            None
+        } else {
+            self.timestep_visible_sky_vectors(date, &weather_data, weather)?
+        };
+        self.update_illuminance(&visible_sky, alloc)?;
+        self.update_lighting(model, state, alloc)?;
+        self.update_glare(&visible_sky, alloc)?;
+        alloc.marched = true;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::{
+        Albedo, DecompositionModel, GlareOptions, GroundTemperature, LightingControl,
+        LightingControlOptions, Viewpoint, WorkplaneOptions,
+    };
+    use schedule::ScheduleConstant;
+    use simple_model::{substance::Normal, Construction, Fenestration, Material, Surface};
+    use validate::assert_close;
+    use weather::SyntheticWeather;
+
+    #[test]
+    fn test_model_mf() {
+        // cleanup
+        let optical_data_path = "./tests/wall/optical_data.json";
+        let path = Path::new(optical_data_path);
+        if path.exists() {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        // Step 1: create the model, write the optical info data.
+        let meta_options = MetaOptions {
+            latitude: -33.,
+            longitude: 72.,
+            standard_meridian: 70.,
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
+        let mut solar_options = model.solar_options.clone().unwrap();
+        solar_options.set_optical_data_path(optical_data_path.to_string());
+
+        let light_model =
+            SolarModel::new(&meta_options, solar_options, &model, &mut state_header, 4).unwrap();
+        assert_eq!(light_model.solar_sky_discretization, 1); //this comes in the model
+
+        // Step 2: Run it again, with a different option
+        let meta_options = MetaOptions {
+            latitude: -33.,
+            longitude: 72.,
+            standard_meridian: 70.,
+            elevation: 0.0,
+        };
+        let mut solar_options = model.solar_options.clone().unwrap();
+        solar_options.set_solar_sky_discretization(2);
+        solar_options.set_optical_data_path(optical_data_path.to_string());
+        let light_model =
+            SolarModel::new(&meta_options, solar_options, &model, &mut state_header, 4).unwrap();
+        // The stored optical data was calculated with another MF, so it is recalculated
+        assert_eq!(light_model.solar_sky_discretization, 2);
+
+        // Step 3: Use the new optical data
+        let mut solar_options = model.solar_options.clone().unwrap();
+        solar_options.set_solar_sky_discretization(2);
+        solar_options.set_optical_data_path(optical_data_path.to_string());
+        let model_options = SolarModelOptions {
+            stale_optical_data: StaleOpticalData::Error,
+            ..SolarModelOptions::default()
+        };
+        let light_model = SolarModel::new_with_options(
+            &meta_options,
+            solar_options,
+            model_options.clone(),
+            &model,
+            &mut state_header,
+            4,
+        )
+        .unwrap();
+        assert_eq!(light_model.solar_sky_discretization, 2);
+
+        // Step 4: Changing the options again is an error, if requested
+        let mut solar_options = model.solar_options.clone().unwrap();
+        solar_options.set_optical_data_path(optical_data_path.to_string());
+        let err = SolarModel::new_with_options(
+            &meta_options,
+            solar_options,
+            model_options,
+            &model,
+            &mut state_header,
+            4,
+        )
+        .err()
+        .unwrap();
+        assert!(err.contains("cannot be used"), "{}", err);
 
-    #[test]
-    fn test_model_mf() {
         // cleanup
-        let optical_data_path = "./tests/wall/optical_data.json";
         let path = Path::new(optical_data_path);
         if path.exists() {
             std::fs::remove_file(path).unwrap();
         }
+    }
+
+    #[test]
+    fn test_skip_ambient_boundary() {
+        // check that surfaces that do not receive sun are ignored
+        let mut model = SimpleModel::default();
+
+        let substance = Normal::new("the substance");
+        model.add_substance(substance.wrap());
+
+        let material = Material::new("the material", "the substance", 0.1);
+        model.add_material(material);
+
+        let mut construction = Construction::new("the construction");
+        construction.materials.push("the material".into());
+        model.add_construction(construction);
+
+        let s: Surface = json5::from_str(
+            "{
+            name: 'the surface',
+            construction:'the construction',
+            vertices: [
+                0, 0, 0, // X, Y and Z of Vertex 0
+                1, 0, 0, // X, Y and Z of Vertex 1
+                1, 1, 0, // X, Y and Z of Vertex 2
+                0, 1, 0  // ...
+            ],
+            front_boundary: {
+                type : 'AmbientTemperature',
+                temperature: 2.0
+            }
+         }",
+        )
+        .unwrap();        
+        model.add_surface(s);
+
+        let s: Surface = json5::from_str(
+            "{
+            name: 'the surface 2',
+            construction:'the construction',
+            vertices: [
+                0, 0, 10, // X, Y and Z of Vertex 0
+                1, 0, 10, // X, Y and Z of Vertex 1
+                1, 1, 10, // X, Y and Z of Vertex 2
+                0, 1, 10  // ...
+            ]
+         }",
+        )
+        .unwrap();
+        model.add_surface(s);
+
+        let fen: Fenestration = json5::from_str(
+            "{
+            name: 'Window 1',
+            construction: 'the construction',
+            vertices: [
+                0.548000,0,2.5000,  // X,Y,Z ==> Vertex 1 {m}
+                0.548000,0,0.5000,  // X,Y,Z ==> Vertex 2 {m}
+                5.548000,0,0.5000,  // X,Y,Z ==> Vertex 3 {m}
+                5.548000,0,2.5000,   // X,Y,Z ==> Vertex 4 {m}
+            ]
+        }",
+        )
+        .unwrap();
+        model.add_fenestration(fen).unwrap();
+
+        let fen: Fenestration = json5::from_str(
+            "{
+            name: 'Window 2',
+            construction: 'the construction',
+            vertices: [
+                0.548000,10,2.5000,  // X,Y,Z ==> Vertex 1 {m}
+                0.548000,10,0.5000,  // X,Y,Z ==> Vertex 2 {m}
+                5.548000,10,0.5000,  // X,Y,Z ==> Vertex 3 {m}
+                5.548000,10,2.5000,   // X,Y,Z ==> Vertex 4 {m}
+            ],
+            back_boundary: {
+                type:'AmbientTemperature',
+                temperature: 2.0
+            }
+        }",
+        )
+        .unwrap();
+        
+        model.add_fenestration(fen).unwrap();
+
+        let meta_options = MetaOptions {
+            latitude: (-41.3 as Float).to_radians(),
+            longitude: (174.78 as Float).to_radians(),
+            standard_meridian: (180. as Float).to_radians(),
+            elevation: 0.0,
+        };
+
+        let mut state_header = SimulationStateHeader::new();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(1);
+        options.set_solar_ambient_divitions(1);
+        options.set_solar_sky_discretization(1);
+
+        let n: usize = 1;
+
+        let solar_model =
+            SolarModel::new(&meta_options, options, &model, &mut state_header, n).unwrap();
+
+        let mut weather = SyntheticWeather::default();
+        weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(24.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(400.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(200.));
+
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = solar_model.allocate_memory().unwrap();
+        solar_model
+            .march(
+                Date {
+                    month: 1,
+                    day: 1,
+                    hour: 12.,
+                },
+                &weather,
+                &model,
+                &mut state,
+                &mut memory,
+            )
+            .unwrap();
+
+        // This surface should receive NO sun at the front but yes at the back
+        assert!(
+            model.surfaces[0]
+                .front_incident_solar_irradiance(&state)
+                .unwrap()
+                .abs()
+                < 1e-9
+        );
+        assert!(
+            model.surfaces[0]
+                .back_incident_solar_irradiance(&state)
+                .unwrap()
+                > 50.
+        );
+
+        // This surface should receive sun on both sides
+        assert!(
+            model.surfaces[1]
+                .front_incident_solar_irradiance(&state)
+                .unwrap()
+                .abs()
+                > 50.
+        );
+        assert!(
+            model.surfaces[1]
+                .back_incident_solar_irradiance(&state)
+                .unwrap()
+                > 50.
+        );
+
+        // This surface should receive sun on both sides
+        assert!(
+            model.fenestrations[0]
+                .front_incident_solar_irradiance(&state)
+                .unwrap()
+                > 50.
+        );
+        assert!(
+            model.fenestrations[0]
+                .back_incident_solar_irradiance(&state)
+                .unwrap()
+                > 50.
+        );
+
+        // This surface should receive NO sun at the back but yes at the front
+        assert!(
+            model.fenestrations[1]
+                .front_incident_solar_irradiance(&state)
+                .unwrap()
+                > 50.
+        );
+        assert!(
+            model.fenestrations[1]
+                .back_incident_solar_irradiance(&state)
+                .unwrap()
+                .abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn test_albedo_option() {
+        fn front_irradiance(albedo: Albedo) -> Float {
+            let meta_options = MetaOptions {
+                latitude: (-41.3 as Float).to_radians(),
+                longitude: (174.78 as Float).to_radians(),
+                standard_meridian: (180. as Float).to_radians(),
+                elevation: 0.0,
+            };
+            let (model, mut state_header) =
+                SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
+            let mut options = SolarOptions::new();
+            options.set_n_solar_irradiance_points(10);
+            options.set_solar_ambient_divitions(3000);
+            options.set_solar_sky_discretization(1);
+            let model_options = SolarModelOptions {
+                albedo,
+                ..SolarModelOptions::default()
+            };
+
+            let solar_model = SolarModel::new_with_options(
+                &meta_options,
+                options,
+                model_options,
+                &model,
+                &mut state_header,
+                1,
+            )
+            .unwrap();
+
+            let mut weather = SyntheticWeather::default();
+            weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+            weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(24.));
+            weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+            weather.direct_normal_radiation = Box::new(ScheduleConstant::new(0.));
+            weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(200.));
+
+            let mut state = state_header.take_values().unwrap();
+            let mut memory = solar_model.allocate_memory().unwrap();
+            solar_model
+                .march(
+                    Date {
+                        month: 1,
+                        day: 1,
+                        hour: 12.,
+                    },
+                    &weather,
+                    &model,
+                    &mut state,
+                    &mut memory,
+                )
+                .unwrap();
+            model.surfaces[0]
+                .front_incident_solar_irradiance(&state)
+                .unwrap()
+        }
+
+        // A vertical wall sees half ground... so a brighter ground means more radiation
+        let dark = front_irradiance(Albedo::Constant { value: 0.0 });
+        let snow = front_irradiance(Albedo::Constant { value: 0.8 });
+        assert!(snow > dark, "snow = {}, dark = {}", snow, dark);
+    }
+
+    #[test]
+    fn test_options_file() {
+        fn front_irradiance(albedo: Albedo) -> Float {
+            let meta_options = MetaOptions {
+                latitude: (-41.3 as Float).to_radians(),
+                longitude: (174.78 as Float).to_radians(),
+                standard_meridian: (180. as Float).to_radians(),
+                elevation: 0.0,
+            };
+            let (model, mut state_header) =
+                SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
+
+            // The options go next to the optical data
+            let optical_data_path = std::env::temp_dir().join(format!(
+                "light_options_file_{}.json",
+                match albedo {
+                    Albedo::Constant { value } => value,
+                    _ => unreachable!(),
+                }
+            ));
+            let optical_data_path = optical_data_path.to_string_lossy().to_string();
+            let options_path = SolarModelOptions::options_path(&optical_data_path);
+            let model_options = SolarModelOptions {
+                albedo: albedo.clone(),
+                ..SolarModelOptions::default()
+            };
+            std::fs::write(
+                &options_path,
+                serde_json::to_string(&model_options).unwrap(),
+            )
+            .unwrap();
+
+            let mut options = model.solar_options.clone().unwrap();
+            options.set_optical_data_path(optical_data_path.clone());
+            let solar_model =
+                SolarModel::new(&meta_options, options, &model, &mut state_header, 1).unwrap();
+            std::fs::remove_file(&options_path).unwrap();
+            std::fs::remove_file(&optical_data_path).unwrap();
+            assert_eq!(solar_model.options.albedo, albedo);
+
+            let mut weather = SyntheticWeather::default();
+            weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+            weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(24.));
+            weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+            weather.direct_normal_radiation = Box::new(ScheduleConstant::new(0.));
+            weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(200.));
+
+            let mut state = state_header.take_values().unwrap();
+            let mut memory = solar_model.allocate_memory().unwrap();
+            solar_model
+                .march(
+                    Date {
+                        month: 1,
+                        day: 1,
+                        hour: 12.,
+                    },
+                    &weather,
+                    &model,
+                    &mut state,
+                    &mut memory,
+                )
+                .unwrap();
+            model.surfaces[0]
+                .front_incident_solar_irradiance(&state)
+                .unwrap()
+        }
+
+        // The albedo set in the options file is used
+        let dark = front_irradiance(Albedo::Constant { value: 0.0 });
+        let snow = front_irradiance(Albedo::Constant { value: 0.8 });
+        assert!(snow > dark, "snow = {}, dark = {}", snow, dark);
+    }
+
+    #[test]
+    fn test_stored_options_follow_the_run() {
+        let meta_options = MetaOptions {
+            latitude: (-41.3 as Float).to_radians(),
+            longitude: (174.78 as Float).to_radians(),
+            standard_meridian: (180. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
+        let optical_data_path = std::env::temp_dir().join("light_stored_options.json");
+        let optical_data_path = optical_data_path.to_string_lossy().to_string();
+        if Path::new(&optical_data_path).exists() {
+            std::fs::remove_file(&optical_data_path).unwrap();
+        }
+        let mut options = model.solar_options.clone().unwrap();
+        options.set_optical_data_path(optical_data_path.clone());
+
+        let mut build = |albedo: Float| -> SolarModel {
+            let model_options = SolarModelOptions {
+                albedo: Albedo::Constant { value: albedo },
+                ..SolarModelOptions::default()
+            };
+            SolarModel::new_with_options(
+                &meta_options,
+                options.clone(),
+                model_options,
+                &model,
+                &mut state_header,
+                1,
+            )
+            .unwrap()
+        };
+        let first = build(0.2);
+        let second = build(0.5);
+
+        // The albedo does not make the optical data stale...
+        assert_eq!(
+            first.optical_info.front_surfaces_dc.get(0, 0).unwrap(),
+            second.optical_info.front_surfaces_dc.get(0, 0).unwrap()
+        );
+
+        // ... but the stored options are those of the last run
+        let expected = Albedo::Constant { value: 0.5 };
+        assert_eq!(second.optical_info.options.albedo, expected);
+        let stored = OpticalInfo::from_file(Path::new(&optical_data_path)).unwrap();
+        assert_eq!(stored.options.albedo, expected);
+        std::fs::remove_file(&optical_data_path).unwrap();
+    }
+
+    #[test]
+    fn test_ir_exchange() {
+        let view_factors = IRViewFactorSet {
+            sky: 0.5,
+            ground: 0.5,
+            air: 0.0,
+            obstructions: Vec::new(),
+            standard_error: 0.0,
+        };
+        let env = IREnvironment {
+            dry_bulb: 10.,
+            horizontal_ir: 300.,
+            ground_temperature: 5.,
+            surface_temperatures: vec![(40., 35.)],
+            fenestration_temperatures: Vec::new(),
+        };
+        let space = Boundary::Space {
+            space: "some space".into(),
+        };
+        let ambient = Boundary::AmbientTemperature { temperature: 30. };
+
+        // Spaces exchange nothing
+        let (incident, net) = SolarModel::ir_exchange(&space, 20., 0.9, &view_factors, &env);
+        assert_close!(incident, black_body(20.));
+        assert_close!(net, 0.0);
+
+        // Black bodies receive the emission of the ambient
+        let (incident, net) = SolarModel::ir_exchange(&ambient, 20., 1.0, &view_factors, &env);
+        assert_close!(incident, black_body(30.));
+        assert_close!(net, black_body(30.) - black_body(20.));
+
+        // Gray bodies exchange less
+        let emissivity = 0.8;
+        let (_, net) = SolarModel::ir_exchange(&ambient, 20., emissivity, &view_factors, &env);
+        let expected = (black_body(30.) - black_body(20.)) / (2. / emissivity - 1.);
+        assert_close!(net, expected);
+
+        // Outdoors, the incident radiation does not depend on the emissivity...
+        // the net exchange does.
+        let (incident, net) =
+            SolarModel::ir_exchange(&Boundary::Outdoor, 20., emissivity, &view_factors, &env);
+        assert_close!(incident, 0.5 * 300. + 0.5 * black_body(10.));
+        assert_close!(net, emissivity * (incident - black_body(20.)));
+
+        // The ground is a black body at ground temperature
+        let (incident, net) =
+            SolarModel::ir_exchange(&Boundary::Ground, 20., emissivity, &view_factors, &env);
+        assert_close!(incident, black_body(5.));
+        assert!(net < 0.0);
+
+        // Obstructions contribute at their own temperature
+        let obstructed = IRViewFactorSet {
+            sky: 0.25,
+            ground: 0.5,
+            air: 0.0,
+            obstructions: vec![(
+                SpaceSide {
+                    kind: SpaceSideKind::Surface,
+                    index: 0,
+                    front: false,
+                },
+                0.25,
+            )],
+            standard_error: 0.0,
+        };
+        let (incident, _) =
+            SolarModel::ir_exchange(&Boundary::Outdoor, 20., emissivity, &obstructed, &env);
+        assert_close!(
+            incident,
+            0.25 * 300. + 0.5 * black_body(10.) + 0.25 * black_body(35.)
+        );
+    }
+
+    #[test]
+    fn test_ground_boundary() {
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (-2.18 as Float).to_radians(),
+            standard_meridian: (-15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(1);
+        options.set_solar_ambient_divitions(100);
+        options.set_solar_sky_discretization(1);
+        // A single timestep, so the irradiance is not averaged with the initial state
+        let model_options = SolarModelOptions {
+            ground_temperature: GroundTemperature::Constant { value: 8. },
+            timestep_averaging: TimestepAveraging::Instantaneous,
+            ..SolarModelOptions::default()
+        };
+        let solar_model = SolarModel::new_with_options(
+            &meta_options,
+            options,
+            model_options,
+            &model,
+            &mut state_header,
+            1,
+        )
+        .unwrap();
+
+        let mut weather = SyntheticWeather::default();
+        weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(25.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(800.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(100.));
+
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = solar_model.allocate_memory().unwrap();
+        solar_model
+            .march(
+                Date {
+                    month: 1,
+                    day: 15,
+                    hour: 12.5,
+                },
+                &weather,
+                &model,
+                &mut state,
+                &mut memory,
+            )
+            .unwrap();
+
+        // The front of the floor touches the ground
+        let floor = model.surfaces.iter().find(|s| s.name == "floor").unwrap();
+        assert_close!(floor.front_ir_irradiance(&state).unwrap(), black_body(8.));
+        assert_close!(floor.front_incident_solar_irradiance(&state).unwrap(), 0.0);
+
+        // And the walls see the outdoors
+        let wall = model
+            .surfaces
+            .iter()
+            .find(|s| s.name == "south_wall")
+            .unwrap();
+        assert!(wall.front_ir_irradiance(&state).unwrap() > 0.0);
+        assert!(wall.front_incident_solar_irradiance(&state).unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_emissivities() {
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (-2.18 as Float).to_radians(),
+            standard_meridian: (-15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(1);
+        options.set_solar_ambient_divitions(100);
+        options.set_solar_sky_discretization(1);
+        let solar_model =
+            SolarModel::new(&meta_options, options, &model, &mut state_header, 1).unwrap();
+
+        // Concrete walls and glass windows
+        assert_close!(solar_model.surface_emissivity(0, true).unwrap(), 0.9);
+        assert_close!(solar_model.surface_emissivity(0, false).unwrap(), 0.9);
+        assert_close!(solar_model.fenestration_emissivity(0, true).unwrap(), 0.84);
+        assert!(solar_model.surface_emissivity(100, true).is_none());
+    }
+
+    #[test]
+    fn test_transmitted_solar() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (-2.18 as Float).to_radians(),
+            standard_meridian: (-15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(10);
+        options.set_solar_ambient_divitions(3000);
+        options.set_solar_sky_discretization(1);
+
+        let solar_model =
+            SolarModel::new(&meta_options, options, &model, &mut state_header, 1).unwrap();
+
+        let mut weather = SyntheticWeather::default();
+        weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(12.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(800.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(100.));
+
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = solar_model.allocate_memory().unwrap();
+        solar_model
+            .march(
+                Date {
+                    month: 1,
+                    day: 15,
+                    hour: 12.5,
+                },
+                &weather,
+                &model,
+                &mut state,
+                &mut memory,
+            )
+            .unwrap();
+
+        // The window faces south... it should let the sun in during a winter noon
+        assert!(memory.fenestration_transmitted_beam[0] > 0.0);
+        assert!(memory.fenestration_transmitted_diffuse[0] > 0.0);
+
+        // And the sun should land on the floor (the back side faces the room)
+        let floor = model
+            .surfaces
+            .iter()
+            .position(|s| s.name == "floor")
+            .unwrap();
+        assert!(memory.surface_back_transmitted_solar[floor] > 0.0);
+        assert_eq!(memory.surface_front_transmitted_solar[floor], 0.0);
+    }
+
+    #[test]
+    fn test_transmitted_solar_samples() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (2.18 as Float).to_radians(),
+            standard_meridian: (15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(1);
+        options.set_solar_ambient_divitions(300);
+        options.set_solar_sky_discretization(1);
+
+        let models: Vec<SolarModel> = [
+            TimestepAveraging::Instantaneous,
+            TimestepAveraging::IntervalStart,
+        ]
+        .iter()
+        .map(|timestep_averaging| {
+            let model_options = SolarModelOptions {
+                timestep_averaging: *timestep_averaging,
+                ..SolarModelOptions::default()
+            };
+            SolarModel::new_with_options(
+                &meta_options,
+                options.clone(),
+                model_options,
+                &model,
+                &mut state_header,
+                1,
+            )
+            .unwrap()
+        })
+        .collect();
+
+        let mut weather = SyntheticWeather::default();
+        weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(12.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(800.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(100.));
+
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = models[0].allocate_memory().unwrap();
+        let date = |hour: Float| Date {
+            month: 1,
+            day: 15,
+            hour,
+        };
+
+        // The sun is low in the south-west at 17:00... and has set by 18:00
+        models[0]
+            .march(date(17.), &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        let beam = memory.fenestration_transmitted_beam[0];
+        let back = memory.surface_back_transmitted_solar.clone();
+        assert!(beam > 0.0);
+        models[0]
+            .march(date(18.), &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        assert_eq!(memory.fenestration_transmitted_beam[0], 0.0);
+
+        // A timestep that ends at 18:00 but represents 17:00 lets the sun in
+        models[1]
+            .march(date(18.), &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        assert_close!(memory.fenestration_transmitted_beam[0], beam, 1e-3);
+        let found = &memory.surface_back_transmitted_solar;
+        for (found, exp) in found.iter().zip(back.iter()) {
+            assert_close!(*found, *exp, 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_workplane_illuminance() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (-2.18 as Float).to_radians(),
+            standard_meridian: (-15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(10);
+        options.set_solar_ambient_divitions(1000);
+        options.set_solar_sky_discretization(1);
+        let model_options = SolarModelOptions {
+            workplanes: Some(WorkplaneOptions {
+                spacing: 1.0,
+                max_depth: 1,
+                ..WorkplaneOptions::default()
+            }),
+            ..SolarModelOptions::default()
+        };
+
+        let solar_model = SolarModel::new_with_options(
+            &meta_options,
+            options,
+            model_options,
+            &model,
+            &mut state_header,
+            1,
+        )
+        .unwrap();
+        assert_eq!(solar_model.workplanes().len(), 1);
+        assert_eq!(solar_model.workplanes()[0].sensors.len(), 16);
+
+        let mut weather = SyntheticWeather::default();
+        weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(12.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(800.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(100.));
+
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = solar_model.allocate_memory().unwrap();
+        assert_eq!(memory.workplane_illuminance[0].len(), 16);
+        let noon = Date {
+            month: 1,
+            day: 15,
+            hour: 12.5,
+        };
+        solar_model
+            .march(noon, &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        assert!(memory.workplane_illuminance[0].iter().all(|e| *e > 0.0));
+
+        // No light at night
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(0.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(0.));
+        let night = Date {
+            month: 1,
+            day: 15,
+            hour: 23.5,
+        };
+        solar_model
+            .march(night, &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        assert!(memory.workplane_illuminance[0].iter().all(|e| *e == 0.0));
+    }
+
+    #[test]
+    fn test_daylight_metrics() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (-2.18 as Float).to_radians(),
+            standard_meridian: (-15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(10);
+        options.set_solar_ambient_divitions(300);
+        options.set_solar_sky_discretization(1);
+        let weather = weather::EPWWeather::from_file("./tests/barcelona.epw").unwrap();
+
+        // No workplanes, no metrics
+        let solar_model =
+            SolarModel::new(&meta_options, options.clone(), &model, &mut state_header, 1).unwrap();
+        assert!(solar_model
+            .daylight_metrics(&weather, DaylightMetricsOptions::default())
+            .is_err());
+
+        let mut model_options = SolarModelOptions {
+            workplanes: Some(WorkplaneOptions {
+                spacing: 1.0,
+                max_depth: 1,
+                ..WorkplaneOptions::default()
+            }),
+            ..SolarModelOptions::default()
+        };
+
+        // The Annual Sunlight Exposure needs a fine sky for the sun, but
+        // the other metrics do not
+        let solar_model = SolarModel::new_with_options(
+            &meta_options,
+            options.clone(),
+            model_options.clone(),
+            &model,
+            &mut state_header,
+            1,
+        )
+        .unwrap();
+        let coarse = solar_model
+            .daylight_metrics(&weather, DaylightMetricsOptions::default())
+            .unwrap();
+        let room = coarse.get("the room").unwrap();
+        assert_eq!(room.daylight_autonomy.len(), 16);
+        assert!(room.annual_sunlight_exposure.is_none());
+        assert!(room.sunlit_hours.is_empty());
+
+        model_options.direct_sun_sky_discretization = Some(2);
+        let solar_model = SolarModel::new_with_options(
+            &meta_options,
+            options,
+            model_options,
+            &model,
+            &mut state_header,
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            solar_model.optical_info.workplanes_sun_dc[0].size().1,
+            solar::ReinhartSky::n_bins(2)
+        );
+        let report = solar_model
+            .daylight_metrics(&weather, DaylightMetricsOptions::default())
+            .unwrap();
+
+        // From 8 to 18, every day
+        assert_close!(report.occupied_hours, 3650.);
+        let room = report.get("the room").unwrap();
+        assert_eq!(room.daylight_autonomy.len(), 16);
+        for i in 0..16 {
+            assert!(room.daylight_autonomy[i] > 0.0 && room.daylight_autonomy[i] <= 1.0);
+            assert_close!(
+                room.udi_fell_short[i] + room.udi_useful[i] + room.udi_exceeded[i],
+                1.0,
+                1e-4
+            );
+        }
+        assert!((0.0..=1.0).contains(&room.spatial_daylight_autonomy));
+        assert!((0.0..=1.0).contains(&room.annual_sunlight_exposure.unwrap()));
+        assert_eq!(room.sunlit_hours.len(), 16);
+    }
+
+    #[test]
+    fn test_lighting_control() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (-2.18 as Float).to_radians(),
+            standard_meridian: (-15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(10);
+        options.set_solar_ambient_divitions(300);
+        options.set_solar_sky_discretization(1);
+        let lighting_control = LightingControlOptions {
+            setpoint: 1.,
+            control: LightingControl::OnOff,
+            ..LightingControlOptions::default()
+        };
+
+        // Lighting control needs workplanes
+        let model_options = SolarModelOptions {
+            lighting_control: Some(lighting_control.clone()),
+            ..SolarModelOptions::default()
+        };
+        assert!(SolarModel::new_with_options(
+            &meta_options,
+            options.clone(),
+            model_options,
+            &model,
+            &mut state_header,
+            1,
+        )
+        .is_err());
+
+        let model_options = SolarModelOptions {
+            workplanes: Some(WorkplaneOptions {
+                spacing: 1.0,
+                max_depth: 1,
+                ..WorkplaneOptions::default()
+            }),
+            lighting_control: Some(lighting_control),
+            ..SolarModelOptions::default()
+        };
+        let solar_model = SolarModel::new_with_options(
+            &meta_options,
+            options,
+            model_options,
+            &model,
+            &mut state_header,
+            1,
+        )
+        .unwrap();
+
+        let mut weather = SyntheticWeather::default();
+        weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(12.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(800.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(100.));
+
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = solar_model.allocate_memory().unwrap();
+        let noon = Date {
+            month: 1,
+            day: 15,
+            hour: 12.5,
+        };
+        solar_model
+            .march(noon, &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        // Daylight is enough at noon...
+        assert_eq!(memory.lighting_power_fraction, vec![0.0]);
+
+        // ... but not at night
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(0.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(0.));
+        let night = Date {
+            month: 1,
+            day: 15,
+            hour: 23.5,
+        };
+        solar_model
+            .march(night, &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        assert_eq!(memory.lighting_power_fraction, vec![1.0]);
+    }
+
+    #[test]
+    fn test_glare() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (-2.18 as Float).to_radians(),
+            standard_meridian: (-15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(10);
+        options.set_solar_ambient_divitions(300);
+        options.set_solar_sky_discretization(1);
+        let model_options = SolarModelOptions {
+            glare: Some(GlareOptions {
+                viewpoints: vec![Viewpoint {
+                    name: "desk".into(),
+                    position: geometry3d::Point3D::new(2., 2., 1.2),
+                    direction: geometry3d::Vector3D::new(0., -1., 0.),
+                }],
+                resolution: 40,
+                max_depth: 1,
+                ..GlareOptions::default()
+            }),
+            ..SolarModelOptions::default()
+        };
+        let solar_model = SolarModel::new_with_options(
+            &meta_options,
+            options,
+            model_options,
+            &model,
+            &mut state_header,
+            1,
+        )
+        .unwrap();
+
+        let mut weather = SyntheticWeather::default();
+        weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(12.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(800.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(100.));
+
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = solar_model.allocate_memory().unwrap();
+        assert_eq!(memory.glare.len(), 1);
+        let noon = Date {
+            month: 1,
+            day: 15,
+            hour: 12.5,
+        };
+        solar_model
+            .march(noon, &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        // Looking at a sunny window
+        let glare = memory.glare[0];
+        assert!(glare.vertical_illuminance > 0.0);
+        assert!(glare.dgps > 0.184);
+        assert!(glare.dgp > 0.0 && glare.dgp <= 1.0);
+
+        // No glare at night
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(0.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(0.));
+        let night = Date {
+            month: 1,
+            day: 15,
+            hour: 23.5,
+        };
+        solar_model
+            .march(night, &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        assert_eq!(memory.glare[0], GlareResult::default());
+    }
+
+    /// A weather with a constant diffuse horizontal irradiance and a direct
+    /// normal irradiance that grows with the hour of the day, except for some
+    /// hours that lack solar data
+    struct GappyWeather {
+        inner: SyntheticWeather,
+        gaps: Vec<Float>,
+        global_horizontal: Option<Float>,
+    }
+
+    impl Weather for GappyWeather {
+        fn get_weather_data(&self, date: Date) -> CurrentWeather {
+            let mut data = self.inner.get_weather_data(date);
+            data.direct_normal_radiation = Some(10. * date.hour);
+            data.global_horizontal_radiation = self.global_horizontal;
+            if self.gaps.iter().any(|h| (h - date.hour).abs() < 1e-3) {
+                data.direct_normal_radiation = None;
+                data.diffuse_horizontal_radiation = None;
+            }
+            data
+        }
+    }
+
+    #[test]
+    fn test_missing_solar_data() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (-2.18 as Float).to_radians(),
+            standard_meridian: (-15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
+        let options = model.solar_options.clone().unwrap();
+
+        let mut inner = SyntheticWeather::default();
+        inner.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        inner.dry_bulb_temperature = Box::new(ScheduleConstant::new(12.));
+        inner.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        inner.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(100.));
+        let mut weather = GappyWeather {
+            inner,
+            gaps: vec![12.5],
+            global_horizontal: Some(500.),
+        };
+        let noon = Date {
+            month: 6,
+            day: 21,
+            hour: 12.5,
+        };
+
+        let mut solar_model = |missing_solar_data: MissingSolarData| {
+            let model_options = SolarModelOptions {
+                missing_solar_data,
+                ..SolarModelOptions::default()
+            };
+            SolarModel::new_with_options(
+                &meta_options,
+                options.clone(),
+                model_options,
+                &model,
+                &mut state_header,
+                1,
+            )
+            .unwrap()
+        };
+
+        // Data that is there is not touched
+        let m = solar_model(MissingSolarData::Error);
+        let mut morning = noon;
+        morning.hour = 9.5;
+        let data = m.solar_weather_data(morning, &weather).unwrap();
+        assert_close!(data.direct_normal_radiation.unwrap(), 95.);
+        assert_close!(data.diffuse_horizontal_radiation.unwrap(), 100.);
+
+        // Error, reporting the date
+        let err = m.solar_weather_data(noon, &weather).unwrap_err();
+        assert!(err.contains("month 6, day 21, hour 12.50"), "{}", err);
+
+        // Zero
+        let m = solar_model(MissingSolarData::Zero);
+        let data = m.solar_weather_data(noon, &weather).unwrap();
+        assert_close!(data.direct_normal_radiation.unwrap(), 0.);
+        assert_close!(data.diffuse_horizontal_radiation.unwrap(), 0.);
+
+        // Interpolate
+        let m = solar_model(MissingSolarData::Interpolate { max_hours: 2 });
+        let data = m.solar_weather_data(noon, &weather).unwrap();
+        assert_close!(data.direct_normal_radiation.unwrap(), 125.);
+        assert_close!(data.diffuse_horizontal_radiation.unwrap(), 100.);
+
+        // ... also across several hours
+        weather.gaps = vec![11.5, 12.5];
+        let data = m.solar_weather_data(noon, &weather).unwrap();
+        assert_close!(data.direct_normal_radiation.unwrap(), 125.);
+
+        // ... unless the gap is too long
+        let m = solar_model(MissingSolarData::Interpolate { max_hours: 1 });
+        let err = m.solar_weather_data(noon, &weather).unwrap_err();
+        assert!(err.contains("Could not interpolate"), "{}", err);
+
+        // Decompose
+        weather.gaps = vec![12.5];
+        let m = solar_model(MissingSolarData::Decompose {
+            model: DecompositionModel::Erbs,
+        });
+        let data = m.solar_weather_data(noon, &weather).unwrap();
+        let cos_zenith = m
+            .solar
+            .sun_position(Time::Standard(noon.day_of_year()))
+            .unwrap()
+            .z;
+        let direct_normal = data.direct_normal_radiation.unwrap();
+        let diffuse_horizontal = data.diffuse_horizontal_radiation.unwrap();
+        assert!(direct_normal > 0.0);
+        assert_close!(direct_normal * cos_zenith + diffuse_horizontal, 500., 1e-2);
+
+        // ... which needs the global horizontal irradiance
+        weather.global_horizontal = None;
+        let err = m.solar_weather_data(noon, &weather).unwrap_err();
+        assert!(err.contains("global horizontal"), "{}", err);
+
+        // Marching through a gap does not panic
+        weather.global_horizontal = Some(500.);
+        let strict = solar_model(MissingSolarData::Error);
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = m.allocate_memory().unwrap();
+        m.march(noon, &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        assert!(strict
+            .march(noon, &weather, &model, &mut state, &mut memory)
+            .is_err());
+    }
+
+    #[test]
+    fn test_decomposition() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (2.18 as Float).to_radians(),
+            standard_meridian: (15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
+        let options = model.solar_options.clone().unwrap();
+
+        let mut inner = SyntheticWeather::default();
+        inner.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        inner.dry_bulb_temperature = Box::new(ScheduleConstant::new(24.));
+        inner.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        inner.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(100.));
+        let mut weather = GappyWeather {
+            inner,
+            gaps: vec![],
+            global_horizontal: Some(500.),
+        };
+        let date = Date {
+            month: 6,
+            day: 21,
+            hour: 9.5,
+        };
+
+        let mut direct = Vec::new();
+        for decomposition in [
+            DecompositionModel::Erbs,
+            DecompositionModel::Reindl,
+            DecompositionModel::BolandRidleyLauret,
+            DecompositionModel::Disc,
+        ] {
+            let model_options = SolarModelOptions {
+                decomposition: Some(decomposition),
+                ..SolarModelOptions::default()
+            };
+            let m = SolarModel::new_with_options(
+                &meta_options,
+                options.clone(),
+                model_options,
+                &model,
+                &mut state_header,
+                1,
+            )
+            .unwrap();
+
+            // The irradiance in the weather data is replaced
+            let data = m.solar_weather_data(date, &weather).unwrap();
+            let direct_normal = data.direct_normal_radiation.unwrap();
+            let diffuse_horizontal = data.diffuse_horizontal_radiation.unwrap();
+            assert!((direct_normal - 95.).abs() > 1.);
+            assert_close!(
+                direct_normal * m.cos_zenith(date) + diffuse_horizontal,
+                500.,
+                1e-2
+            );
+            direct.push(direct_normal);
+
+            // Which needs the global horizontal irradiance
+            weather.global_horizontal = None;
+            let err = m.solar_weather_data(date, &weather).unwrap_err();
+            assert!(err.contains("global horizontal"), "{}", err);
+            weather.global_horizontal = Some(500.);
+        }
 
-        // Step 1: create the model, write the optical info data.
+        // The models do not agree exactly
+        assert!(direct.windows(2).any(|w| (w[0] - w[1]).abs() > 1.));
+    }
+
+    /// A clear weather, with solar radiation between 7:00 and 17:00 and a
+    /// temperature that grows with the hour of the day
+    struct DaytimeWeather {
+        inner: SyntheticWeather,
+    }
+
+    impl Weather for DaytimeWeather {
+        fn get_weather_data(&self, date: Date) -> CurrentWeather {
+            let mut data = self.inner.get_weather_data(date);
+            let (direct_normal, diffuse_horizontal) = if (7.0..17.0).contains(&date.hour) {
+                (800., 100.)
+            } else {
+                (0., 0.)
+            };
+            data.direct_normal_radiation = Some(direct_normal);
+            data.diffuse_horizontal_radiation = Some(diffuse_horizontal);
+            // It gets warmer during the day, so the IR changes as well
+            data.dry_bulb_temperature = Some(10. + date.hour);
+            data
+        }
+    }
+
+    #[test]
+    fn test_integrated_timesteps() {
+        // Barcelona
         let meta_options = MetaOptions {
-            latitude: -33.,
-            longitude: 72.,
-            standard_meridian: 70.,
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (2.18 as Float).to_radians(),
+            standard_meridian: (15. as Float).to_radians(),
             elevation: 0.0,
         };
         let (model, mut state_header) = SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
-        let mut solar_options = model.solar_options.clone().unwrap();
-        solar_options.set_optical_data_path(optical_data_path.to_string());
+        let options = model.solar_options.clone().unwrap();
+        let mut inner = SyntheticWeather::default();
+        inner.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        inner.dry_bulb_temperature = Box::new(ScheduleConstant::new(24.));
+        inner.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        let weather = DaytimeWeather { inner };
 
-        let light_model =
-            SolarModel::new(&meta_options, solar_options, &model, &mut state_header, 4).unwrap();
-        assert_eq!(light_model.solar_sky_discretization, 1); //this comes in the model
+        // Builds the models first, as they register elements in the state
+        let runs: Vec<(usize, SolarModel)> = [(60, 1), (12, 1), (4, 3), (1, 12)]
+            .iter()
+            .map(|(n, samples)| {
+                let model_options = SolarModelOptions {
+                    timestep_averaging: TimestepAveraging::Integrated { samples: *samples },
+                    ..SolarModelOptions::default()
+                };
+                let m = SolarModel::new_with_options(
+                    &meta_options,
+                    options.clone(),
+                    model_options,
+                    &model,
+                    &mut state_header,
+                    *n,
+                )
+                .unwrap();
+                (*n, m)
+            })
+            .collect();
+        let mut state = state_header.take_values().unwrap();
 
-        // Step 2: Run it again, with a different option
+        // The solar radiation received during a whole day, in Wh/m2
+        let mut totals = Vec::with_capacity(runs.len());
+        for (n, m) in runs.iter() {
+            let mut memory = m.allocate_memory().unwrap();
+            let dt = 1. / *n as Float;
+            let mut date = Date {
+                month: 6,
+                day: 21,
+                hour: 0.0,
+            };
+            let mut total = 0.0;
+            for _ in 0..24 * n {
+                date.add_hours(dt);
+                m.march(date, &weather, &model, &mut state, &mut memory)
+                    .unwrap();
+                for s in model.surfaces.iter() {
+                    total += dt
+                        * (s.front_incident_solar_irradiance(&state).unwrap()
+                            + s.back_incident_solar_irradiance(&state).unwrap());
+                }
+            }
+            totals.push(total);
+        }
+        assert!(totals[0] > 0.0);
+        for (total, (n, _)) in totals.iter().zip(runs.iter()) {
+            let err = (total - totals[0]).abs() / totals[0];
+            assert!(
+                err < 0.01,
+                "{} timesteps per hour: {} vs {}",
+                n,
+                total,
+                totals[0]
+            );
+        }
+
+        // The number of timesteps must be sensible
+        assert!(SolarModel::new_with_options(
+            &meta_options,
+            options,
+            SolarModelOptions::default(),
+            &model,
+            &mut state_header,
+            0,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_timestep_averaging() {
+        // Barcelona
         let meta_options = MetaOptions {
-            latitude: -33.,
-            longitude: 72.,
-            standard_meridian: 70.,
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (2.18 as Float).to_radians(),
+            standard_meridian: (15. as Float).to_radians(),
             elevation: 0.0,
         };
-        let mut solar_options = model.solar_options.clone().unwrap();
-        solar_options.set_solar_sky_discretization(2);
-        solar_options.set_optical_data_path(optical_data_path.to_string());
-        let light_model =
-            SolarModel::new(&meta_options, solar_options, &model, &mut state_header, 4).unwrap();
-        assert_eq!(light_model.solar_sky_discretization, 1); //this comes from the optical data.
+        let (model, mut state_header) = SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
+        let options = model.solar_options.clone().unwrap();
+        let mut inner = SyntheticWeather::default();
+        inner.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        inner.dry_bulb_temperature = Box::new(ScheduleConstant::new(24.));
+        inner.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        let weather = DaytimeWeather { inner };
 
-        // cleanup
-        let path = Path::new(optical_data_path);
-        if path.exists() {
-            std::fs::remove_file(path).unwrap();
-        }
-    }
+        let modes = [
+            TimestepAveraging::Instantaneous,
+            TimestepAveraging::TrailingAverage,
+            TimestepAveraging::IntervalStart,
+            TimestepAveraging::IntervalCentred,
+            TimestepAveraging::StartEnd,
+            TimestepAveraging::Integrated { samples: 2 },
+        ];
+        let models: Vec<SolarModel> = modes
+            .iter()
+            .map(|timestep_averaging| {
+                let model_options = SolarModelOptions {
+                    timestep_averaging: *timestep_averaging,
+                    ..SolarModelOptions::default()
+                };
+                SolarModel::new_with_options(
+                    &meta_options,
+                    options.clone(),
+                    model_options,
+                    &model,
+                    &mut state_header,
+                    1,
+                )
+                .unwrap()
+            })
+            .collect();
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = models[0].allocate_memory().unwrap();
 
-    #[test]
-    fn test_skip_ambient_boundary() {
-        // check that surfaces that do not receive sun are ignored
-        let mut model = SimpleModel::default();
+        // Marches one hourly timestep, returning the solar and the outdoor IR
+        // irradiance received by the surfaces
+        let mut march = |m: &SolarModel, hour: Float| -> (Float, Float) {
+            let date = Date {
+                month: 6,
+                day: 21,
+                hour,
+            };
+            m.march(date, &weather, &model, &mut state, &mut memory)
+                .unwrap();
+            let (mut solar, mut ir) = (0.0, 0.0);
+            for s in model.surfaces.iter() {
+                solar += s.front_incident_solar_irradiance(&state).unwrap()
+                    + s.back_incident_solar_irradiance(&state).unwrap();
+                if matches!(s.front_boundary, Boundary::Outdoor) {
+                    ir += s.front_ir_irradiance(&state).unwrap();
+                }
+                if matches!(s.back_boundary, Boundary::Outdoor) {
+                    ir += s.back_ir_irradiance(&state).unwrap();
+                }
+            }
+            (solar, ir)
+        };
 
-        let substance = Normal::new("the substance");
-        model.add_substance(substance.wrap());
+        // Trailing average: the first timestep has no previous one (it is
+        // not averaged with zero), and the second one depends on it. This
+        // goes first, as the memory has not marched yet.
+        let first = march(&models[1], 8.);
+        let second = march(&models[1], 9.);
 
-        let material = Material::new("the material", "the substance", 0.1);
-        model.add_material(material);
+        // The values at some instants
+        let instantaneous = &models[0];
+        let at_8 = march(instantaneous, 8.);
+        let at_8_25 = march(instantaneous, 8.25);
+        let at_8_5 = march(instantaneous, 8.5);
+        let at_8_75 = march(instantaneous, 8.75);
+        let at_9 = march(instantaneous, 9.);
+        assert!(at_8.0 > 0.0 && at_8.1 > 0.0);
 
-        let mut construction = Construction::new("the construction");
-        construction.materials.push("the material".into());
-        model.add_construction(construction);
+        let check = |found: (Float, Float), exp: (Float, Float)| {
+            assert_close!(found.0, exp.0, 1e-2);
+            assert_close!(found.1, exp.1, 1e-2);
+        };
+        check(first, at_8);
+        check(second, ((at_9.0 + first.0) / 2., (at_9.1 + first.1) / 2.));
 
-        let s: Surface = json5::from_str(
-            "{
-            name: 'the surface',
-            construction:'the construction',
-            vertices: [
-                0, 0, 0, // X, Y and Z of Vertex 0
-                1, 0, 0, // X, Y and Z of Vertex 1
-                1, 1, 0, // X, Y and Z of Vertex 2
-                0, 1, 0  // ...
-            ],
-            front_boundary: {
-                type : 'AmbientTemperature',
-                temperature: 2.0
-            }
-         }",
-        )
-        .unwrap();        
-        model.add_surface(s);
+        // Instants within the timestep that ends at 9:00
+        check(march(&models[2], 9.), at_8);
+        check(march(&models[3], 9.), at_8_5);
+        check(
+            march(&models[4], 9.),
+            ((at_8.0 + at_9.0) / 2., (at_8.1 + at_9.1) / 2.),
+        );
+        check(
+            march(&models[5], 9.),
+            ((at_8_25.0 + at_8_75.0) / 2., (at_8_25.1 + at_8_75.1) / 2.),
+        );
 
-        let s: Surface = json5::from_str(
-            "{
-            name: 'the surface 2',
-            construction:'the construction',
-            vertices: [
-                0, 0, 10, // X, Y and Z of Vertex 0
-                1, 0, 10, // X, Y and Z of Vertex 1
-                1, 1, 10, // X, Y and Z of Vertex 2
-                0, 1, 10  // ...
-            ]
-         }",
-        )
-        .unwrap();
-        model.add_surface(s);
+        // Modes other than the trailing average do not depend on the previous call
+        check(march(&models[3], 9.), at_8_5);
+        check(march(&models[0], 9.), at_9);
+    }
 
-        let fen: Fenestration = json5::from_str(
-            "{
-            name: 'Window 1',
-            construction: 'the construction',
-            vertices: [
-                0.548000,0,2.5000,  // X,Y,Z ==> Vertex 1 {m}
-                0.548000,0,0.5000,  // X,Y,Z ==> Vertex 2 {m}
-                5.548000,0,0.5000,  // X,Y,Z ==> Vertex 3 {m}
-                5.548000,0,2.5000,   // X,Y,Z ==> Vertex 4 {m}
-            ]
-        }",
+    #[test]
+    fn test_night_resets_both_sides() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (2.18 as Float).to_radians(),
+            standard_meridian: (15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
+        let options = model.solar_options.clone().unwrap();
+        let model_options = SolarModelOptions {
+            timestep_averaging: TimestepAveraging::Instantaneous,
+            ..SolarModelOptions::default()
+        };
+        let solar_model = SolarModel::new_with_options(
+            &meta_options,
+            options,
+            model_options,
+            &model,
+            &mut state_header,
+            1,
         )
         .unwrap();
-        model.add_fenestration(fen).unwrap();
+        let mut inner = SyntheticWeather::default();
+        inner.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        inner.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        let weather = DaytimeWeather { inner };
 
-        let fen: Fenestration = json5::from_str(
-            "{
-            name: 'Window 2',
-            construction: 'the construction',
-            vertices: [
-                0.548000,10,2.5000,  // X,Y,Z ==> Vertex 1 {m}
-                0.548000,10,0.5000,  // X,Y,Z ==> Vertex 2 {m}
-                5.548000,10,0.5000,  // X,Y,Z ==> Vertex 3 {m}
-                5.548000,10,2.5000,   // X,Y,Z ==> Vertex 4 {m}
-            ],
-            back_boundary: {
-                type:'AmbientTemperature',
-                temperature: 2.0
-            }
-        }",
-        )
-        .unwrap();
-        
-        model.add_fenestration(fen).unwrap();
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = solar_model.allocate_memory().unwrap();
+        let mut march = |hour: Float| {
+            let date = Date {
+                month: 6,
+                day: 21,
+                hour,
+            };
+            solar_model
+                .march(date, &weather, &model, &mut state, &mut memory)
+                .unwrap();
+            let wall = &model.surfaces[0];
+            (
+                wall.front_incident_solar_irradiance(&state).unwrap(),
+                wall.back_incident_solar_irradiance(&state).unwrap(),
+            )
+        };
+
+        // Both sides of the wall see the sky during the day...
+        let (front, back) = march(12.);
+        assert!(front > 0.0 && back > 0.0, "front = {front}, back = {back}");
+
+        // ... and nothing at night. The back side used to keep its daytime
+        // value, because the night reset the front side twice.
+        let (front, back) = march(23.);
+        assert_eq!(front, 0.0);
+        assert_eq!(back, 0.0);
+    }
+
+    #[test]
+    fn test_timestep_averaging_interior() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (2.18 as Float).to_radians(),
+            standard_meridian: (15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(1);
+        options.set_solar_ambient_divitions(300);
+        options.set_solar_sky_discretization(1);
+        let mut inner = SyntheticWeather::default();
+        inner.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        inner.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        let weather = DaytimeWeather { inner };
+
+        let models: Vec<SolarModel> = [
+            TimestepAveraging::Instantaneous,
+            TimestepAveraging::IntervalCentred,
+            TimestepAveraging::TrailingAverage,
+        ]
+        .iter()
+        .map(|timestep_averaging| {
+            let model_options = SolarModelOptions {
+                timestep_averaging: *timestep_averaging,
+                workplanes: Some(WorkplaneOptions {
+                    spacing: 1.0,
+                    max_depth: 1,
+                    ..WorkplaneOptions::default()
+                }),
+                ..SolarModelOptions::default()
+            };
+            SolarModel::new_with_options(
+                &meta_options,
+                options.clone(),
+                model_options,
+                &model,
+                &mut state_header,
+                1,
+            )
+            .unwrap()
+        })
+        .collect();
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = models[0].allocate_memory().unwrap();
+        let date = |hour: Float| Date {
+            month: 6,
+            day: 21,
+            hour,
+        };
+        let floor = model.surfaces.iter().find(|s| s.name == "floor").unwrap();
+
+        // The illuminance follows the sampled instants
+        models[0]
+            .march(date(8.5), &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        let at_8_5 = memory.workplane_illuminance[0].clone();
+        let interior_ir = floor.back_ir_irradiance(&state).unwrap();
+        assert!(at_8_5.iter().all(|e| *e > 0.0));
+        models[1]
+            .march(date(9.), &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        for (found, exp) in memory.workplane_illuminance[0].iter().zip(at_8_5.iter()) {
+            assert_close!(*found, *exp, 1e-3);
+        }
+
+        // The IR exchanged within the space is averaged like the rest
+        floor.set_back_ir_irradiance(&mut state, 0.0).unwrap();
+        models[2]
+            .march(date(9.), &weather, &model, &mut state, &mut memory)
+            .unwrap();
+        assert_close!(floor.back_ir_irradiance(&state).unwrap(), interior_ir / 2.);
+    }
 
+    #[test]
+    fn test_sky_model() {
         let meta_options = MetaOptions {
             latitude: (-41.3 as Float).to_radians(),
             longitude: (174.78 as Float).to_radians(),
             standard_meridian: (180. as Float).to_radians(),
             elevation: 0.0,
         };
-
-        let mut state_header = SimulationStateHeader::new();
+        let (model, mut state_header) = SimpleModel::from_file("./tests/wall/wall.spl").unwrap();
         let mut options = SolarOptions::new();
-        options.set_n_solar_irradiance_points(1);
-        options.set_solar_ambient_divitions(1);
+        options.set_n_solar_irradiance_points(10);
+        options.set_solar_ambient_divitions(3000);
         options.set_solar_sky_discretization(1);
 
-        let n: usize = 1;
-
-        let solar_model =
-            SolarModel::new(&meta_options, options, &model, &mut state_header, n).unwrap();
+        let models: Vec<SolarModel> = [SkyModel::Perez, SkyModel::Uniform, SkyModel::CieOvercast]
+            .iter()
+            .map(|sky_model| {
+                let model_options = SolarModelOptions {
+                    sky_model: *sky_model,
+                    albedo: Albedo::Constant { value: 0.2 },
+                    timestep_averaging: TimestepAveraging::Instantaneous,
+                    ..SolarModelOptions::default()
+                };
+                SolarModel::new_with_options(
+                    &meta_options,
+                    options.clone(),
+                    model_options,
+                    &model,
+                    &mut state_header,
+                    1,
+                )
+                .unwrap()
+            })
+            .collect();
 
+        // Overcast
         let mut weather = SyntheticWeather::default();
         weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
         weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(24.));
-        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
-        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(400.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(10.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(0.));
         weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(200.));
 
         let mut state = state_header.take_values().unwrap();
-        solar_model
-            .march(
-                Date {
-                    month: 1,
-                    day: 1,
-                    hour: 12.,
-                },
-                &weather,
-                &model,
-                &mut state,
-                &mut (),
-            )
-            .unwrap();
-
-        // This surface should receive NO sun at the front but yes at the back
-        assert!(
-            model.surfaces[0]
-                .front_incident_solar_irradiance(&state)
-                .unwrap()
-                .abs()
-                < 1e-9
-        );
-        assert!(
-            model.surfaces[0]
-                .back_incident_solar_irradiance(&state)
-                .unwrap()
-                > 50.
-        );
+        let mut memory = models[0].allocate_memory().unwrap();
+        let date = Date {
+            month: 1,
+            day: 1,
+            hour: 12.,
+        };
+        let mut front = Vec::new();
+        for m in models.iter() {
+            m.march(date, &weather, &model, &mut state, &mut memory)
+                .unwrap();
+            front.push(
+                model.surfaces[0]
+                    .front_incident_solar_irradiance(&state)
+                    .unwrap(),
+            );
+        }
 
-        // This surface should receive sun on both sides
-        assert!(
-            model.surfaces[1]
-                .front_incident_solar_irradiance(&state)
-                .unwrap()
-                .abs()
-                > 50.
-        );
-        assert!(
-            model.surfaces[1]
-                .back_incident_solar_irradiance(&state)
-                .unwrap()
-                > 50.
-        );
+        // A vertical wall sees half the sky and half the ground
+        assert_close!(front[1], 0.5 * 200. + 0.5 * 0.2 * 200., 0.1);
 
-        // This surface should receive sun on both sides
-        assert!(
-            model.fenestrations[0]
-                .front_incident_solar_irradiance(&state)
-                .unwrap()
-                > 50.
-        );
-        assert!(
-            model.fenestrations[0]
-                .back_incident_solar_irradiance(&state)
-                .unwrap()
-                > 50.
-        );
+        // The overcast sky is brighter at the zenith, which a vertical wall barely sees
+        assert!(front[2] < front[1], "{:?}", front);
+        assert!(front[0] > 0.0);
 
-        // This surface should receive NO sun at the back but yes at the front
-        assert!(
-            model.fenestrations[1]
-                .front_incident_solar_irradiance(&state)
-                .unwrap()
-                > 50.
-        );
-        assert!(
-            model.fenestrations[1]
-                .back_incident_solar_irradiance(&state)
-                .unwrap()
-                .abs()
-                < 1e-9
-        );
+        // Invalid skies are reported
+        let model_options = SolarModelOptions {
+            sky_model: SkyModel::CieGeneral { sky_type: 0 },
+            ..SolarModelOptions::default()
+        };
+        assert!(SolarModel::new_with_options(
+            &meta_options,
+            options,
+            model_options,
+            &model,
+            &mut state_header,
+            1,
+        )
+        .is_err());
     }
 }
@@ -0,0 +1,370 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Finds which surface or fenestration of a model (if any) is hit by a ray,
+//! so that the obstructions seen by each surface can be classified by what
+//! they are.
+//!
+//! Rays are cast into the [`Scene`] (using its acceleration structure), and the
+//! triangle they hit is then mapped back to the surface or fenestration it was
+//! made from. Triangles that do not belong to any of them (e.g., other
+//! obstructions in the scene) are reported as unknown obstructions.
+
+use crate::interior_radiation::{SpaceSide, SpaceSideKind};
+use crate::Float;
+use geometry3d::{Point3D, Polygon3D, Ray3D, Vector3D};
+use rendering::{Ray, Scene};
+use simple_model::SimpleModel;
+
+/// Rays hitting objects closer than this are ignored
+const MIN_DISTANCE: Float = 1e-4;
+
+/// How far (in m) can the vertices of a triangle be from the plane of a
+/// surface or fenestration and still be considered part of it
+const PLANE_TOLERANCE: Float = 1e-3;
+
+/// A surface or fenestration that can obstruct the view of others
+struct Obstruction<'a> {
+    /// Whether this is a surface or a fenestration
+    kind: SpaceSideKind,
+
+    /// The index of the surface or fenestration in the model
+    index: usize,
+
+    /// The polygon
+    polygon: &'a Polygon3D,
+
+    /// The normal of the polygon
+    normal: Vector3D,
+
+    /// A point in the plane of the polygon
+    point: Point3D,
+
+    /// The corners of the bounding box of the polygon
+    bounds: (Point3D, Point3D),
+}
+
+impl<'a> Obstruction<'a> {
+    /// Checks whether a triangle lies on this surface or fenestration (i.e.,
+    /// on its plane and within its bounding box)
+    fn contains_triangle(&self, vertices: &[Point3D; 3]) -> bool {
+        let (min, max) = self.bounds;
+        let on_plane = vertices
+            .iter()
+            .all(|v| (self.normal * (*v - self.point)).abs() < PLANE_TOLERANCE);
+        let below = |f: fn(&Point3D) -> Float, limit: Float| {
+            vertices.iter().all(|v| f(v) < limit - PLANE_TOLERANCE)
+        };
+        let above = |f: fn(&Point3D) -> Float, limit: Float| {
+            vertices.iter().all(|v| f(v) > limit + PLANE_TOLERANCE)
+        };
+        on_plane
+            && !below(|p| p.x, min.x)
+            && !below(|p| p.y, min.y)
+            && !below(|p| p.z, min.z)
+            && !above(|p| p.x, max.x)
+            && !above(|p| p.y, max.y)
+            && !above(|p| p.z, max.z)
+    }
+}
+
+/// The corners of the bounding box of a polygon
+fn bounds(polygon: &Polygon3D) -> (Point3D, Point3D) {
+    let vertices = polygon.outer().vertices();
+    let mut min = vertices[0];
+    let mut max = vertices[0];
+    for v in vertices.iter() {
+        min = Point3D::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z));
+        max = Point3D::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z));
+    }
+    (min, max)
+}
+
+/// The vertices of a triangle of the [`Scene`]
+fn triangle_vertices(scene: &Scene, index: usize) -> [Point3D; 3] {
+    let t = &scene.triangles[index];
+    [
+        Point3D::new(t[0], t[1], t[2]),
+        Point3D::new(t[3], t[4], t[5]),
+        Point3D::new(t[6], t[7], t[8]),
+    ]
+}
+
+/// The surfaces and fenestrations of a model, seen as obstructions
+pub(crate) struct Obstructions<'a> {
+    /// The scene into which rays are cast
+    scene: &'a Scene,
+
+    /// The surfaces and fenestrations
+    list: Vec<Obstruction<'a>>,
+
+    /// The elements of `list` that each triangle of the `scene` may belong
+    /// to, with the fenestrations first. Fenestrations are often drawn over
+    /// the surface that contains them (i.e., without a hole), so they need
+    /// to be checked before it.
+    candidates: Vec<Vec<usize>>,
+}
+
+impl<'a> Obstructions<'a> {
+    /// Collects the surfaces and fenestrations of a model, and maps the
+    /// triangles of the `scene` (which needs to have its accelerator built)
+    /// to them.
+    pub fn new(model: &'a SimpleModel, scene: &'a Scene) -> Result<Self, String> {
+        let mut list = Vec::with_capacity(model.surfaces.len() + model.fenestrations.len());
+        let fenestrations = model
+            .fenestrations
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (SpaceSideKind::Fenestration, i, &s.vertices));
+        let surfaces = model
+            .surfaces
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (SpaceSideKind::Surface, i, &s.vertices));
+        for (kind, index, polygon) in fenestrations.chain(surfaces) {
+            list.push(Obstruction {
+                kind,
+                index,
+                polygon,
+                normal: polygon.normal(),
+                point: polygon.outer().vertices()[0],
+                bounds: bounds(polygon),
+            })
+        }
+
+        let candidates = (0..scene.triangles.len())
+            .map(|i| {
+                let vertices = triangle_vertices(scene, i);
+                list.iter()
+                    .enumerate()
+                    .filter(|(_, o)| o.contains_triangle(&vertices))
+                    .map(|(j, _)| j)
+                    .collect()
+            })
+            .collect();
+
+        Ok(Self {
+            scene,
+            list,
+            candidates,
+        })
+    }
+
+    /// Creates obstructions that do not tell the objects in a `scene` apart,
+    /// so everything hit is reported as an unknown obstruction
+    pub fn unclassified(scene: &'a Scene) -> Self {
+        Self {
+            scene,
+            list: Vec::new(),
+            candidates: Vec::new(),
+        }
+    }
+
+    /// Casts a ray into the scene. Returns the distance to the closest object
+    /// hit, and the side of the surface or fenestration it is (`None` if the
+    /// object is not part of the model). The object `skip` (e.g., the one
+    /// emitting the ray) is ignored. The `node_aux` is reused by the
+    /// acceleration structure, to avoid allocating on every ray.
+    pub fn cast(
+        &self,
+        ray: &Ray3D,
+        skip: Option<(SpaceSideKind, usize)>,
+        node_aux: &mut Vec<usize>,
+    ) -> Result<Option<(Float, Option<SpaceSide>)>, String> {
+        let direction = ray.direction;
+        let mut travelled = 0.0;
+        loop {
+            let mut r = Ray {
+                geometry: Ray3D {
+                    origin: ray.origin + direction * travelled,
+                    direction,
+                },
+                ..Ray::default()
+            };
+            let triangle = match self.scene.cast_ray(&mut r, node_aux) {
+                Some(i) => i,
+                None => return Ok(None),
+            };
+
+            // Find where the ray hits the plane of the triangle
+            let [a, b, c] = triangle_vertices(self.scene, triangle);
+            let normal = (b - a).cross(c - a);
+            let cos = normal * direction;
+            let t = if cos.abs() < Float::EPSILON {
+                0.0
+            } else {
+                (normal * (a - r.geometry.origin)) / cos
+            };
+            let hit = r.geometry.origin + direction * t;
+
+            let mut side = None;
+            let candidates = self.candidates.get(triangle).map(|c| c.as_slice());
+            for &j in candidates.unwrap_or(&[]) {
+                let o = &self.list[j];
+                if o.polygon.test_point(hit)? {
+                    side = Some(o);
+                    break;
+                }
+            }
+            // Hits on the edges might not be inside any polygon
+            let side = side.or_else(|| candidates.and_then(|c| c.first()).map(|j| &self.list[*j]));
+
+            let skipped = match side {
+                Some(o) => skip == Some((o.kind, o.index)),
+                None => false,
+            };
+            if t < MIN_DISTANCE || skipped {
+                travelled += t.max(0.0) + MIN_DISTANCE;
+                continue;
+            }
+
+            // Rays travelling against the normal hit the front
+            let side = side.map(|o| SpaceSide {
+                kind: o.kind,
+                index: o.index,
+                front: o.normal * direction < 0.0,
+            });
+            return Ok(Some((travelled + t, side)));
+        }
+    }
+
+    /// Finds the side of the closest surface or fenestration hit by a ray,
+    /// and the distance to it. The object `skip` (e.g., the one emitting
+    /// the ray) is ignored. Returns `None` if nothing is hit, or if the
+    /// closest object is not part of the model.
+    #[cfg(test)]
+    pub fn closest_hit(
+        &self,
+        ray: &Ray3D,
+        skip: Option<(SpaceSideKind, usize)>,
+    ) -> Result<Option<(Float, SpaceSide)>, String> {
+        let mut node_aux = Vec::with_capacity(2);
+        let hit = self.cast(ray, skip, &mut node_aux)?;
+        Ok(hit.and_then(|(t, side)| side.map(|s| (t, s))))
+    }
+
+    /// Finds the side of the closest surface or fenestration hit by a ray.
+    /// The object `skip` (e.g., the one emitting the ray) is ignored.
+    #[cfg(test)]
+    pub fn first_hit(
+        &self,
+        ray: &Ray3D,
+        skip: Option<(SpaceSideKind, usize)>,
+    ) -> Result<Option<SpaceSide>, String> {
+        Ok(self.closest_hit(ray, skip)?.map(|(_, side)| side))
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use rendering::Wavelengths;
+
+    fn scene(model: &SimpleModel) -> Scene {
+        let mut scene = Scene::from_simple_model(model, Wavelengths::Solar).unwrap();
+        scene.build_accelerator();
+        scene
+    }
+
+    #[test]
+    fn test_first_hit() {
+        let (model, _) = SimpleModel::from_file("./tests/cube/cube.spl").unwrap();
+        let scene = scene(&model);
+        let obstructions = Obstructions::new(&model, &scene).unwrap();
+
+        // From the centre of the cube, looking up, we see the back of the ceiling
+        let ray = Ray3D {
+            origin: Point3D::new(0.5, 0.5, 0.5),
+            direction: Vector3D::new(0., 0., 1.),
+        };
+        let hit = obstructions.first_hit(&ray, None).unwrap().unwrap();
+        assert_eq!(hit.kind, SpaceSideKind::Surface);
+        assert_eq!(hit.index, 1);
+        assert!(!hit.front);
+
+        // Unless we skip it
+        let hit = obstructions
+            .first_hit(&ray, Some((SpaceSideKind::Surface, 1)))
+            .unwrap();
+        assert!(hit.is_none());
+
+        // From above, looking down, we see the front of the ceiling
+        let ray = Ray3D {
+            origin: Point3D::new(0.5, 0.5, 2.),
+            direction: Vector3D::new(0., 0., -1.),
+        };
+        let hit = obstructions.first_hit(&ray, None).unwrap().unwrap();
+        assert_eq!(hit.index, 1);
+        assert!(hit.front);
+
+        // Looking away, we see nothing
+        let ray = Ray3D {
+            origin: Point3D::new(0.5, 0.5, 2.),
+            direction: Vector3D::new(0., 1., 0.),
+        };
+        assert!(obstructions.first_hit(&ray, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_fenestrations_win_ties() {
+        let (model, _) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let scene = scene(&model);
+        let obstructions = Obstructions::new(&model, &scene).unwrap();
+
+        // The window is drawn over the south wall
+        let ray = Ray3D {
+            origin: Point3D::new(2., 2., 1.5),
+            direction: Vector3D::new(0., -1., 0.),
+        };
+        let (t, hit) = obstructions.closest_hit(&ray, None).unwrap().unwrap();
+        assert_eq!(hit.kind, SpaceSideKind::Fenestration);
+        assert_eq!(hit.index, 0);
+        assert!((t - 2.).abs() < 1e-4);
+
+        // Below the window, we see the wall
+        let ray = Ray3D {
+            origin: Point3D::new(2., 2., 0.5),
+            direction: Vector3D::new(0., -1., 0.),
+        };
+        let hit = obstructions.first_hit(&ray, None).unwrap().unwrap();
+        assert_eq!(hit.kind, SpaceSideKind::Surface);
+    }
+
+    #[test]
+    fn test_unclassified() {
+        let (model, _) = SimpleModel::from_file("./tests/cube/cube.spl").unwrap();
+        let scene = scene(&model);
+        let obstructions = Obstructions::unclassified(&scene);
+        let mut node_aux = Vec::new();
+
+        // The ceiling is hit... but not identified
+        let ray = Ray3D {
+            origin: Point3D::new(0.5, 0.5, 0.5),
+            direction: Vector3D::new(0., 0., 1.),
+        };
+        let (t, side) = obstructions
+            .cast(&ray, None, &mut node_aux)
+            .unwrap()
+            .unwrap();
+        assert!((t - 0.5).abs() < 1e-4);
+        assert!(side.is_none());
+        assert!(obstructions.first_hit(&ray, None).unwrap().is_none());
+    }
+}
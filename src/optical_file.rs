@@ -0,0 +1,499 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Reads and writes [`OpticalInfo`] files. The format is chosen by the
+//! extension of the file:
+//!
+//! * `.bin`: a compact binary format (see below)
+//! * `.bin.gz`: the same binary format, compressed with gzip
+//! * anything else (e.g., `.json`): JSON, which is easier to inspect and debug
+//!
+//! Most of an `OpticalInfo` are its Daylight Coefficient matrices, so the binary
+//! format stores these as raw little-endian numbers, and everything else as JSON.
+//! Its layout is:
+//!
+//! | Field | Size (bytes) | Content |
+//! |-------|--------------|---------|
+//! | Magic | 8 | `LIGHTOI\0` |
+//! | Layout version | 4 | `u32`, currently `1` |
+//! | Float size | 1 | `4` for `f32` or `8` for `f64` numbers |
+//! | Metadata length | 8 | `u64`, the number of bytes of the metadata |
+//! | Metadata | - | The JSON of the `OpticalInfo`, with all matrices empty |
+//! | Number of matrices | 4 | `u32` |
+//! | Matrices | - | For each matrix: its number of rows and columns (`u64`), followed by its elements, row by row |
+//!
+//! All integers and numbers are little-endian. The matrices are stored in the order
+//! given by `matrix_slots()`, which is the order of the fields of `OpticalInfo`.
+//! When reading, the size of each matrix is checked against the metadata before
+//! allocating it, so that corrupt files return an error.
+
+use crate::glare::PixelTarget;
+use crate::{Float, OpticalInfo};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use matrix::Matrix;
+use solar::ReinhartSky;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// The first bytes of every binary file
+const MAGIC: &[u8; 8] = b"LIGHTOI\0";
+
+/// The version of the binary layout described in the module documentation
+const LAYOUT_VERSION: u32 = 1;
+
+/// The finest sky discretization (i.e., MF) accepted when reading matrices
+const MAX_SKY_DISCRETIZATION: usize = 12;
+
+/// The formats in which an [`OpticalInfo`] can be stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileFormat {
+    /// JSON
+    Json,
+
+    /// The binary format described in the module documentation
+    Binary,
+
+    /// The binary format, compressed with gzip
+    CompressedBinary,
+}
+
+impl FileFormat {
+    /// Chooses the format based on the extension of a file
+    pub(crate) fn from_path(path: &Path) -> Self {
+        let name = path.to_string_lossy().to_lowercase();
+        if name.ends_with(".bin.gz") {
+            Self::CompressedBinary
+        } else if name.ends_with(".bin") {
+            Self::Binary
+        } else {
+            Self::Json
+        }
+    }
+}
+
+/// Gets all the matrices in an [`OpticalInfo`], in the order in which
+/// they are stored in binary files
+fn matrix_slots(info: &mut OpticalInfo) -> Vec<&mut Matrix> {
+    let mut ret = vec![
+        &mut info.front_surfaces_dc,
+        &mut info.back_surfaces_dc,
+        &mut info.front_fenestrations_dc,
+        &mut info.back_fenestrations_dc,
+        &mut info.front_surfaces_sun_dc,
+        &mut info.back_surfaces_sun_dc,
+        &mut info.front_fenestrations_sun_dc,
+        &mut info.back_fenestrations_sun_dc,
+    ];
+    ret.extend(info.workplanes_dc.iter_mut());
+    ret.extend(info.workplanes_sun_dc.iter_mut());
+    for view in info.glare_views.iter_mut() {
+        ret.push(&mut view.dc);
+        ret.push(&mut view.irradiance_dc);
+    }
+    ret
+}
+
+/// Gets the number of rows expected for each of the matrices returned by
+/// `matrix_slots()`, when these are known from the metadata (i.e., the
+/// signature and the sensors of the workplanes)
+fn expected_rows(info: &OpticalInfo) -> Vec<Option<usize>> {
+    let signature = &info.signature;
+    let known = |names: &Vec<String>| {
+        if signature.format_version == 0 {
+            // Written before the signature existed
+            None
+        } else {
+            Some(names.len())
+        }
+    };
+    let mut ret = vec![
+        known(&signature.front_surfaces),
+        known(&signature.back_surfaces),
+        known(&signature.front_fenestrations),
+        known(&signature.back_fenestrations),
+        known(&signature.front_surfaces),
+        known(&signature.back_surfaces),
+        known(&signature.front_fenestrations),
+        known(&signature.back_fenestrations),
+    ];
+    let sensors = |i: usize| info.workplanes.get(i).map(|w| w.sensors.len());
+    ret.extend((0..info.workplanes_dc.len()).map(sensors));
+    ret.extend((0..info.workplanes_sun_dc.len()).map(sensors));
+    for view in info.glare_views.iter() {
+        let surfaces = view
+            .pixels
+            .iter()
+            .filter(|p| matches!(p.target, PixelTarget::Surface { .. }))
+            .count();
+        ret.push(Some(1));
+        ret.push(Some(surfaces));
+    }
+    ret
+}
+
+/// Checks that the size of a matrix read from a binary file is plausible,
+/// returning the number of bytes its elements occupy
+fn matrix_bytes(
+    nrows: usize,
+    ncols: usize,
+    expected_rows: Option<usize>,
+    float_size: usize,
+) -> Result<usize, String> {
+    if let Some(expected) = expected_rows {
+        if nrows != expected {
+            return Err(format!(
+                "Binary optical information has a matrix with {} rows, but its metadata expects {}",
+                nrows, expected
+            ));
+        }
+    }
+    if !(1..=MAX_SKY_DISCRETIZATION).any(|mf| ReinhartSky::n_bins(mf) == ncols) {
+        return Err(format!(
+            "Binary optical information has a matrix with {} columns, which does not match any sky discretization",
+            ncols
+        ));
+    }
+    match nrows
+        .checked_mul(ncols)
+        .and_then(|n| n.checked_mul(float_size))
+    {
+        Some(v) => Ok(v),
+        None => Err(format!(
+            "Binary optical information has a matrix that is too large ({} rows by {} columns)",
+            nrows, ncols
+        )),
+    }
+}
+
+/// Maps an IO error into a `String`
+fn io_error(e: std::io::Error) -> String {
+    format!("Unable to read or write optical information: {}", e)
+}
+
+/// Writes an [`OpticalInfo`] in the binary format
+pub(crate) fn write_binary<W: Write>(info: &OpticalInfo, mut writer: W) -> Result<(), String> {
+    // The metadata is everything but the matrices
+    let mut metadata = info.clone();
+    let matrices: Vec<Matrix> = matrix_slots(&mut metadata)
+        .into_iter()
+        .map(|m| std::mem::replace(m, Matrix::empty()))
+        .collect();
+    let json = match serde_json::to_vec(&metadata) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("{}", e)),
+    };
+
+    writer.write_all(MAGIC).map_err(io_error)?;
+    writer
+        .write_all(&LAYOUT_VERSION.to_le_bytes())
+        .map_err(io_error)?;
+    writer
+        .write_all(&[std::mem::size_of::<Float>() as u8])
+        .map_err(io_error)?;
+    writer
+        .write_all(&(json.len() as u64).to_le_bytes())
+        .map_err(io_error)?;
+    writer.write_all(&json).map_err(io_error)?;
+    writer
+        .write_all(&(matrices.len() as u32).to_le_bytes())
+        .map_err(io_error)?;
+    for m in matrices.iter() {
+        let (nrows, ncols) = m.size();
+        writer
+            .write_all(&(nrows as u64).to_le_bytes())
+            .map_err(io_error)?;
+        writer
+            .write_all(&(ncols as u64).to_le_bytes())
+            .map_err(io_error)?;
+        let mut row_bytes = Vec::with_capacity(ncols * std::mem::size_of::<Float>());
+        for row in 0..nrows {
+            row_bytes.clear();
+            for col in 0..ncols {
+                row_bytes.extend_from_slice(&m.get(row, col)?.to_le_bytes());
+            }
+            writer.write_all(&row_bytes).map_err(io_error)?;
+        }
+    }
+    writer.flush().map_err(io_error)
+}
+
+/// Reads `N` bytes
+fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], String> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf).map_err(io_error)?;
+    Ok(buf)
+}
+
+/// Reads a little-endian `u64`, to be used as a size
+fn read_size<R: Read>(reader: &mut R) -> Result<usize, String> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?) as usize)
+}
+
+/// Reads an [`OpticalInfo`] stored in the binary format
+pub(crate) fn read_binary<R: Read>(mut reader: R) -> Result<OpticalInfo, String> {
+    let magic: [u8; 8] = read_bytes(&mut reader)?;
+    if magic != *MAGIC {
+        return Err("This is not a binary optical information file".to_string());
+    }
+    let version = u32::from_le_bytes(read_bytes(&mut reader)?);
+    if version != LAYOUT_VERSION {
+        return Err(format!(
+            "Unsupported binary optical information layout version {}... expecting {}",
+            version, LAYOUT_VERSION
+        ));
+    }
+    let [float_size] = read_bytes::<R, 1>(&mut reader)?;
+    if float_size != 4 && float_size != 8 {
+        return Err(format!(
+            "Invalid float size {} in binary optical information",
+            float_size
+        ));
+    }
+
+    // Read through `take()`, so that a corrupt length does not allocate
+    // more memory than the data that is actually there
+    let json_len = read_size(&mut reader)?;
+    let mut json = Vec::new();
+    (&mut reader)
+        .take(json_len as u64)
+        .read_to_end(&mut json)
+        .map_err(io_error)?;
+    if json.len() != json_len {
+        return Err("Binary optical information is truncated".to_string());
+    }
+    let mut info: OpticalInfo = match serde_json::from_slice(&json) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Unable to parse optical_info metadata: {}", e)),
+    };
+
+    let n_matrices = u32::from_le_bytes(read_bytes(&mut reader)?) as usize;
+    let expected_rows = expected_rows(&info);
+    let mut slots = matrix_slots(&mut info);
+    if slots.len() != n_matrices {
+        return Err(format!(
+            "Binary optical information has {} matrices, but its metadata expects {}",
+            n_matrices,
+            slots.len()
+        ));
+    }
+    for (slot, expected_rows) in slots.iter_mut().zip(expected_rows) {
+        let nrows = read_size(&mut reader)?;
+        let ncols = read_size(&mut reader)?;
+        if nrows == 0 || ncols == 0 {
+            **slot = Matrix::empty();
+            continue;
+        }
+        let n_bytes = matrix_bytes(nrows, ncols, expected_rows, float_size as usize)?;
+        let mut bytes = Vec::new();
+        (&mut reader)
+            .take(n_bytes as u64)
+            .read_to_end(&mut bytes)
+            .map_err(io_error)?;
+        if bytes.len() != n_bytes {
+            return Err("Binary optical information is truncated".to_string());
+        }
+        let mut values = bytes.chunks_exact(float_size as usize).map(|c| {
+            if float_size == 4 {
+                f32::from_le_bytes([c[0], c[1], c[2], c[3]]) as Float
+            } else {
+                f64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]) as Float
+            }
+        });
+        let mut m = Matrix::new(0.0, nrows, ncols);
+        for row in 0..nrows {
+            for col in 0..ncols {
+                // There are exactly nrows * ncols values
+                if let Some(v) = values.next() {
+                    m.set(row, col, v)?;
+                }
+            }
+        }
+        **slot = m;
+    }
+    Ok(info)
+}
+
+/// Writes an [`OpticalInfo`] into a file, in the format given by its extension
+pub(crate) fn write_file(info: &OpticalInfo, path: &Path) -> Result<(), String> {
+    let file = match File::create(path) {
+        Ok(v) => BufWriter::new(v),
+        Err(e) => return Err(format!("{}", e)),
+    };
+    match FileFormat::from_path(path) {
+        FileFormat::Json => {
+            let s = match serde_json::to_value(info) {
+                Ok(v) => v,
+                Err(e) => return Err(format!("{}", e)),
+            };
+            let mut file = file;
+            if let Err(e) = writeln!(&mut file, "{}", s) {
+                return Err(format!("{}", e));
+            }
+            file.flush().map_err(io_error)
+        }
+        FileFormat::Binary => write_binary(info, file),
+        FileFormat::CompressedBinary => {
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            write_binary(info, &mut encoder)?;
+            encoder.finish().map_err(io_error)?;
+            Ok(())
+        }
+    }
+}
+
+/// Reads an [`OpticalInfo`] from a file, in the format given by its extension
+pub(crate) fn read_file(path: &Path) -> Result<OpticalInfo, String> {
+    let path_str = path.to_string_lossy();
+    let file = match File::open(path) {
+        Ok(v) => BufReader::new(v),
+        Err(_) => return Err(format!("Unable to read optical_info file '{}'", path_str)),
+    };
+    let result = match FileFormat::from_path(path) {
+        FileFormat::Json => serde_json::from_reader(file).map_err(|e| format!("{}", e)),
+        FileFormat::Binary => read_binary(file),
+        FileFormat::CompressedBinary => read_binary(GzDecoder::new(file)),
+    };
+    result.map_err(|e| {
+        format!(
+            "Unable to parse optical_info object in file '{}': {}",
+            path_str, e
+        )
+    })
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::daylight::Workplane;
+    use validate::assert_close;
+
+    /// A small `OpticalInfo`, with a few matrices
+    fn sample_info() -> OpticalInfo {
+        let n_bins = ReinhartSky::n_bins(1);
+        let mut front = Matrix::new(0.0, 2, n_bins);
+        front.set(0, 0, 0.25).unwrap();
+        front.set(1, 2, 1.5).unwrap();
+        let mut workplane = Matrix::new(0.0, 1, n_bins);
+        workplane.set(0, 1, 179.).unwrap();
+        OpticalInfo {
+            front_surfaces_dc: front,
+            workplanes: vec![Workplane {
+                space: "the space".into(),
+                sensors: vec![geometry3d::Point3D::new(1., 1., 0.8)],
+            }],
+            workplanes_dc: vec![workplane],
+            workplanes_sun_dc: vec![Matrix::empty()],
+            ..OpticalInfo::default()
+        }
+    }
+
+    fn check(info: &OpticalInfo, read: &OpticalInfo) {
+        assert_eq!(read.front_surfaces_dc.size(), (2, ReinhartSky::n_bins(1)));
+        assert_close!(read.front_surfaces_dc.get(0, 0).unwrap(), 0.25);
+        assert_close!(read.front_surfaces_dc.get(1, 2).unwrap(), 1.5);
+        assert_close!(read.front_surfaces_dc.get(1, 1).unwrap(), 0.0);
+        assert!(read.back_surfaces_dc.is_empty());
+        assert_eq!(read.workplanes.len(), 1);
+        assert_eq!(read.workplanes[0].space, "the space");
+        assert_eq!(read.workplanes_dc.len(), 1);
+        assert_close!(read.workplanes_dc[0].get(0, 1).unwrap(), 179.);
+        assert_eq!(read.workplanes_sun_dc.len(), 1);
+        assert!(read.workplanes_sun_dc[0].is_empty());
+        assert_eq!(read.options, info.options);
+        assert_eq!(read.signature, info.signature);
+    }
+
+    #[test]
+    fn test_file_format() {
+        let format = |p: &str| FileFormat::from_path(Path::new(p));
+        assert_eq!(format("optical.json"), FileFormat::Json);
+        assert_eq!(format("optical"), FileFormat::Json);
+        assert_eq!(format("optical.bin"), FileFormat::Binary);
+        assert_eq!(format("optical.BIN"), FileFormat::Binary);
+        assert_eq!(format("optical.bin.gz"), FileFormat::CompressedBinary);
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let info = sample_info();
+        let mut bytes: Vec<u8> = Vec::new();
+        write_binary(&info, &mut bytes).unwrap();
+        assert_eq!(&bytes[0..8], MAGIC);
+        let read = read_binary(bytes.as_slice()).unwrap();
+        check(&info, &read);
+
+        // Not a binary file
+        assert!(read_binary("{}".as_bytes()).is_err());
+
+        // Truncated
+        assert!(read_binary(&bytes[0..bytes.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_sizes() {
+        let info = sample_info();
+        let mut bytes: Vec<u8> = Vec::new();
+        write_binary(&info, &mut bytes).unwrap();
+        let json_len = u64::from_le_bytes(bytes[13..21].try_into().unwrap()) as usize;
+        let first_matrix = 21 + json_len + 4;
+        let corrupt = |offset: usize, value: u64| {
+            let mut corrupt = bytes.clone();
+            corrupt[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            read_binary(corrupt.as_slice())
+        };
+
+        // Huge metadata
+        assert!(corrupt(13, u64::MAX).is_err());
+
+        // Overflowing number of rows
+        assert!(corrupt(first_matrix, u64::MAX).is_err());
+
+        // Many rows, but not enough data
+        assert!(corrupt(first_matrix, 1 << 40).is_err());
+
+        // Columns that do not match any sky
+        assert!(corrupt(first_matrix + 8, 3).is_err());
+
+        // Rows that do not match the signature
+        let mut info = sample_info();
+        info.signature.format_version = crate::OPTICAL_INFO_FORMAT_VERSION;
+        info.signature.front_surfaces = vec!["a".into(), "b".into()];
+        let mut bytes: Vec<u8> = Vec::new();
+        write_binary(&info, &mut bytes).unwrap();
+        assert!(read_binary(bytes.as_slice()).is_ok());
+        info.signature.front_surfaces.pop();
+        let mut bytes: Vec<u8> = Vec::new();
+        write_binary(&info, &mut bytes).unwrap();
+        assert!(read_binary(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_files() {
+        let info = sample_info();
+        for name in ["optical.json", "optical.bin", "optical.bin.gz"] {
+            let path = std::env::temp_dir().join(format!("light_optical_file_{}", name));
+            write_file(&info, &path).unwrap();
+            let read = read_file(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            check(&info, &read);
+        }
+    }
+}
@@ -17,16 +17,24 @@ LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
+use crate::daylight::Workplane;
+use crate::glare::GlareView;
+use crate::interior_radiation::{InteriorViewFactors, SpaceSide, SpaceSideKind};
+use crate::obstructions::Obstructions;
+use crate::optical_file;
+use crate::row_cache::{RowCache, RowKey, RowKeys};
 use crate::solar_surface::SolarSurface;
-use crate::Float;
+use crate::view_factors::ViewFactorCalculator;
+use crate::{Float, SolarModelOptions, ViewFactorMode};
 use matrix::Matrix;
 use rendering::{DCFactory, Scene, Wavelengths};
 use serde::{Deserialize, Serialize};
-use simple_model::{SimpleModel, SimulationStateHeader, SolarOptions};
+use simple_model::{Boundary, SimpleModel, SimulationStateHeader, SolarOptions};
 use solar::ReinhartSky;
+use std::path::Path;
 
 /// A set of view factors as seen by a `ThermalSurface`.
-#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct IRViewFactorSet {
     /// The fraction of the view that corresponds to the sky
     pub sky: Float,
@@ -37,6 +45,210 @@ pub struct IRViewFactorSet {
     /// The fraction of the view that corresponds to other objects and
     /// surfaces (they are assumed to be at air temperature)
     pub air: Float,
+
+    /// The fraction of the view that corresponds to each of the sides of
+    /// the surfaces and fenestrations of the model that obstruct the view.
+    /// This is only calculated in [`ViewFactorMode::Physical`] mode.
+    #[serde(default)]
+    pub obstructions: Vec<(SpaceSide, Float)>,
+
+    /// An estimate of the standard error of the view factors above (i.e.,
+    /// the largest of them all), which indicates whether enough rays were
+    /// sampled to make them converge
+    #[serde(default)]
+    pub standard_error: Float,
+}
+
+/// The version of the format in which [`OpticalInfo`] is stored. It needs to be
+/// increased whenever changes in this crate make stored optical information invalid.
+pub const OPTICAL_INFO_FORMAT_VERSION: u32 = 2;
+
+/// Identifies the model and options that an [`OpticalInfo`] was calculated for,
+/// so that stored optical information is not reused after these change.
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpticalInfoSignature {
+    /// The [`OPTICAL_INFO_FORMAT_VERSION`] used when writing the information.
+    /// Files written before this was introduced have a version of `0`.
+    pub format_version: u32,
+
+    /// A hash of the geometry, boundaries and materials of the model,
+    /// and of the options that affect the optical calculations
+    pub hash: u64,
+
+    /// The name of the surfaces that have a row in the `front_surfaces_dc`
+    /// (i.e., those whose front boundary receives sun), in order
+    pub front_surfaces: Vec<String>,
+
+    /// The name of the surfaces that have a row in the `back_surfaces_dc`
+    pub back_surfaces: Vec<String>,
+
+    /// The name of the fenestrations that have a row in the `front_fenestrations_dc`
+    pub front_fenestrations: Vec<String>,
+
+    /// The name of the fenestrations that have a row in the `back_fenestrations_dc`
+    pub back_fenestrations: Vec<String>,
+
+    /// Identifies the calculations done for each side of each surface and
+    /// fenestration, so that they can be reused if the model changes
+    #[serde(default)]
+    pub row_keys: RowKeys,
+}
+
+/// Calculates the 64-bit FNV-1a hash of some bytes. This is used instead of
+/// the `std` hashers because its result needs to be stable across Rust versions
+/// and platforms.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Describes some data for hashing it, through its JSON serialization.
+///
+/// Unlike the `Debug` output, this is a stable format: it only changes if
+/// the data (or its fields, e.g., after updating a dependency) changes, in
+/// which case stored optical information is stale anyway.
+pub(crate) fn canonical<T: Serialize + ?Sized>(value: &T) -> String {
+    // Data that cannot be serialized is described by the error, which
+    // at least does not depend on the formatting of Rust's Debug
+    serde_json::to_string(value).unwrap_or_else(|e| format!("!{}", e))
+}
+
+/// Gets the names of the elements that receive sun (and therefore have a
+/// row in the Daylight Coefficient matrices)
+fn rows<'a, I: Iterator<Item = (&'a String, &'a Boundary)>>(list: I) -> Vec<String> {
+    list.filter(|(_, boundary)| SolarSurface::boundary_receives_sun(boundary))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+impl OpticalInfoSignature {
+    /// Calculates the signature of a model and options
+    pub fn new(
+        options: &SolarOptions,
+        model_options: &SolarModelOptions,
+        model: &SimpleModel,
+    ) -> Self {
+        let front_surfaces = rows(model.surfaces.iter().map(|s| (&s.name, &s.front_boundary)));
+        let back_surfaces = rows(model.surfaces.iter().map(|s| (&s.name, &s.back_boundary)));
+        let front_fenestrations = rows(
+            model
+                .fenestrations
+                .iter()
+                .map(|s| (&s.name, &s.front_boundary)),
+        );
+        let back_fenestrations = rows(
+            model
+                .fenestrations
+                .iter()
+                .map(|s| (&s.name, &s.back_boundary)),
+        );
+
+        // Only the options that affect the optical information are considered
+        let calculation_options = serde_json::json!({
+            "mf": options.solar_sky_discretization_or(crate::model::MODULE_NAME, 1),
+            "n_solar_rays": options.n_solar_irradiance_points_or(crate::model::MODULE_NAME, 10),
+            "ambient_divitions": options.solar_ambient_divitions_or(crate::model::MODULE_NAME, 300),
+            "direct_sun_sky_discretization": model_options.direct_sun_sky_discretization,
+            "solar_max_depth": model_options.solar_max_depth,
+            "interior_view_factor_points": model_options.interior_view_factor_points,
+            "interior_view_factor_samples": model_options.interior_view_factor_samples,
+            "ir_view_factor_samples": model_options.ir_view_factor_samples,
+            "view_factor_mode": model_options.view_factor_mode,
+            "workplanes": model_options.workplanes,
+            "glare": model_options.glare,
+        });
+
+        let mut content = calculation_options.to_string();
+        for s in model.surfaces.iter() {
+            content.push_str(&format!(
+                "surface|{}|{}|{}|{}|{}\n",
+                s.name,
+                s.construction,
+                canonical(&s.vertices),
+                canonical(&s.front_boundary),
+                canonical(&s.back_boundary)
+            ));
+        }
+        for s in model.fenestrations.iter() {
+            content.push_str(&format!(
+                "fenestration|{}|{}|{}|{}|{}\n",
+                s.name,
+                s.construction,
+                canonical(&s.vertices),
+                canonical(&s.front_boundary),
+                canonical(&s.back_boundary)
+            ));
+        }
+        content.push_str(&format!(
+            "{}|{}|{}",
+            canonical(&model.constructions),
+            canonical(&model.materials),
+            canonical(&model.substances)
+        ));
+
+        Self {
+            format_version: OPTICAL_INFO_FORMAT_VERSION,
+            hash: fnv1a(content.as_bytes()),
+            front_surfaces,
+            back_surfaces,
+            front_fenestrations,
+            back_fenestrations,
+            row_keys: RowKeys::new(options, model_options, model),
+        }
+    }
+
+    /// Checks whether optical information with this signature can be used
+    /// for a model and options with the `expected` signature, returning an
+    /// error that explains why if it cannot.
+    pub fn check(&self, expected: &Self) -> Result<(), String> {
+        if self.format_version != expected.format_version {
+            return Err(format!(
+                "it was written in format version {}, but version {} is expected",
+                self.format_version, expected.format_version
+            ));
+        }
+        let rows = [
+            (
+                "front of surfaces",
+                &self.front_surfaces,
+                &expected.front_surfaces,
+            ),
+            (
+                "back of surfaces",
+                &self.back_surfaces,
+                &expected.back_surfaces,
+            ),
+            (
+                "front of fenestrations",
+                &self.front_fenestrations,
+                &expected.front_fenestrations,
+            ),
+            (
+                "back of fenestrations",
+                &self.back_fenestrations,
+                &expected.back_fenestrations,
+            ),
+        ];
+        for (name, found, exp) in rows {
+            if found != exp {
+                return Err(format!(
+                    "the rows of the {} are {:?}, but the model has {:?}",
+                    name, found, exp
+                ));
+            }
+        }
+        if self.hash != expected.hash {
+            return Err(
+                "the geometry, boundaries or materials of the model, or the options, have changed"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
 }
 
 /// Information about the solar radiation and other optical elements
@@ -66,6 +278,65 @@ pub struct OpticalInfo {
 
     /// The Daylight Coefficients matrix for the back-side of the fenestrations in the scene
     pub back_fenestrations_dc: Matrix,
+
+    /// The direct-sun Daylight Coefficients matrix for the front-side of the surfaces in the
+    /// scene. It is empty unless `direct_sun_sky_discretization` is set in the [`SolarModelOptions`]
+    #[serde(default)]
+    pub front_surfaces_sun_dc: Matrix,
+
+    /// The direct-sun Daylight Coefficients matrix for the back-side of the surfaces in the
+    /// scene. It is empty unless `direct_sun_sky_discretization` is set in the [`SolarModelOptions`]
+    #[serde(default)]
+    pub back_surfaces_sun_dc: Matrix,
+
+    /// The direct-sun Daylight Coefficients matrix for the front-side of the fenestrations in the
+    /// scene. It is empty unless `direct_sun_sky_discretization` is set in the [`SolarModelOptions`]
+    #[serde(default)]
+    pub front_fenestrations_sun_dc: Matrix,
+
+    /// The direct-sun Daylight Coefficients matrix for the back-side of the fenestrations in the
+    /// scene. It is empty unless `direct_sun_sky_discretization` is set in the [`SolarModelOptions`]
+    #[serde(default)]
+    pub back_fenestrations_sun_dc: Matrix,
+
+    /// The view factors between the surfaces and fenestrations that
+    /// bound each space, in the same order as the spaces in the model
+    #[serde(default)]
+    pub interior_view_factors: Vec<InteriorViewFactors>,
+
+    /// The illuminance sensors of each space, in the same order as the spaces in
+    /// the model. It is empty unless `workplanes` is set in the [`SolarModelOptions`]
+    #[serde(default)]
+    pub workplanes: Vec<Workplane>,
+
+    /// The visible Daylight Coefficients matrix of each [`Workplane`], with one
+    /// row per sensor
+    #[serde(default)]
+    pub workplanes_dc: Vec<Matrix>,
+
+    /// The visible Daylight Coefficients matrix of each [`Workplane`], without
+    /// inter-reflections. It is used for calculating the illuminance from
+    /// the direct sun alone (e.g., for the Annual Sunlight Exposure), so it
+    /// uses the `direct_sun_sky_discretization` of the [`SolarModelOptions`], if set.
+    #[serde(default)]
+    pub workplanes_sun_dc: Vec<Matrix>,
+
+    /// The viewpoints evaluated for glare. It is empty unless `glare`
+    /// is set in the [`SolarModelOptions`]
+    #[serde(default)]
+    pub glare_views: Vec<GlareView>,
+
+    /// The [`SolarModelOptions`] of the last run that used this object. Those
+    /// that affect the optical information are the ones used for building it,
+    /// and the rest (e.g., the albedo) are updated when stored data is reused.
+    /// These are stored so that runs that read this data from a file are
+    /// reproducible.
+    #[serde(default)]
+    pub options: SolarModelOptions,
+
+    /// The model and options this information was calculated for
+    #[serde(default)]
+    pub signature: OpticalInfoSignature,
 }
 
 impl OpticalInfo {
@@ -74,9 +345,41 @@ impl OpticalInfo {
     /// This will trigger ray-tracing processes, so it might be slow.
     pub fn new(
         options: &SolarOptions,
+        model_options: &SolarModelOptions,
+        model: &SimpleModel,
+        state: &mut SimulationStateHeader,
+    ) -> Result<Self, String> {
+        Self::calc(options, model_options, model, state, &RowCache::default())
+    }
+
+    /// Calculates the OpticalInformation of a model that changed since `previous`
+    /// was calculated, reusing the Daylight Coefficients and view factors of the
+    /// surfaces and fenestrations whose geometry and surroundings did not change.
+    ///
+    /// The workplanes, glare views and interior view factors are reused for the
+    /// spaces whose geometry (and, for the first two, the outside seen through
+    /// their fenestrations) did not change.
+    pub fn new_from_previous(
+        previous: &OpticalInfo,
+        options: &SolarOptions,
+        model_options: &SolarModelOptions,
+        model: &SimpleModel,
+        state: &mut SimulationStateHeader,
+    ) -> Result<Self, String> {
+        let cache = RowCache::new(previous)?;
+        Self::calc(options, model_options, model, state, &cache)
+    }
+
+    fn calc(
+        options: &SolarOptions,
+        model_options: &SolarModelOptions,
         model: &SimpleModel,
         state: &mut SimulationStateHeader,
+        cache: &RowCache,
     ) -> Result<Self, String> {
+        let mut signature = OpticalInfoSignature::new(options, model_options, model);
+        let keys = &signature.row_keys;
+
         // Collect calculation options
         let mf = options.solar_sky_discretization_or(crate::model::MODULE_NAME, 1);
         let n_solar_rays = options.n_solar_irradiance_points_or(crate::model::MODULE_NAME, 10);
@@ -92,49 +395,247 @@ impl OpticalInfo {
 
         // calculator
         let solar_dc_factory = DCFactory {
-            max_depth: 0,
+            max_depth: model_options.solar_max_depth,
             n_ambient_samples: options.solar_ambient_divitions_or(crate::model::MODULE_NAME, 300),
             reinhart: ReinhartSky::new(mf),
             ..DCFactory::default()
         };
 
-        // calculate
-        let front_surfaces_dc =
-            SolarSurface::calc_solar_dc_matrix(&surfaces, &solar_scene, &solar_dc_factory, true)?;
+        // calculate, reusing what has not changed
+        let front_surfaces_dc = SolarSurface::calc_solar_dc_matrix_reusing(
+            &surfaces,
+            &solar_scene,
+            &solar_dc_factory,
+            true,
+            &cache.dc_rows(&keys.front_surfaces),
+        )?;
 
-        let back_surfaces_dc =
-            SolarSurface::calc_solar_dc_matrix(&surfaces, &solar_scene, &solar_dc_factory, false)?;
+        let back_surfaces_dc = SolarSurface::calc_solar_dc_matrix_reusing(
+            &surfaces,
+            &solar_scene,
+            &solar_dc_factory,
+            false,
+            &cache.dc_rows(&keys.back_surfaces),
+        )?;
 
-        let front_fenestrations_dc = SolarSurface::calc_solar_dc_matrix(
+        let front_fenestrations_dc = SolarSurface::calc_solar_dc_matrix_reusing(
             &fenestrations,
             &solar_scene,
             &solar_dc_factory,
             true,
+            &cache.dc_rows(&keys.front_fenestrations),
         )?;
 
-        let back_fenestrations_dc = SolarSurface::calc_solar_dc_matrix(
+        let back_fenestrations_dc = SolarSurface::calc_solar_dc_matrix_reusing(
             &fenestrations,
             &solar_scene,
             &solar_dc_factory,
             false,
+            &cache.dc_rows(&keys.back_fenestrations),
         )?;
 
+        // Calculate the direct-sun matrices, if required
+        let (
+            front_surfaces_sun_dc,
+            back_surfaces_sun_dc,
+            front_fenestrations_sun_dc,
+            back_fenestrations_sun_dc,
+        ) = match model_options.direct_sun_sky_discretization {
+            Some(sun_mf) => {
+                let sun_dc_factory = DCFactory {
+                    reinhart: ReinhartSky::new(sun_mf),
+                    ..solar_dc_factory
+                };
+                (
+                    SolarSurface::calc_solar_dc_matrix_reusing(
+                        &surfaces,
+                        &solar_scene,
+                        &sun_dc_factory,
+                        true,
+                        &cache.sun_dc_rows(&keys.front_surfaces),
+                    )?,
+                    SolarSurface::calc_solar_dc_matrix_reusing(
+                        &surfaces,
+                        &solar_scene,
+                        &sun_dc_factory,
+                        false,
+                        &cache.sun_dc_rows(&keys.back_surfaces),
+                    )?,
+                    SolarSurface::calc_solar_dc_matrix_reusing(
+                        &fenestrations,
+                        &solar_scene,
+                        &sun_dc_factory,
+                        true,
+                        &cache.sun_dc_rows(&keys.front_fenestrations),
+                    )?,
+                    SolarSurface::calc_solar_dc_matrix_reusing(
+                        &fenestrations,
+                        &solar_scene,
+                        &sun_dc_factory,
+                        false,
+                        &cache.sun_dc_rows(&keys.back_fenestrations),
+                    )?,
+                )
+            }
+            None => (
+                Matrix::empty(),
+                Matrix::empty(),
+                Matrix::empty(),
+                Matrix::empty(),
+            ),
+        };
+
+        // The visible scene is only needed for daylighting and glare
+        let visible_scene = if model_options.workplanes.is_some() || model_options.glare.is_some() {
+            let mut scene = Scene::from_simple_model(model, Wavelengths::Visible)?;
+            scene.build_accelerator();
+            Some(scene)
+        } else {
+            None
+        };
+
+        // Calculate the illuminance sensors, if required
+        let (workplanes, workplanes_dc, workplanes_sun_dc) =
+            match (&model_options.workplanes, &visible_scene) {
+                (Some(workplane_options), Some(visible_scene)) => {
+                    let mut workplanes = Workplane::from_model(model, workplane_options)?;
+                    let visible_dc_factory = DCFactory {
+                        max_depth: workplane_options.max_depth,
+                        n_ambient_samples: options
+                            .solar_ambient_divitions_or(crate::model::MODULE_NAME, 300),
+                        reinhart: ReinhartSky::new(mf),
+                        ..DCFactory::default()
+                    };
+                    // The direct sun uses the fine sky, if given (e.g., for
+                    // the Annual Sunlight Exposure)
+                    let direct_dc_factory = DCFactory {
+                        max_depth: 0,
+                        n_ambient_samples: options
+                            .solar_ambient_divitions_or(crate::model::MODULE_NAME, 300),
+                        reinhart: ReinhartSky::new(
+                            model_options.direct_sun_sky_discretization.unwrap_or(mf),
+                        ),
+                        ..DCFactory::default()
+                    };
+                    let mut workplanes_dc = Vec::with_capacity(workplanes.len());
+                    let mut workplanes_sun_dc = Vec::with_capacity(workplanes.len());
+                    for (i, workplane) in workplanes.iter_mut().enumerate() {
+                        // Reuse the workplanes of the spaces that did not change
+                        let cached = keys.workplanes.get(i).and_then(|k| cache.workplane(*k));
+                        match cached {
+                            Some((cached, dc, sun_dc)) => {
+                                *workplane = cached;
+                                workplanes_dc.push(dc);
+                                workplanes_sun_dc.push(sun_dc);
+                            }
+                            None => {
+                                workplanes_dc
+                                    .push(workplane.calc_dc(visible_scene, &visible_dc_factory));
+                                workplanes_sun_dc
+                                    .push(workplane.calc_dc(visible_scene, &direct_dc_factory));
+                            }
+                        }
+                    }
+                    (workplanes, workplanes_dc, workplanes_sun_dc)
+                }
+                _ => (Vec::new(), Vec::new(), Vec::new()),
+            };
+
+        // Prepare the viewpoints for glare analysis, if required
+        let mut glare_view_keys = Vec::new();
+        let glare_views = match (&model_options.glare, &visible_scene) {
+            (Some(glare_options), Some(visible_scene)) => {
+                let obstructions = Obstructions::new(model, visible_scene)?;
+                let glare_dc_factory = DCFactory {
+                    max_depth: glare_options.max_depth,
+                    n_ambient_samples: options
+                        .solar_ambient_divitions_or(crate::model::MODULE_NAME, 300),
+                    reinhart: ReinhartSky::new(mf),
+                    ..DCFactory::default()
+                };
+                let mut glare_views = Vec::with_capacity(glare_options.viewpoints.len());
+                for viewpoint in glare_options.viewpoints.iter() {
+                    // Reuse the views from spaces that did not change
+                    let context = match viewpoint.space(model, &obstructions)? {
+                        Some(space) => keys.spaces[space],
+                        None => signature.hash,
+                    };
+                    let key = RowKeys::glare_view_key(context, viewpoint, glare_options, options);
+                    glare_view_keys.push(key);
+                    let view = match cache.glare_view(key) {
+                        Some(view) => view.clone(),
+                        None => GlareView::new(
+                            viewpoint.clone(),
+                            glare_options,
+                            model,
+                            &obstructions,
+                            visible_scene,
+                            &glare_dc_factory,
+                            mf,
+                        )?,
+                    };
+                    glare_views.push(view);
+                }
+                glare_views
+            }
+            _ => Vec::new(),
+        };
+
+        let mode = model_options.view_factor_mode;
+        let obstructions = match mode {
+            ViewFactorMode::Physical => Obstructions::new(model, &solar_scene)?,
+            ViewFactorMode::Legacy => Obstructions::unclassified(&solar_scene),
+        };
+        let calculator =
+            ViewFactorCalculator::new(obstructions, mode, model_options.ir_view_factor_samples);
         let mut front_surfaces_view_factors = Vec::with_capacity(surfaces.len());
-        for s in surfaces.iter() {
-            front_surfaces_view_factors.push(s.calc_view_factors(&solar_scene, true)?)
-        }
         let mut back_surfaces_view_factors = Vec::with_capacity(surfaces.len());
-        for s in surfaces {
-            back_surfaces_view_factors.push(s.calc_view_factors(&solar_scene, false)?)
+        // Reuse the view factors that have not changed
+        let calc_view_factors =
+            |s: &SolarSurface, own, front_side, key: &RowKey| match cache.view_factors(key) {
+                Some(v) => Ok(v.clone()),
+                None => calculator.calc(s, own, front_side),
+            };
+        for (i, s) in surfaces.iter().enumerate() {
+            let own = Some((SpaceSideKind::Surface, i));
+            front_surfaces_view_factors.push(calc_view_factors(
+                s,
+                own,
+                true,
+                &keys.front_surfaces[i],
+            )?);
+            back_surfaces_view_factors.push(calc_view_factors(
+                s,
+                own,
+                false,
+                &keys.back_surfaces[i],
+            )?);
         }
         let mut front_fenestrations_view_factors = Vec::with_capacity(fenestrations.len());
-        for s in fenestrations.iter() {
-            front_fenestrations_view_factors.push(s.calc_view_factors(&solar_scene, true)?);
-        }
         let mut back_fenestrations_view_factors = Vec::with_capacity(fenestrations.len());
-        for s in fenestrations.iter() {
-            back_fenestrations_view_factors.push(s.calc_view_factors(&solar_scene, false)?)
+        for (i, s) in fenestrations.iter().enumerate() {
+            let own = Some((SpaceSideKind::Fenestration, i));
+            front_fenestrations_view_factors.push(calc_view_factors(
+                s,
+                own,
+                true,
+                &keys.front_fenestrations[i],
+            )?);
+            back_fenestrations_view_factors.push(calc_view_factors(
+                s,
+                own,
+                false,
+                &keys.back_fenestrations[i],
+            )?);
         }
+        let interior_view_factors = InteriorViewFactors::calc(
+            model,
+            &Obstructions::new(model, &solar_scene)?,
+            model_options.interior_view_factor_points,
+            model_options.interior_view_factor_samples,
+            &cache.interior_view_factors(&keys.interior_view_factors),
+        )?;
+        signature.row_keys.glare_views = glare_view_keys;
 
         Ok(Self {
             front_surfaces_view_factors,
@@ -145,8 +646,35 @@ impl OpticalInfo {
             back_surfaces_dc,
             front_fenestrations_dc,
             back_fenestrations_dc,
+            front_surfaces_sun_dc,
+            back_surfaces_sun_dc,
+            front_fenestrations_sun_dc,
+            back_fenestrations_sun_dc,
+            interior_view_factors,
+            workplanes,
+            workplanes_dc,
+            workplanes_sun_dc,
+            glare_views,
+            options: model_options.clone(),
+            signature,
         })
     }
+
+    /// Reads an `OpticalInfo` from a file. Files ending in `.bin` or `.bin.gz` are
+    /// read in a compact (and maybe compressed) binary format, and any other
+    /// file is read as JSON.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        if !path.is_file() {
+            return Err(format!("Path '{}' is not a file", path.to_string_lossy()));
+        }
+        optical_file::read_file(path)
+    }
+
+    /// Writes this `OpticalInfo` into a file, so that it can be reused. The format
+    /// is chosen by the extension of the file, as in [`OpticalInfo::from_file`].
+    pub fn to_file(&self, path: &Path) -> Result<(), String> {
+        optical_file::write_file(self, path)
+    }
 }
 
 #[cfg(test)]
@@ -158,7 +686,11 @@ mod testing {
         SimulationStateHeader, SolarOptions, Surface,
     };
 
-    use crate::OpticalInfo;
+    use super::{OpticalInfoSignature, OPTICAL_INFO_FORMAT_VERSION};
+    use crate::solar_surface::SolarSurface;
+    use crate::{Albedo, Float, OpticalInfo, SolarModelOptions};
+    use matrix::Matrix;
+    use validate::assert_close;
 
     #[test]
     fn test_new() {
@@ -240,7 +772,8 @@ mod testing {
         .unwrap();
         model.add_fenestration(fen).unwrap();
 
-        let info = OpticalInfo::new(&options, &model, &mut state).unwrap();
+        let info =
+            OpticalInfo::new(&options, &SolarModelOptions::default(), &model, &mut state).unwrap();
         assert_eq!(info.back_fenestrations_dc.size(), (2, 146)); // 2 fenestrations, 146 patches
         assert_eq!(info.front_fenestrations_dc.size(), (2, 146)); // 2 fenestrations, 146 patches
         assert_eq!(info.back_surfaces_dc.size(), (2, 146)); // 2 fenestrations, 146 patches
@@ -339,7 +872,8 @@ mod testing {
         .unwrap();        
         model.add_fenestration(fen).unwrap();
 
-        let info = OpticalInfo::new(&options, &model, &mut state).unwrap();
+        let info =
+            OpticalInfo::new(&options, &SolarModelOptions::default(), &model, &mut state).unwrap();
         assert_eq!(info.back_fenestrations_dc.size(), (1, 146)); // 1 fenestration has no solar radiation at the back
         assert_eq!(info.front_fenestrations_dc.size(), (2, 146)); // 2 fenestrations, 146 patches
         assert_eq!(info.back_surfaces_dc.size(), (2, 146)); // 2 fenestrations, 146 patches
@@ -349,4 +883,181 @@ mod testing {
         assert_eq!(info.front_fenestrations_view_factors.len(), 2);
         assert_eq!(info.back_fenestrations_view_factors.len(), 2);
     }
+
+    #[test]
+    fn test_direct_sun_matrices() {
+        let mut model = SimpleModel::default();
+        let mut state = SimulationStateHeader::new();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(1);
+        options.set_solar_ambient_divitions(1);
+        options.set_solar_sky_discretization(1);
+
+        let substance = Normal::new("the substance");
+        model.add_substance(substance.wrap());
+
+        let material = Material::new("the material", "the substance", 0.1);
+        model.add_material(material);
+
+        let mut construction = Construction::new("the construction");
+        construction.materials.push("the material".into());
+        model.add_construction(construction);
+
+        let s: Surface = json5::from_str(
+            "{
+            name: 'the surface',
+            construction:'the construction',
+            vertices: [
+                0, 0, 0, // X, Y and Z of Vertex 0
+                1, 0, 0, // X, Y and Z of Vertex 1
+                1, 1, 0, // X, Y and Z of Vertex 2
+                0, 1, 0  // ...
+            ]
+         }",
+        )
+        .unwrap();
+        model.add_surface(s);
+
+        // No direct sun by default
+        let info =
+            OpticalInfo::new(&options, &SolarModelOptions::default(), &model, &mut state).unwrap();
+        assert!(info.front_surfaces_sun_dc.is_empty());
+        assert!(info.back_surfaces_sun_dc.is_empty());
+
+        // Direct sun with MF = 2
+        let model_options = SolarModelOptions {
+            direct_sun_sky_discretization: Some(2),
+            ..SolarModelOptions::default()
+        };
+        let info = OpticalInfo::new(&options, &model_options, &model, &mut state).unwrap();
+        assert_eq!(info.front_surfaces_dc.size(), (1, 146)); // 1 surface, 146 patches
+        assert_eq!(info.front_surfaces_sun_dc.size(), (1, 577)); // 1 surface, 577 patches
+        assert_eq!(info.back_surfaces_sun_dc.size(), (1, 577)); // 1 surface, 577 patches
+        assert!(info.front_fenestrations_sun_dc.is_empty()); // no fenestrations
+        assert_eq!(info.options, model_options);
+    }
+
+    #[test]
+    fn test_courtyard_bounces() {
+        // The irradiance of a row of a DC matrix, under a sky (and ground)
+        // of uniform radiance equal to 1.
+        fn uniform_sky_irradiance(dc: &Matrix, row: usize) -> Float {
+            let (.., ncols) = dc.size();
+            (0..ncols).map(|col| dc.get(row, col).unwrap()).sum()
+        }
+
+        // A 10x10m courtyard, surrounded by 10m-high light walls
+        let (model, mut state) = SimpleModel::from_file("./tests/courtyard/courtyard.spl").unwrap();
+        let options = model.solar_options.clone().unwrap();
+
+        let direct_only =
+            OpticalInfo::new(&options, &SolarModelOptions::default(), &model, &mut state).unwrap();
+
+        let model_options = SolarModelOptions {
+            solar_max_depth: 2,
+            ..SolarModelOptions::default()
+        };
+        let bounced = OpticalInfo::new(&options, &model_options, &model, &mut state).unwrap();
+
+        // The floor of the courtyard (front side) mostly sees walls, so it
+        // should receive a lot more light when these reflect.
+        let floor_direct = uniform_sky_irradiance(&direct_only.front_surfaces_dc, 0);
+        let floor_bounced = uniform_sky_irradiance(&bounced.front_surfaces_dc, 0);
+        assert!(
+            floor_bounced > 1.1 * floor_direct,
+            "floor_bounced = {}, floor_direct = {}",
+            floor_bounced,
+            floor_direct
+        );
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let (model, mut state) = SimpleModel::from_file("./tests/courtyard/courtyard.spl").unwrap();
+        let options = model.solar_options.clone().unwrap();
+        let model_options = SolarModelOptions {
+            solar_max_depth: 1,
+            ..SolarModelOptions::default()
+        };
+        let info = OpticalInfo::new(&options, &model_options, &model, &mut state).unwrap();
+
+        let path = std::env::temp_dir().join("light_optical_info_round_trip.json");
+        info.to_file(&path).unwrap();
+        let read = OpticalInfo::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.options, model_options);
+        assert_eq!(read.signature, info.signature);
+        assert_eq!(read.front_surfaces_dc.size(), info.front_surfaces_dc.size());
+        let (nrows, ncols) = info.front_surfaces_dc.size();
+        for row in 0..nrows {
+            for col in 0..ncols {
+                let exp = info.front_surfaces_dc.get(row, col).unwrap();
+                let found = read.front_surfaces_dc.get(row, col).unwrap();
+                assert_close!(found, exp, 1e-5);
+            }
+        }
+
+        // Reading something that is not there fails
+        let missing = std::env::temp_dir().join("light_optical_info_missing.json");
+        assert!(OpticalInfo::from_file(&missing).is_err());
+    }
+
+    #[test]
+    fn test_signature() {
+        let (model, ..) = SimpleModel::from_file("./tests/courtyard/courtyard.spl").unwrap();
+        let options = model.solar_options.clone().unwrap();
+        let model_options = SolarModelOptions::default();
+        let signature = OpticalInfoSignature::new(&options, &model_options, &model);
+        assert_eq!(signature.format_version, OPTICAL_INFO_FORMAT_VERSION);
+        assert_eq!(
+            signature.front_surfaces.len(),
+            model
+                .surfaces
+                .iter()
+                .filter(|s| SolarSurface::boundary_receives_sun(&s.front_boundary))
+                .count()
+        );
+
+        // Same model and options
+        let again = OpticalInfoSignature::new(&options, &model_options, &model);
+        assert!(again.check(&signature).is_ok());
+
+        // Options that do not affect the optical information
+        let albedo_options = SolarModelOptions {
+            albedo: Albedo::Constant { value: 0.3 },
+            ..SolarModelOptions::default()
+        };
+        let albedo = OpticalInfoSignature::new(&options, &albedo_options, &model);
+        assert!(albedo.check(&signature).is_ok());
+
+        // Options that do
+        let bounced_options = SolarModelOptions {
+            solar_max_depth: 2,
+            ..SolarModelOptions::default()
+        };
+        let bounced = OpticalInfoSignature::new(&options, &bounced_options, &model);
+        assert!(bounced.check(&signature).is_err());
+
+        let mut finer = options.clone();
+        finer.set_solar_sky_discretization(4);
+        let finer = OpticalInfoSignature::new(&finer, &model_options, &model);
+        assert!(finer.check(&signature).is_err());
+
+        // Another model
+        let (room, ..) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let room = OpticalInfoSignature::new(&options, &model_options, &room);
+        assert!(room.check(&signature).is_err());
+
+        // Old files, without a signature
+        let old = OpticalInfoSignature::default();
+        let err = old.check(&signature).unwrap_err();
+        assert!(err.contains("format version 0"), "{}", err);
+
+        // Rows that do not match
+        let mut rows = signature.clone();
+        rows.front_surfaces.pop();
+        let err = rows.check(&signature).unwrap_err();
+        assert!(err.contains("front of surfaces"), "{}", err);
+    }
 }
@@ -0,0 +1,635 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+use crate::decomposition::DecompositionModel;
+use crate::epw;
+use crate::glare::GlareOptions;
+use crate::lighting_control::LightingControlOptions;
+use crate::sky::SkyModel;
+use crate::Float;
+use calendar::Date;
+use serde::{Deserialize, Serialize};
+use simple_model::SolarOptions;
+use std::path::{Path, PathBuf};
+
+/// The source of the ground albedo used when building the sky vectors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Albedo {
+    /// The same albedo for the whole simulation
+    Constant {
+        /// The albedo (i.e., ground reflectance), between 0 and 1
+        value: Float,
+    },
+
+    /// One albedo per month, from January to December. This
+    /// can be used as a simple schedule (e.g., for snow-covered winters)
+    Monthly {
+        /// The albedo of each month, starting with January
+        values: [Float; 12],
+    },
+
+    /// Reads the albedo column of an EPW file, hour by hour.
+    EPWFile {
+        /// The path to the EPW file
+        path: String,
+        /// The value used when the EPW file reports missing data (i.e., `999`)
+        fallback: Float,
+    },
+}
+
+impl std::default::Default for Albedo {
+    fn default() -> Self {
+        Self::Constant { value: 0.2 }
+    }
+}
+
+impl Albedo {
+    /// Loads the hourly data needed by this source, if any.
+    pub(crate) fn load(&self) -> Result<Vec<Float>, String> {
+        match self {
+            Self::EPWFile { path, fallback } => {
+                epw::read_column(path, epw::ALBEDO_COLUMN, epw::ALBEDO_MISSING, *fallback)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Gets the albedo for a certain date. The `hourly_data` is the one
+    /// returned by [`Albedo::load`]
+    pub(crate) fn get(&self, date: Date, hourly_data: &[Float]) -> Result<Float, String> {
+        let v = match self {
+            Self::Constant { value } => *value,
+            Self::Monthly { values } => values[(date.month as usize - 1) % 12],
+            Self::EPWFile { fallback, .. } => {
+                if hourly_data.is_empty() {
+                    *fallback
+                } else {
+                    hourly_data[epw::hour_index(date) % hourly_data.len()]
+                }
+            }
+        };
+        if !(0.0..=1.0).contains(&v) {
+            return Err(format!("Albedo should be between 0 and 1... found {}", v));
+        }
+        Ok(v)
+    }
+}
+
+/// The source of the temperature of the ground, used for the sides of
+/// surfaces and fenestrations whose boundary is `Boundary::Ground`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum GroundTemperature {
+    /// Use the outdoor dry bulb temperature, just as for the ground seen
+    /// by exterior surfaces
+    DryBulb,
+
+    /// The same ground temperature (in C) for the whole simulation
+    Constant {
+        /// The temperature of the ground, in C
+        value: Float,
+    },
+
+    /// One ground temperature (in C) per month, from January to December
+    Monthly {
+        /// The ground temperature of each month, starting with January
+        values: [Float; 12],
+    },
+
+    /// Reads the monthly ground temperatures in the `GROUND TEMPERATURES`
+    /// header of an EPW file
+    EPWFile {
+        /// The path to the EPW file
+        path: String,
+        /// Which of the depths in the header to use, starting from zero
+        /// (i.e., the shallowest one, often 0.5m)
+        #[serde(default)]
+        depth_index: usize,
+    },
+}
+
+impl std::default::Default for GroundTemperature {
+    fn default() -> Self {
+        Self::DryBulb
+    }
+}
+
+impl GroundTemperature {
+    /// Loads the monthly data needed by this source, if any.
+    pub(crate) fn load(&self) -> Result<Vec<Float>, String> {
+        match self {
+            Self::EPWFile { path, depth_index } => {
+                Ok(epw::read_ground_temperatures(path, *depth_index)?.to_vec())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Gets the ground temperature for a certain date. The `monthly_data` is the one
+    /// returned by [`GroundTemperature::load`]
+    pub(crate) fn get(&self, date: Date, monthly_data: &[Float], dry_bulb: Float) -> Float {
+        let month = (date.month as usize - 1) % 12;
+        match self {
+            Self::DryBulb => dry_bulb,
+            Self::Constant { value } => *value,
+            Self::Monthly { values } => values[month],
+            Self::EPWFile { .. } => monthly_data[month],
+        }
+    }
+}
+
+/// How the view of the outdoors of each surface is split into sky, ground,
+/// air and obstructions, for the infrared calculations.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ViewFactorMode {
+    /// The unobstructed view upwards is sky, the unobstructed view downwards
+    /// is ground, and obstructions are classified by what they are: the
+    /// surfaces and fenestrations of the model contribute IR at their own
+    /// temperature, and any other geometry (e.g., shading devices) is
+    /// assumed to be at air temperature.
+    #[default]
+    Physical,
+
+    /// The heuristic used by EnergyPlus: the sky view factor $`F_{sky}`$ is
+    /// split into $`F_{sky}\beta`$ of sky and $`F_{sky}(1-\beta)`$ of air,
+    /// with $`\beta = \sqrt{F_{sky}}`$. Obstructions are not accounted for.
+    Legacy,
+}
+
+/// How the illuminance sensors of each space are laid out, and how
+/// their Daylight Coefficients are calculated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkplaneOptions {
+    /// The height of the sensors over the floor, in m
+    pub height: Float,
+
+    /// The distance between neighbouring sensors, in m
+    pub spacing: Float,
+
+    /// The minimum distance between the sensors and the edges of the floor, in m
+    pub margin: Float,
+
+    /// The number of bounces considered when calculating the Daylight
+    /// Coefficients of the sensors. Contrary to the solar calculations, light
+    /// reaching a workplane has often bounced a few times.
+    pub max_depth: usize,
+}
+
+impl std::default::Default for WorkplaneOptions {
+    fn default() -> Self {
+        Self {
+            height: 0.8,
+            spacing: 0.5,
+            margin: 0.5,
+            max_depth: 3,
+        }
+    }
+}
+
+/// What to do when the optical information stored at the `optical_data_path`
+/// of the `SolarOptions` was calculated for a different model or with
+/// different options (or by an older version of this crate).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StaleOpticalData {
+    /// Calculate the optical information again, overwriting the stored one
+    #[default]
+    Recalculate,
+
+    /// Return an error explaining why the stored information cannot be used
+    Error,
+}
+
+/// What to do when the weather data lacks the direct normal or the diffuse
+/// horizontal irradiance of a timestep
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum MissingSolarData {
+    /// Return an error, reporting the date of the missing data
+    #[default]
+    Error,
+
+    /// Interpolate linearly between the closest earlier and later hours
+    /// that have data. It is an error if these are not found.
+    Interpolate {
+        /// How many hours to look backwards and forwards
+        max_hours: usize,
+    },
+
+    /// Split the global horizontal irradiance, using a decomposition
+    /// model. It is an error if the global horizontal irradiance is missing.
+    Decompose {
+        /// The decomposition model
+        model: DecompositionModel,
+    },
+
+    /// Assume that the missing data is zero
+    Zero,
+}
+
+/// How the solar and infrared irradiance set on each timestep represent the
+/// timestep. Each call to `march()` simulates the timestep that ends at
+/// the date it receives.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum TimestepAveraging {
+    /// The irradiance at the end of the timestep
+    Instantaneous,
+
+    /// The average between the irradiance at the end of the timestep and the
+    /// value set on the previous timestep. This is the default, as it is what
+    /// earlier versions of this crate did for the solar irradiance (the
+    /// infrared irradiance used to be instantaneous). Note that results depend
+    /// on the previous call. The first timestep simulated with a memory has no
+    /// previous one, so its irradiance is instantaneous.
+    #[default]
+    TrailingAverage,
+
+    /// The irradiance at the start of the timestep
+    IntervalStart,
+
+    /// The irradiance at the middle of the timestep
+    IntervalCentred,
+
+    /// The average between the irradiance at the start and at the end of
+    /// the timestep
+    StartEnd,
+
+    /// The average of the irradiance at the middle of `samples` equal
+    /// fractions of the timestep. This integrates the changes in sun position
+    /// (and weather) within the timestep, making the results consistent
+    /// across timestep lengths.
+    Integrated {
+        /// The number of instants sampled within each timestep
+        samples: usize,
+    },
+}
+
+impl TimestepAveraging {
+    /// Gets the instants sampled within a timestep, as the fraction of the
+    /// timestep before its end (i.e., `0` is the end and `1` is the start),
+    /// and the weight of each of them.
+    pub fn samples(&self) -> Vec<(Float, Float)> {
+        match self {
+            Self::Instantaneous | Self::TrailingAverage => vec![(0.0, 1.0)],
+            Self::IntervalStart => vec![(1.0, 1.0)],
+            Self::IntervalCentred => vec![(0.5, 1.0)],
+            Self::StartEnd => vec![(1.0, 0.5), (0.0, 0.5)],
+            Self::Integrated { samples } => {
+                let weight = 1. / *samples as Float;
+                (0..*samples)
+                    .map(|i| (1. - (i as Float + 0.5) * weight, weight))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Options for the [`crate::SolarModel`] that are not part of the
+/// `SolarOptions` object defined in `simple_model`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SolarModelOptions {
+    /// The source of the ground albedo
+    pub albedo: Albedo,
+
+    /// If given, the direct sun is calculated separately from the diffuse
+    /// sky, using a Reinhart sky with this MF (e.g., `6`). This avoids smearing
+    /// the sun over a large patch of the coarse sky used for diffuse light.
+    ///
+    /// Note that finer skies need more ambient samples (i.e., the
+    /// `solar_ambient_divitions` in `SolarOptions`) in order to be sampled properly.
+    pub direct_sun_sky_discretization: Option<usize>,
+
+    /// The number of bounces (i.e., inter-reflections) considered when
+    /// calculating the solar Daylight Coefficients. Zero means that only the
+    /// light coming directly from the sky, the sun and the ground plane is
+    /// considered, ignoring the light reflected by neighbouring buildings,
+    /// courtyards, etc. The reflectances of the materials in the model are used.
+    ///
+    /// Each bounce is sampled with `solar_ambient_divitions` rays
+    /// (from `SolarOptions`), so this can become slow quickly.
+    pub solar_max_depth: usize,
+
+    /// The number of points sampled over each surface when calculating the
+    /// view factors between the surfaces that bound the same space. These
+    /// are used for the longwave radiation exchange within spaces.
+    pub interior_view_factor_points: usize,
+
+    /// The number of directions sampled from each of the
+    /// `interior_view_factor_points` when ray-tracing the view factors
+    /// between the surfaces that bound the same space.
+    pub interior_view_factor_samples: usize,
+
+    /// The number of directions sampled from each point of a surface or
+    /// fenestration when calculating its [`crate::IRViewFactorSet`]. The
+    /// resulting `standard_error` can be used for checking whether this
+    /// is enough.
+    pub ir_view_factor_samples: usize,
+
+    /// The source of the temperature of the ground in contact with
+    /// surfaces and fenestrations
+    pub ground_temperature: GroundTemperature,
+
+    /// How the outdoor view factors for the infrared calculations are calculated
+    pub view_factor_mode: ViewFactorMode,
+
+    /// If given, a [`crate::Workplane`] is laid over the floors of each space,
+    /// and the illuminance at its sensors is calculated on every timestep.
+    pub workplanes: Option<WorkplaneOptions>,
+
+    /// If given, the luminaires of each space are dimmed according to the
+    /// illuminance at its [`crate::Workplane`]. This needs `workplanes`.
+    pub lighting_control: Option<LightingControlOptions>,
+
+    /// If given, glare is evaluated at some viewpoints on every timestep
+    pub glare: Option<GlareOptions>,
+
+    /// What to do when the stored optical information does not match the
+    /// model and options
+    pub stale_optical_data: StaleOpticalData,
+
+    /// What to do when the weather data lacks the direct normal or
+    /// the diffuse horizontal irradiance
+    pub missing_solar_data: MissingSolarData,
+
+    /// If given, the direct normal and diffuse horizontal irradiance are
+    /// always derived from the global horizontal irradiance using this
+    /// model, ignoring those reported by the weather data. This is meant
+    /// for weather sources that only measure the global horizontal irradiance.
+    pub decomposition: Option<DecompositionModel>,
+
+    /// How the solar and infrared irradiance of each timestep are averaged
+    pub timestep_averaging: TimestepAveraging,
+
+    /// The distribution of the diffuse radiance over the sky
+    pub sky_model: SkyModel,
+}
+
+impl std::default::Default for SolarModelOptions {
+    fn default() -> Self {
+        Self {
+            albedo: Albedo::default(),
+            direct_sun_sky_discretization: None,
+            solar_max_depth: 0,
+            interior_view_factor_points: 100,
+            interior_view_factor_samples: 1000,
+            ir_view_factor_samples: 10000,
+            ground_temperature: GroundTemperature::default(),
+            view_factor_mode: ViewFactorMode::default(),
+            workplanes: None,
+            lighting_control: None,
+            glare: None,
+            stale_optical_data: StaleOpticalData::default(),
+            missing_solar_data: MissingSolarData::default(),
+            decomposition: None,
+            timestep_averaging: TimestepAveraging::default(),
+            sky_model: SkyModel::default(),
+        }
+    }
+}
+
+/// The extensions of the optical information files, which are replaced
+/// when deriving the path of their options file
+const OPTICAL_DATA_EXTENSIONS: [&str; 3] = [".bin.gz", ".bin", ".json"];
+
+impl SolarModelOptions {
+    /// Reads the options from a JSON file. Missing fields take their default value.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let path_str = path.to_string_lossy();
+        let data = match std::fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Unable to read options file '{}': {}", path_str, e)),
+        };
+        match serde_json::from_str(&data) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(format!(
+                "Unable to parse options file '{}': {}",
+                path_str, e
+            )),
+        }
+    }
+
+    /// Gets the path of the options file that goes with an optical data file.
+    /// This is next to it, with its extension replaced by `.options.json`
+    /// (e.g., `optical_data.json` becomes `optical_data.options.json`).
+    pub fn options_path(optical_data_path: &str) -> PathBuf {
+        let n = optical_data_path.len();
+        let stem = OPTICAL_DATA_EXTENSIONS
+            .iter()
+            .find(|ext| {
+                ext.len() < n
+                    && optical_data_path.is_char_boundary(n - ext.len())
+                    && optical_data_path[n - ext.len()..].eq_ignore_ascii_case(ext)
+            })
+            .map(|ext| &optical_data_path[..n - ext.len()])
+            .unwrap_or(optical_data_path);
+        PathBuf::from(format!("{}.options.json", stem))
+    }
+
+    /// Gets the options that go with the `SolarOptions` of a model. These are
+    /// read from the options file next to its `optical_data_path` (see
+    /// [`Self::options_path`]), if there is one, or are the default ones otherwise.
+    pub fn from_solar_options(options: &SolarOptions) -> Result<Self, String> {
+        if let Ok(optical_data_path) = options.optical_data_path() {
+            let path = Self::options_path(optical_data_path);
+            if path.exists() {
+                return Self::from_file(&path);
+            }
+        }
+        Ok(Self::default())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use validate::assert_close;
+
+    #[test]
+    fn test_options_path() {
+        let path = |p: &str| SolarModelOptions::options_path(p);
+        assert_eq!(
+            path("dir/optical_data.json"),
+            PathBuf::from("dir/optical_data.options.json")
+        );
+        assert_eq!(path("optical.BIN"), PathBuf::from("optical.options.json"));
+        assert_eq!(
+            path("optical.bin.gz"),
+            PathBuf::from("optical.options.json")
+        );
+        assert_eq!(path("optical"), PathBuf::from("optical.options.json"));
+    }
+
+    #[test]
+    fn test_albedo_default() {
+        let albedo = Albedo::default();
+        let date = Date {
+            month: 3,
+            day: 1,
+            hour: 12.,
+        };
+        assert_close!(albedo.get(date, &[]).unwrap(), 0.2);
+    }
+
+    #[test]
+    fn test_albedo_monthly() {
+        let mut values = [0.2; 12];
+        values[0] = 0.7; // snow in January
+        let albedo = Albedo::Monthly { values };
+
+        let january = Date {
+            month: 1,
+            day: 21,
+            hour: 12.,
+        };
+        assert_close!(albedo.get(january, &[]).unwrap(), 0.7);
+
+        let july = Date {
+            month: 7,
+            day: 21,
+            hour: 12.,
+        };
+        assert_close!(albedo.get(july, &[]).unwrap(), 0.2);
+    }
+
+    #[test]
+    fn test_albedo_out_of_range() {
+        let albedo = Albedo::Constant { value: 1.2 };
+        let date = Date {
+            month: 3,
+            day: 1,
+            hour: 12.,
+        };
+        assert!(albedo.get(date, &[]).is_err());
+    }
+
+    #[test]
+    fn test_albedo_epw() {
+        // Barcelona's EPW reports an albedo of 0.0
+        let albedo = Albedo::EPWFile {
+            path: "./tests/barcelona.epw".into(),
+            fallback: 0.3,
+        };
+        let data = albedo.load().unwrap();
+        assert_eq!(data.len(), 8760);
+        let date = Date {
+            month: 3,
+            day: 1,
+            hour: 12.,
+        };
+        assert_close!(albedo.get(date, &data).unwrap(), 0.0);
+
+        // A file that does not exist
+        let albedo = Albedo::EPWFile {
+            path: "./tests/not_a_file.epw".into(),
+            fallback: 0.3,
+        };
+        assert!(albedo.load().is_err());
+    }
+
+    #[test]
+    fn test_options_serde_default() {
+        let options: SolarModelOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!(options, SolarModelOptions::default());
+
+        let options: SolarModelOptions =
+            serde_json::from_str("{\"albedo\": {\"type\": \"Constant\", \"value\": 0.6}}").unwrap();
+        assert_eq!(options.albedo, Albedo::Constant { value: 0.6 });
+    }
+
+    #[test]
+    fn test_ground_temperature() {
+        let date = Date {
+            month: 2,
+            day: 1,
+            hour: 12.,
+        };
+        let ground = GroundTemperature::default();
+        assert!(ground.load().unwrap().is_empty());
+        assert_close!(ground.get(date, &[], 21.), 21.);
+
+        let ground = GroundTemperature::Constant { value: 12. };
+        assert_close!(ground.get(date, &[], 21.), 12.);
+
+        let mut values = [0.0; 12];
+        values[1] = 7.;
+        let ground = GroundTemperature::Monthly { values };
+        assert_close!(ground.get(date, &[], 21.), 7.);
+
+        let ground = GroundTemperature::EPWFile {
+            path: "./tests/barcelona.epw".into(),
+            depth_index: 0,
+        };
+        let data = ground.load().unwrap();
+        assert_eq!(data.len(), 12);
+        assert_close!(ground.get(date, &data, 21.), 10.14);
+
+        let ground: GroundTemperature =
+            serde_json::from_str(r#"{"type": "EPWFile", "path": "./tests/barcelona.epw"}"#)
+                .unwrap();
+        assert_eq!(
+            ground,
+            GroundTemperature::EPWFile {
+                path: "./tests/barcelona.epw".into(),
+                depth_index: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_timestep_averaging() {
+        let total = |averaging: TimestepAveraging| -> Float {
+            averaging.samples().iter().map(|(_, weight)| weight).sum()
+        };
+
+        // A single instant
+        assert_eq!(TimestepAveraging::Instantaneous.samples(), vec![(0.0, 1.0)]);
+        assert_eq!(
+            TimestepAveraging::TrailingAverage.samples(),
+            vec![(0.0, 1.0)]
+        );
+        assert_eq!(TimestepAveraging::IntervalStart.samples(), vec![(1.0, 1.0)]);
+        assert_eq!(
+            TimestepAveraging::IntervalCentred.samples(),
+            vec![(0.5, 1.0)]
+        );
+
+        // Both ends
+        assert_eq!(
+            TimestepAveraging::StartEnd.samples(),
+            vec![(1.0, 0.5), (0.0, 0.5)]
+        );
+
+        // The middle of each quarter of the timestep
+        let integrated = TimestepAveraging::Integrated { samples: 4 };
+        let samples = integrated.samples();
+        assert_eq!(samples.len(), 4);
+        for ((found, _), exp) in samples.iter().zip([0.875, 0.625, 0.375, 0.125]) {
+            assert_close!(*found, exp);
+        }
+        assert_close!(total(integrated), 1.);
+        assert_close!(total(TimestepAveraging::StartEnd), 1.);
+
+        let averaging: TimestepAveraging =
+            serde_json::from_str(r#"{"type": "Integrated", "samples": 6}"#).unwrap();
+        assert_eq!(averaging, TimestepAveraging::Integrated { samples: 6 });
+    }
+}
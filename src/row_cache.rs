@@ -0,0 +1,810 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Reuses the parts of a previous [`OpticalInfo`] that are still valid after
+//! the model changed, so that only the surfaces and fenestrations whose
+//! geometry or surroundings changed are traced again.
+//!
+//! Each side of each surface and fenestration gets a [`RowKey`], which is a hash
+//! of its own geometry and boundaries, of the options used for the calculations,
+//! and of its surroundings. When inter-reflections are not considered (i.e.,
+//! `solar_max_depth` is zero) the surroundings are the surfaces and fenestrations
+//! that lie (at least partially) in front of that side, as the rays leaving it
+//! cannot reach anything else. Otherwise, light can reach it after bouncing
+//! anywhere, so the surroundings are the whole model.
+//!
+//! The results calculated for each space (i.e., its workplane, its interior view
+//! factors and the glare views inside it) are keyed by the geometry of the space:
+//! the surfaces and fenestrations that bound it and the elements inside it. The
+//! workplanes and glare views also see the outside through the fenestrations of
+//! the space, so their keys include the [`RowKey`] of the outer side of those
+//! fenestrations as well.
+
+use crate::daylight::Workplane;
+use crate::glare::{GlareOptions, GlareView, Viewpoint};
+use crate::interior_radiation::InteriorViewFactors;
+use crate::optical_info::{
+    canonical, fnv1a, IRViewFactorSet, OpticalInfo, OPTICAL_INFO_FORMAT_VERSION,
+};
+use crate::solar_surface::SolarSurface;
+use crate::{Float, SolarModelOptions};
+use geometry3d::{Point3D, Polygon3D};
+use matrix::Matrix;
+use serde::{Deserialize, Serialize};
+use simple_model::{Boundary, SimpleModel, SolarOptions};
+use std::collections::HashMap;
+
+/// The distance that a vertex of another element needs to be in front of
+/// a side for being considered part of its surroundings. Elements that are
+/// coplanar with a side (e.g., the windows in the same façade) cannot be
+/// reached by its rays.
+const CONTEXT_TOLERANCE: Float = 1e-3;
+
+/// Identifies the calculations done for one side of a surface or fenestration
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RowKey {
+    /// A hash of the geometry and surroundings of this side, and of the
+    /// options that affect its calculations
+    pub key: u64,
+
+    /// The row of this side in the Daylight Coefficient matrices, if
+    /// it receives sun
+    pub row: Option<usize>,
+}
+
+/// The [`RowKey`] of each side of each surface and fenestration in a model,
+/// and the keys of the results calculated for each space and viewpoint
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RowKeys {
+    /// The keys of the front side of the surfaces, one per surface
+    pub front_surfaces: Vec<RowKey>,
+
+    /// The keys of the back side of the surfaces, one per surface
+    pub back_surfaces: Vec<RowKey>,
+
+    /// The keys of the front side of the fenestrations, one per fenestration
+    pub front_fenestrations: Vec<RowKey>,
+
+    /// The keys of the back side of the fenestrations, one per fenestration
+    pub back_fenestrations: Vec<RowKey>,
+
+    /// A hash of the geometry of each space and of the outside seen through
+    /// its fenestrations, one per space
+    #[serde(default)]
+    pub spaces: Vec<u64>,
+
+    /// The keys of the workplanes, one per space (or none, if the
+    /// `workplanes` are not set in the [`SolarModelOptions`])
+    #[serde(default)]
+    pub workplanes: Vec<u64>,
+
+    /// The keys of the interior view factors, one per space
+    #[serde(default)]
+    pub interior_view_factors: Vec<u64>,
+
+    /// The keys of the glare views, one per viewpoint
+    #[serde(default)]
+    pub glare_views: Vec<u64>,
+}
+
+/// A surface or a fenestration
+struct Element<'a> {
+    description: String,
+    polygon: &'a Polygon3D,
+    front_boundary: &'a Boundary,
+    back_boundary: &'a Boundary,
+}
+
+/// Describes a construction through its resolved layers (i.e., the properties
+/// of its materials and their substances), so that changing those also
+/// changes the keys of the elements that use it
+fn construction_description(model: &SimpleModel, construction: &str) -> String {
+    let describe = || -> Result<String, String> {
+        let construction = model.get_construction(construction)?;
+        let mut ret = canonical(&*construction);
+        for material in construction.materials.iter() {
+            let material = model.get_material(material)?;
+            let substance = model.get_substance(&material.substance)?;
+            ret.push_str(&format!(
+                "|{}|{}",
+                canonical(&*material),
+                canonical(&substance)
+            ));
+        }
+        Ok(ret)
+    };
+    // Missing data will fail elsewhere... here we just describe it
+    describe().unwrap_or_else(|e| format!("{}|{}", construction, e))
+}
+
+impl RowKeys {
+    /// Calculates the keys of the surfaces and fenestrations of a model
+    pub(crate) fn new(
+        options: &SolarOptions,
+        model_options: &SolarModelOptions,
+        model: &SimpleModel,
+    ) -> Self {
+        let mut elements: Vec<Element> = Vec::new();
+        for (i, s) in model.surfaces.iter().enumerate() {
+            elements.push(Element {
+                description: format!(
+                    "surface|{}|{}|{}|{}|{}|{}\n",
+                    i,
+                    s.name,
+                    construction_description(model, &s.construction),
+                    canonical(&s.vertices),
+                    canonical(&s.front_boundary),
+                    canonical(&s.back_boundary)
+                ),
+                polygon: &s.vertices,
+                front_boundary: &s.front_boundary,
+                back_boundary: &s.back_boundary,
+            });
+        }
+        for (i, s) in model.fenestrations.iter().enumerate() {
+            elements.push(Element {
+                description: format!(
+                    "fenestration|{}|{}|{}|{}|{}|{}\n",
+                    i,
+                    s.name,
+                    construction_description(model, &s.construction),
+                    canonical(&s.vertices),
+                    canonical(&s.front_boundary),
+                    canonical(&s.back_boundary)
+                ),
+                polygon: &s.vertices,
+                front_boundary: &s.front_boundary,
+                back_boundary: &s.back_boundary,
+            });
+        }
+
+        let calculation_options = serde_json::json!({
+            "mf": options.solar_sky_discretization_or(crate::model::MODULE_NAME, 1),
+            "n_solar_rays": options.n_solar_irradiance_points_or(crate::model::MODULE_NAME, 10),
+            "ambient_divitions": options.solar_ambient_divitions_or(crate::model::MODULE_NAME, 300),
+            "direct_sun_sky_discretization": model_options.direct_sun_sky_discretization,
+            "solar_max_depth": model_options.solar_max_depth,
+            "ir_view_factor_samples": model_options.ir_view_factor_samples,
+            "view_factor_mode": model_options.view_factor_mode,
+        })
+        .to_string();
+
+        // With inter-reflections, everything is part of the surroundings
+        let whole_model = if model_options.solar_max_depth > 0 {
+            let mut content: String = elements.iter().map(|e| e.description.as_str()).collect();
+            content.push_str(&format!(
+                "{}|{}|{}",
+                canonical(&model.constructions),
+                canonical(&model.materials),
+                canonical(&model.substances)
+            ));
+            Some(content)
+        } else {
+            None
+        };
+
+        let key = |i: usize, front: bool| -> u64 {
+            let element = &elements[i];
+            let mut content = format!(
+                "{}|{}|{}",
+                calculation_options,
+                if front { "front" } else { "back" },
+                element.description
+            );
+            match &whole_model {
+                Some(whole_model) => content.push_str(whole_model),
+                None => {
+                    let normal = if front {
+                        element.polygon.normal()
+                    } else {
+                        element.polygon.normal() * -1.
+                    };
+                    let origin = element.polygon.outer().vertices()[0];
+                    for (j, other) in elements.iter().enumerate() {
+                        let in_front = other
+                            .polygon
+                            .outer()
+                            .vertices()
+                            .iter()
+                            .any(|v| (*v - origin) * normal > CONTEXT_TOLERANCE);
+                        if j != i && in_front {
+                            content.push_str(&other.description);
+                        }
+                    }
+                }
+            }
+            fnv1a(content.as_bytes())
+        };
+
+        let n_surfaces = model.surfaces.len();
+        let keys = |range: std::ops::Range<usize>, front: bool| -> Vec<RowKey> {
+            let mut n_rows = 0;
+            range
+                .map(|i| {
+                    let boundary = if front {
+                        elements[i].front_boundary
+                    } else {
+                        elements[i].back_boundary
+                    };
+                    let row = if SolarSurface::boundary_receives_sun(boundary) {
+                        n_rows += 1;
+                        Some(n_rows - 1)
+                    } else {
+                        None
+                    };
+                    RowKey {
+                        key: key(i, front),
+                        row,
+                    }
+                })
+                .collect()
+        };
+
+        // The geometry of each space: what bounds it, and what is inside
+        // it. The view from the space also includes what lies in front of
+        // the outer side of its fenestrations.
+        let mut enclosures = Vec::with_capacity(model.spaces.len());
+        let mut spaces = Vec::with_capacity(model.spaces.len());
+        for space in model.spaces.iter() {
+            let faces = |boundary: &Boundary| matches!(boundary, Boundary::Space { space: s } if s == &space.name);
+            let mut enclosure = format!("space|{}\n", space.name);
+            let mut view = String::new();
+            let mut bounding = vec![false; elements.len()];
+            let (mut min, mut max) = (
+                Point3D::new(Float::MAX, Float::MAX, Float::MAX),
+                Point3D::new(Float::MIN, Float::MIN, Float::MIN),
+            );
+            for (i, element) in elements.iter().enumerate() {
+                let (front, back) = (faces(element.front_boundary), faces(element.back_boundary));
+                if !front && !back {
+                    continue;
+                }
+                bounding[i] = true;
+                enclosure.push_str(&element.description);
+                for v in element.polygon.outer().vertices() {
+                    min = Point3D::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z));
+                    max = Point3D::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z));
+                }
+                if i >= n_surfaces {
+                    if !front {
+                        view.push_str(&format!("{}|", key(i, true)));
+                    }
+                    if !back {
+                        view.push_str(&format!("{}|", key(i, false)));
+                    }
+                }
+            }
+            let inside = |v: &Point3D| {
+                v.x > min.x + CONTEXT_TOLERANCE
+                    && v.x < max.x - CONTEXT_TOLERANCE
+                    && v.y > min.y + CONTEXT_TOLERANCE
+                    && v.y < max.y - CONTEXT_TOLERANCE
+                    && v.z > min.z + CONTEXT_TOLERANCE
+                    && v.z < max.z - CONTEXT_TOLERANCE
+            };
+            for (i, element) in elements.iter().enumerate() {
+                if !bounding[i] && element.polygon.outer().vertices().iter().any(inside) {
+                    enclosure.push_str(&element.description);
+                }
+            }
+            spaces.push(fnv1a(format!("{}{}", enclosure, view).as_bytes()));
+            enclosures.push(enclosure);
+        }
+
+        let workplanes = match &model_options.workplanes {
+            Some(workplane_options) => {
+                let workplane_options = serde_json::json!({
+                    "mf": options.solar_sky_discretization_or(crate::model::MODULE_NAME, 1),
+                    "ambient_divitions": options.solar_ambient_divitions_or(crate::model::MODULE_NAME, 300),
+                    "direct_sun_sky_discretization": model_options.direct_sun_sky_discretization,
+                    "workplanes": workplane_options,
+                });
+                spaces
+                    .iter()
+                    .map(|space| fnv1a(format!("{}|{}", workplane_options, space).as_bytes()))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        // The rays stay inside the space, so only its enclosure matters
+        let interior_options = serde_json::json!({
+            "interior_view_factor_points": model_options.interior_view_factor_points,
+            "interior_view_factor_samples": model_options.interior_view_factor_samples,
+        });
+        let interior_view_factors = enclosures
+            .iter()
+            .map(|enclosure| fnv1a(format!("{}|{}", interior_options, enclosure).as_bytes()))
+            .collect();
+
+        Self {
+            front_surfaces: keys(0..n_surfaces, true),
+            back_surfaces: keys(0..n_surfaces, false),
+            front_fenestrations: keys(n_surfaces..elements.len(), true),
+            back_fenestrations: keys(n_surfaces..elements.len(), false),
+            spaces,
+            workplanes,
+            interior_view_factors,
+            // These depend on the space that contains each viewpoint, which
+            // is found when calculating them (see `glare_view_key()`)
+            glare_views: Vec::new(),
+        }
+    }
+
+    /// Calculates the key of a glare view, given the `context` it depends on
+    /// (i.e., the key of the space that contains its viewpoint or, if that
+    /// space is unknown, a hash of the whole model)
+    pub(crate) fn glare_view_key(
+        context: u64,
+        viewpoint: &Viewpoint,
+        glare_options: &GlareOptions,
+        options: &SolarOptions,
+    ) -> u64 {
+        let content = serde_json::json!({
+            "context": context,
+            "viewpoint": viewpoint,
+            "resolution": glare_options.resolution,
+            "max_depth": glare_options.max_depth,
+            "mf": options.solar_sky_discretization_or(crate::model::MODULE_NAME, 1),
+            "ambient_divitions": options.solar_ambient_divitions_or(crate::model::MODULE_NAME, 300),
+        });
+        fnv1a(content.to_string().as_bytes())
+    }
+}
+
+/// The results calculated for one side of a surface or fenestration
+struct CachedRow {
+    dc: Option<Matrix>,
+    sun_dc: Option<Matrix>,
+    view_factors: IRViewFactorSet,
+}
+
+/// The workplane calculated for a space, and its Daylight Coefficients
+struct CachedWorkplane {
+    workplane: Workplane,
+    dc: Matrix,
+    sun_dc: Matrix,
+}
+
+/// The results of a previous [`OpticalInfo`], indexed by their [`RowKey`]
+/// (or by the keys of the spaces and glare views)
+#[derive(Default)]
+pub(crate) struct RowCache {
+    rows: HashMap<u64, CachedRow>,
+    workplanes: HashMap<u64, CachedWorkplane>,
+    interior_view_factors: HashMap<u64, InteriorViewFactors>,
+    glare_views: HashMap<u64, GlareView>,
+}
+
+/// Copies one row of a matrix, if it exists
+fn matrix_row(matrix: &Matrix, row: usize) -> Result<Option<Matrix>, String> {
+    let (nrows, ncols) = matrix.size();
+    if row >= nrows {
+        return Ok(None);
+    }
+    let mut ret = Matrix::new(0.0, 1, ncols);
+    for col in 0..ncols {
+        ret.set(0, col, matrix.get(row, col)?)?;
+    }
+    Ok(Some(ret))
+}
+
+impl RowCache {
+    /// Indexes the results of a previous [`OpticalInfo`]. Nothing is reused
+    /// from information written with a different [`OPTICAL_INFO_FORMAT_VERSION`],
+    /// as the way of calculating it has changed.
+    pub(crate) fn new(previous: &OpticalInfo) -> Result<Self, String> {
+        let mut ret = Self::default();
+        if previous.signature.format_version != OPTICAL_INFO_FORMAT_VERSION {
+            return Ok(ret);
+        }
+        let keys = &previous.signature.row_keys;
+        ret.add(
+            &keys.front_surfaces,
+            &previous.front_surfaces_dc,
+            &previous.front_surfaces_sun_dc,
+            &previous.front_surfaces_view_factors,
+        )?;
+        ret.add(
+            &keys.back_surfaces,
+            &previous.back_surfaces_dc,
+            &previous.back_surfaces_sun_dc,
+            &previous.back_surfaces_view_factors,
+        )?;
+        ret.add(
+            &keys.front_fenestrations,
+            &previous.front_fenestrations_dc,
+            &previous.front_fenestrations_sun_dc,
+            &previous.front_fenestrations_view_factors,
+        )?;
+        ret.add(
+            &keys.back_fenestrations,
+            &previous.back_fenestrations_dc,
+            &previous.back_fenestrations_sun_dc,
+            &previous.back_fenestrations_view_factors,
+        )?;
+
+        // Files without keys (or inconsistent ones) cannot be reused
+        if keys.workplanes.len() == previous.workplanes.len()
+            && keys.workplanes.len() == previous.workplanes_dc.len()
+            && keys.workplanes.len() == previous.workplanes_sun_dc.len()
+        {
+            for (i, key) in keys.workplanes.iter().enumerate() {
+                ret.workplanes.insert(
+                    *key,
+                    CachedWorkplane {
+                        workplane: previous.workplanes[i].clone(),
+                        dc: previous.workplanes_dc[i].clone(),
+                        sun_dc: previous.workplanes_sun_dc[i].clone(),
+                    },
+                );
+            }
+        }
+        if keys.interior_view_factors.len() == previous.interior_view_factors.len() {
+            for (key, v) in keys
+                .interior_view_factors
+                .iter()
+                .zip(previous.interior_view_factors.iter())
+            {
+                ret.interior_view_factors.insert(*key, v.clone());
+            }
+        }
+        if keys.glare_views.len() == previous.glare_views.len() {
+            for (key, v) in keys.glare_views.iter().zip(previous.glare_views.iter()) {
+                ret.glare_views.insert(*key, v.clone());
+            }
+        }
+        Ok(ret)
+    }
+
+    fn add(
+        &mut self,
+        keys: &[RowKey],
+        dc: &Matrix,
+        sun_dc: &Matrix,
+        view_factors: &[IRViewFactorSet],
+    ) -> Result<(), String> {
+        // Files without keys (or inconsistent ones) cannot be reused
+        if keys.len() != view_factors.len() {
+            return Ok(());
+        }
+        for (key, view_factors) in keys.iter().zip(view_factors.iter()) {
+            let (dc, sun_dc) = match key.row {
+                Some(row) => (matrix_row(dc, row)?, matrix_row(sun_dc, row)?),
+                None => (None, None),
+            };
+            self.rows.insert(
+                key.key,
+                CachedRow {
+                    dc,
+                    sun_dc,
+                    view_factors: view_factors.clone(),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Gets the stored Daylight Coefficients row of each of the `keys`, if any
+    pub(crate) fn dc_rows(&self, keys: &[RowKey]) -> Vec<Option<Matrix>> {
+        keys.iter()
+            .map(|k| self.rows.get(&k.key).and_then(|r| r.dc.clone()))
+            .collect()
+    }
+
+    /// Gets the stored direct-sun Daylight Coefficients row of each of the `keys`, if any
+    pub(crate) fn sun_dc_rows(&self, keys: &[RowKey]) -> Vec<Option<Matrix>> {
+        keys.iter()
+            .map(|k| self.rows.get(&k.key).and_then(|r| r.sun_dc.clone()))
+            .collect()
+    }
+
+    /// Gets the stored view factors of a side, if any
+    pub(crate) fn view_factors(&self, key: &RowKey) -> Option<&IRViewFactorSet> {
+        self.rows.get(&key.key).map(|r| &r.view_factors)
+    }
+
+    /// Gets the stored workplane with the given key, and its Daylight
+    /// Coefficients and direct-sun Daylight Coefficients, if any
+    pub(crate) fn workplane(&self, key: u64) -> Option<(Workplane, Matrix, Matrix)> {
+        self.workplanes
+            .get(&key)
+            .map(|w| (w.workplane.clone(), w.dc.clone(), w.sun_dc.clone()))
+    }
+
+    /// Gets the stored interior view factors of each space, given their keys
+    pub(crate) fn interior_view_factors(&self, keys: &[u64]) -> Vec<Option<InteriorViewFactors>> {
+        keys.iter()
+            .map(|k| self.interior_view_factors.get(k).cloned())
+            .collect()
+    }
+
+    /// Gets the stored glare view with the given key, if any
+    pub(crate) fn glare_view(&self, key: u64) -> Option<&GlareView> {
+        self.glare_views.get(&key)
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::WorkplaneOptions;
+    use geometry3d::Vector3D;
+    use simple_model::{
+        substance::Normal, Construction, Material, SimulationStateHeader, Substance, Surface,
+    };
+
+    /// A horizontal square of 1x1m at height `z`, facing up (or down)
+    fn square(name: &str, z: Float, up: bool) -> Surface {
+        let vertices = if up {
+            format!("0, 0, {z}, 1, 0, {z}, 1, 1, {z}, 0, 1, {z}")
+        } else {
+            format!("0, 0, {z}, 0, 1, {z}, 1, 1, {z}, 1, 0, {z}")
+        };
+        json5::from_str(&format!(
+            "{{
+                name: '{name}',
+                construction: 'the construction',
+                vertices: [{vertices}]
+            }}"
+        ))
+        .unwrap()
+    }
+
+    /// A floor, a shade over it at height `shade_z`, and a surface far
+    /// below them, facing down
+    fn model(shade_z: Float) -> SimpleModel {
+        model_with_substance(shade_z, Normal::new("the substance").wrap())
+    }
+
+    /// Like `model()`, but all the surfaces are made of `substance`, which
+    /// needs to be called "the substance"
+    fn model_with_substance(shade_z: Float, substance: Substance) -> SimpleModel {
+        let mut model = SimpleModel::default();
+        model.add_substance(substance);
+        model.add_material(Material::new("the material", "the substance", 0.1));
+        let mut construction = Construction::new("the construction");
+        construction.materials.push("the material".into());
+        model.add_construction(construction);
+
+        model.add_surface(square("floor", 0., true));
+        model.add_surface(square("shade", shade_z, true));
+        model.add_surface(square("far", -5., false));
+        model
+    }
+
+    fn options() -> SolarOptions {
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(1);
+        options.set_solar_ambient_divitions(1);
+        options.set_solar_sky_discretization(1);
+        options
+    }
+
+    #[test]
+    fn test_row_keys() {
+        let options = options();
+        let model_options = SolarModelOptions::default();
+        let before = RowKeys::new(&options, &model_options, &model(2.));
+        let after = RowKeys::new(&options, &model_options, &model(3.));
+        assert_eq!(before.front_surfaces.len(), 3);
+        assert_eq!(before.front_surfaces[1].row, Some(1));
+
+        // The floor sees the shade with its front...
+        assert_ne!(before.front_surfaces[0], after.front_surfaces[0]);
+        // ... but not with its back, which faces down
+        assert_eq!(before.back_surfaces[0], after.back_surfaces[0]);
+        // The shade itself changed
+        assert_ne!(before.front_surfaces[1], after.front_surfaces[1]);
+        assert_ne!(before.back_surfaces[1], after.back_surfaces[1]);
+        // The far surface only sees the shade with its back
+        assert_eq!(before.front_surfaces[2], after.front_surfaces[2]);
+        assert_ne!(before.back_surfaces[2], after.back_surfaces[2]);
+
+        // Same model, same keys
+        let again = RowKeys::new(&options, &model_options, &model(2.));
+        assert_eq!(before, again);
+
+        // With inter-reflections, everything is affected
+        let bounces = SolarModelOptions {
+            solar_max_depth: 1,
+            ..SolarModelOptions::default()
+        };
+        let before = RowKeys::new(&options, &bounces, &model(2.));
+        let after = RowKeys::new(&options, &bounces, &model(3.));
+        assert_ne!(before.back_surfaces[0], after.back_surfaces[0]);
+        assert_ne!(before.front_surfaces[2], after.front_surfaces[2]);
+
+        // Changing the properties of a substance, but not its name
+        let transparent: Substance = json5::from_str(
+            "{
+                type: 'Normal',
+                name: 'the substance',
+                solar_transmittance: 0.5,
+            }",
+        )
+        .unwrap();
+        let before = RowKeys::new(&options, &model_options, &model(2.));
+        let after = RowKeys::new(
+            &options,
+            &model_options,
+            &model_with_substance(2., transparent),
+        );
+        for (before, after) in before.back_surfaces.iter().zip(after.back_surfaces.iter()) {
+            assert_ne!(before, after);
+        }
+    }
+
+    #[test]
+    fn test_reuse() {
+        let options = options();
+        let model_options = SolarModelOptions::default();
+        let before = model(2.);
+        let mut state = SimulationStateHeader::new();
+        let mut previous = OpticalInfo::new(&options, &model_options, &before, &mut state).unwrap();
+
+        // Mark what was calculated before
+        let marker = -123.;
+        for row in 0..3 {
+            previous.front_surfaces_dc.set(row, 0, marker).unwrap();
+            previous.back_surfaces_dc.set(row, 0, marker).unwrap();
+            previous.front_surfaces_view_factors[row].sky = marker;
+            previous.back_surfaces_view_factors[row].sky = marker;
+        }
+
+        let after = model(3.);
+        let mut state = SimulationStateHeader::new();
+        let info =
+            OpticalInfo::new_from_previous(&previous, &options, &model_options, &after, &mut state)
+                .unwrap();
+        assert_eq!(
+            info.front_surfaces_dc.size(),
+            previous.front_surfaces_dc.size()
+        );
+
+        let reused = |dc: &Matrix, vfs: &[IRViewFactorSet], row: usize| {
+            let dc_reused = dc.get(row, 0).unwrap() == marker;
+            let vf_reused = vfs[row].sky == marker;
+            assert_eq!(dc_reused, vf_reused);
+            dc_reused
+        };
+        let front = |row| {
+            reused(
+                &info.front_surfaces_dc,
+                &info.front_surfaces_view_factors,
+                row,
+            )
+        };
+        let back = |row| {
+            reused(
+                &info.back_surfaces_dc,
+                &info.back_surfaces_view_factors,
+                row,
+            )
+        };
+        assert!(!front(0));
+        assert!(back(0));
+        assert!(!front(1));
+        assert!(!back(1));
+        assert!(front(2));
+        assert!(!back(2));
+
+        // Nothing is reused from a file written with another format version
+        let mut other_version = previous.clone();
+        other_version.signature.format_version = OPTICAL_INFO_FORMAT_VERSION + 1;
+        let mut state = SimulationStateHeader::new();
+        let info = OpticalInfo::new_from_previous(
+            &other_version,
+            &options,
+            &model_options,
+            &before,
+            &mut state,
+        )
+        .unwrap();
+        assert!((0..3).all(|row| info.back_surfaces_dc.get(row, 0).unwrap() != marker));
+
+        // Nothing is reused from a file without keys
+        previous.signature = Default::default();
+        let mut state = SimulationStateHeader::new();
+        let info = OpticalInfo::new_from_previous(
+            &previous,
+            &options,
+            &model_options,
+            &before,
+            &mut state,
+        )
+        .unwrap();
+        assert!((0..3).all(|row| info.back_surfaces_dc.get(row, 0).unwrap() != marker));
+    }
+
+    #[test]
+    fn test_reuse_spaces() {
+        // The room, and a shade at a certain distance from its south façade
+        // (i.e., in front of its window if `y` is negative)
+        let room = |y: Float| -> (SimpleModel, SimulationStateHeader) {
+            let (mut model, state) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+            let shade: Surface = json5::from_str(&format!(
+                "{{
+                    name: 'shade',
+                    construction: 'wall',
+                    vertices: [1, {y}, 0, 3, {y}, 0, 3, {y}, 3, 1, {y}, 3]
+                }}"
+            ))
+            .unwrap();
+            model.add_surface(shade);
+            (model, state)
+        };
+        let options = options();
+        let model_options = SolarModelOptions {
+            workplanes: Some(WorkplaneOptions {
+                spacing: 1.0,
+                max_depth: 0,
+                ..WorkplaneOptions::default()
+            }),
+            glare: Some(GlareOptions {
+                viewpoints: vec![Viewpoint {
+                    name: "desk".into(),
+                    position: Point3D::new(2., 2., 1.2),
+                    direction: Vector3D::new(0., -1., 0.),
+                }],
+                resolution: 4,
+                max_depth: 0,
+                ..GlareOptions::default()
+            }),
+            interior_view_factor_points: 1,
+            interior_view_factor_samples: 10,
+            ..SolarModelOptions::default()
+        };
+
+        // Mark what was calculated before, returning whether the workplane,
+        // the glare view and the interior view factors were reused
+        let marker = -123.;
+        let reused = |before: Float, after: Float| -> (bool, bool, bool) {
+            let (model, mut state) = room(before);
+            let mut previous =
+                OpticalInfo::new(&options, &model_options, &model, &mut state).unwrap();
+            previous.workplanes_dc[0].set(0, 0, marker).unwrap();
+            previous.glare_views[0].dc.set(0, 0, marker).unwrap();
+            previous.interior_view_factors[0].view_factors[0][0] = marker;
+
+            let (model, mut state) = room(after);
+            let info = OpticalInfo::new_from_previous(
+                &previous,
+                &options,
+                &model_options,
+                &model,
+                &mut state,
+            )
+            .unwrap();
+            (
+                info.workplanes_dc[0].get(0, 0).unwrap() == marker,
+                info.glare_views[0].dc.get(0, 0).unwrap() == marker,
+                info.interior_view_factors[0].view_factors[0][0] == marker,
+            )
+        };
+
+        // A shade behind the room cannot be seen from inside
+        assert_eq!(reused(10., 12.), (true, true, true));
+
+        // A shade in front of the window changes the view, but not the
+        // view factors between the sides of the room
+        assert_eq!(reused(-2., -3.), (false, false, true));
+    }
+}
@@ -0,0 +1,598 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Sky models that can be used instead of the Perez all-weather sky when
+//! building the sky vectors (e.g., for comparing results with other tools).
+//! They produce vectors with the same shape as `PerezSky::gen_sky_vec()`: the
+//! ground in the first bin, followed by the patches of a Reinhart sky.
+
+use crate::daylight::WHITE_EFFICACY;
+use crate::decomposition::extraterrestrial_irradiance;
+use crate::{Float, PI};
+use calendar::Date;
+use geometry3d::Vector3D;
+use matrix::Matrix;
+use serde::{Deserialize, Serialize};
+use solar::{ReinhartSky, SkyUnits, Solar, Time};
+use weather::CurrentWeather;
+
+/// The parameters `a`, `b` (gradation) and `c`, `d`, `e` (indicatrix) of the
+/// 15 CIE general skies (ISO 15469:2004 / CIE S 011/E:2003)
+const CIE_GENERAL_SKIES: [[Float; 5]; 15] = [
+    [4.0, -0.70, 0.0, -1.0, 0.00],
+    [4.0, -0.70, 2.0, -1.5, 0.15],
+    [1.1, -0.80, 0.0, -1.0, 0.00],
+    [1.1, -0.80, 2.0, -1.5, 0.15],
+    [0.0, -1.00, 0.0, -1.0, 0.00],
+    [0.0, -1.00, 2.0, -1.5, 0.15],
+    [0.0, -1.00, 5.0, -2.5, 0.30],
+    [0.0, -1.00, 10.0, -3.0, 0.45],
+    [-1.0, -0.55, 2.0, -1.5, 0.15],
+    [-1.0, -0.55, 5.0, -2.5, 0.30],
+    [-1.0, -0.55, 10.0, -3.0, 0.45],
+    [-1.0, -0.32, 10.0, -3.0, 0.45],
+    [-1.0, -0.32, 16.0, -3.0, 0.30],
+    [-1.0, -0.15, 16.0, -3.0, 0.30],
+    [-1.0, -0.15, 24.0, -2.8, 0.15],
+];
+
+/// The luminous efficacy (in lm/W) assumed for the direct sun when
+/// building visible sky vectors with these models
+const SUN_LUMINOUS_EFFICACY: Float = 100.;
+
+/// The luminous efficacy (in lm/W) assumed for the diffuse sky when
+/// building visible sky vectors with these models
+const SKY_LUMINOUS_EFFICACY: Float = 120.;
+
+/// The number of sampled directions per Reinhart patch, in each dimension
+const SAMPLES_PER_PATCH: usize = 4;
+
+/// The distribution of the diffuse radiance over the sky
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SkyModel {
+    /// The Perez all-weather sky, built by `PerezSky::gen_sky_vec()`
+    #[default]
+    Perez,
+
+    /// An isotropic sky (i.e., the same radiance in all directions)
+    Uniform,
+
+    /// The CIE standard overcast sky (i.e., CIE general sky 1)
+    CieOvercast,
+
+    /// A partly cloudy sky, rather uniform, with a distinct solar corona
+    /// (i.e., CIE general sky 8)
+    CieIntermediate,
+
+    /// The CIE standard clear sky, with low luminance turbidity
+    /// (i.e., CIE general sky 12)
+    CieClear,
+
+    /// One of the 15 CIE general skies
+    CieGeneral {
+        /// The type of sky, between 1 (overcast) and 15 (clear and turbid)
+        sky_type: usize,
+    },
+
+    /// The model by Hay and Davies (1980): an isotropic sky plus a circumsolar
+    /// component that comes from the direction of the sun. The share of the
+    /// latter is the ratio between the direct normal and the extraterrestrial irradiance.
+    HayDavies,
+
+    /// The Hay-Davies-Klucher-Reindl model (Reindl et al., 1990), which adds
+    /// horizon brightening to the Hay-Davies model. Here, the brightening
+    /// is a band along the lowest row of patches, whose radiance makes vertical
+    /// surfaces receive what the model predicts for them.
+    #[serde(alias = "Reindl")]
+    Hdkr,
+}
+
+impl SkyModel {
+    /// Checks that the model is valid
+    pub fn validate(&self) -> Result<(), String> {
+        if let Self::CieGeneral { sky_type } = self {
+            if !(1..=15).contains(sky_type) {
+                return Err(format!(
+                    "The type of CIE general sky must be between 1 and 15... found {}",
+                    sky_type
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the type of CIE general sky this model corresponds to, if any
+    fn cie_sky_type(&self) -> Option<usize> {
+        match self {
+            Self::CieOvercast => Some(1),
+            Self::CieIntermediate => Some(8),
+            Self::CieClear => Some(12),
+            Self::CieGeneral { sky_type } => Some(*sky_type),
+            _ => None,
+        }
+    }
+}
+
+/// Calculates the luminance of a CIE general sky of a certain type (between
+/// 1 and 15) relative to its zenith luminance. `zenith` is the zenith angle of
+/// the sky element, `sun_zenith` is the one of the sun and `scattering_angle`
+/// is the angle between the sky element and the sun (all in radians).
+pub fn cie_relative_luminance(
+    sky_type: usize,
+    zenith: Float,
+    sun_zenith: Float,
+    scattering_angle: Float,
+) -> Float {
+    let [a, b, c, d, e] = CIE_GENERAL_SKIES[sky_type.clamp(1, 15) - 1];
+    let gradation = |z: Float| {
+        let cos = z.cos();
+        if cos <= 1e-6 {
+            1.
+        } else {
+            1. + a * (b / cos).exp()
+        }
+    };
+    let indicatrix =
+        |x: Float| 1. + c * ((d * x).exp() - (d * PI / 2.).exp()) + e * x.cos().powi(2);
+    indicatrix(scattering_angle) * gradation(zenith) / (indicatrix(sun_zenith) * gradation(0.0))
+}
+
+/// The directions sampled over the patches of a Reinhart sky, used for
+/// integrating the sky models over each patch
+pub(crate) struct SkyPatches {
+    /// The subdivision of the Reinhart sky
+    pub mf: usize,
+
+    /// The Reinhart sky
+    reinhart: ReinhartSky,
+
+    /// The bin, the direction and the solid angle of each sample
+    samples: Vec<(usize, Vector3D, Float)>,
+
+    /// The solid angle of each bin (the first one being the ground)
+    solid_angles: Vec<Float>,
+
+    /// The altitude (in radians) of the top of the lowest row of patches
+    horizon_band: Float,
+}
+
+impl SkyPatches {
+    /// Samples the patches of a Reinhart sky with a certain subdivision
+    pub fn new(mf: usize) -> Self {
+        let reinhart = ReinhartSky::new(mf);
+        let n_bins = ReinhartSky::n_bins(mf);
+
+        // The rows of the Tregenza sky are 12 degrees high (i.e., fewer than 8
+        // fit between the horizon and the zenith), and the lowest one has 30 patches
+        let n_altitudes = 8 * mf * SAMPLES_PER_PATCH;
+        let n_azimuths = 30 * mf * SAMPLES_PER_PATCH;
+        let d_altitude = PI / 2. / n_altitudes as Float;
+        let d_azimuth = 2. * PI / n_azimuths as Float;
+
+        let mut samples = Vec::with_capacity(n_altitudes * n_azimuths);
+        let mut solid_angles = vec![0.0; n_bins];
+        for i in 0..n_altitudes {
+            let altitude = (i as Float + 0.5) * d_altitude;
+            let solid_angle = altitude.cos() * d_altitude * d_azimuth;
+            for j in 0..n_azimuths {
+                let azimuth = (j as Float + 0.5) * d_azimuth;
+                let direction = Vector3D::new(
+                    altitude.cos() * azimuth.sin(),
+                    altitude.cos() * azimuth.cos(),
+                    altitude.sin(),
+                );
+                let bin = reinhart.dir_to_bin(direction);
+                solid_angles[bin] += solid_angle;
+                samples.push((bin, direction, solid_angle));
+            }
+        }
+
+        Self {
+            mf,
+            reinhart,
+            samples,
+            solid_angles,
+            horizon_band: (12. / mf as Float).to_radians(),
+        }
+    }
+
+    /// The solid angle (in sr) of each bin of the sky, the first one being the ground
+    pub fn solid_angles(&self) -> &[Float] {
+        &self.solid_angles
+    }
+
+    /// Builds a sky vector, like `PerezSky::gen_sky_vec()` does, using a
+    /// [`SkyModel`] other than `Perez`. The ground is only added with the sky.
+    #[allow(clippy::too_many_arguments)]
+    pub fn gen_sky_vec(
+        &self,
+        model: SkyModel,
+        solar: &Solar,
+        date: Date,
+        weather_data: &CurrentWeather,
+        units: SkyUnits,
+        albedo: Float,
+        add_sky: bool,
+        add_sun: bool,
+    ) -> Result<Matrix, String> {
+        if model == SkyModel::Perez {
+            return Err("The Perez sky is built by PerezSky::gen_sky_vec()".into());
+        }
+        model.validate()?;
+        let direct_normal = weather_data.direct_normal_radiation.unwrap_or(0.0).max(0.0);
+        let diffuse_horizontal = weather_data
+            .diffuse_horizontal_radiation
+            .unwrap_or(0.0)
+            .max(0.0);
+        let sun = solar
+            .sun_position(Time::Standard(date.day_of_year()))
+            .filter(|direction| direction.z > 0.0);
+        let cos_zenith = sun.map(|direction| direction.z).unwrap_or(0.0);
+        let direct_horizontal = direct_normal * cos_zenith;
+        let (sun_efficacy, sky_efficacy) = if matches!(units, SkyUnits::Visible) {
+            (
+                SUN_LUMINOUS_EFFICACY / WHITE_EFFICACY,
+                SKY_LUMINOUS_EFFICACY / WHITE_EFFICACY,
+            )
+        } else {
+            (1., 1.)
+        };
+
+        // The radiance of each patch, multiplied by its solid angle
+        let mut patch_irradiance = vec![0.0; self.solid_angles.len()];
+        if add_sky {
+            let mut diffuse = diffuse_horizontal;
+
+            // Circumsolar component, which comes from the sun
+            if let (Some(sun), SkyModel::HayDavies | SkyModel::Hdkr) = (sun, model) {
+                let anisotropy = (direct_normal / extraterrestrial_irradiance(date.day_of_year()))
+                    .clamp(0.0, 1.0);
+                let circumsolar = anisotropy * diffuse_horizontal;
+                diffuse -= circumsolar;
+                // Spread over the patch of the sun, delivering the circumsolar
+                // horizontal irradiance
+                let sun_bin = self.reinhart.dir_to_bin(sun);
+                let horizontal: Float = self
+                    .samples
+                    .iter()
+                    .filter(|(bin, ..)| *bin == sun_bin)
+                    .map(|(_, direction, solid_angle)| solid_angle * direction.z)
+                    .sum();
+                if horizontal > 0.0 {
+                    patch_irradiance[sun_bin] +=
+                        circumsolar * self.solid_angles[sun_bin] / horizontal;
+                }
+            }
+
+            // Horizon brightening, which makes vertical surfaces receive
+            // an extra 0.5 * f * sin^3(45 degrees) of the isotropic part
+            if model == SkyModel::Hdkr {
+                let global = direct_horizontal + diffuse_horizontal;
+                if global > 0.0 {
+                    let f = (direct_horizontal / global).sqrt();
+                    let extra = diffuse * 0.5 * f * (PI / 4.).sin().powi(3);
+                    let band = self
+                        .samples
+                        .iter()
+                        .filter(|(_, d, _)| d.z < self.horizon_band.sin());
+                    let vertical: Float = band.clone().map(|(_, d, w)| w * d.y.max(0.0)).sum();
+                    let radiance = extra / vertical;
+                    for (bin, direction, solid_angle) in band {
+                        patch_irradiance[*bin] += radiance * solid_angle;
+                        diffuse -= radiance * solid_angle * direction.z;
+                    }
+                }
+            }
+
+            // The rest of the diffuse radiance, normalized so that it adds up
+            // to the diffuse horizontal irradiance
+            let relative: Vec<Float> = self
+                .samples
+                .iter()
+                .map(|(_, direction, _)| match (model.cie_sky_type(), sun) {
+                    (Some(sky_type), Some(sun)) => cie_relative_luminance(
+                        sky_type,
+                        direction.z.clamp(-1.0, 1.0).acos(),
+                        sun.z.clamp(-1.0, 1.0).acos(),
+                        (*direction * sun).clamp(-1.0, 1.0).acos(),
+                    ),
+                    (Some(sky_type), None) => {
+                        // The sun is below the horizon... only the gradation remains
+                        let zenith = direction.z.clamp(-1.0, 1.0).acos();
+                        cie_relative_luminance(sky_type, zenith, PI / 2., PI / 2.)
+                    }
+                    (None, _) => 1.0,
+                })
+                .collect();
+            let horizontal: Float = self
+                .samples
+                .iter()
+                .zip(relative.iter())
+                .map(|((_, direction, solid_angle), r)| r * solid_angle * direction.z)
+                .sum();
+            if horizontal > 0.0 {
+                let scale = diffuse.max(0.0) / horizontal;
+                for ((bin, _, solid_angle), r) in self.samples.iter().zip(relative.iter()) {
+                    patch_irradiance[*bin] += scale * r * solid_angle;
+                }
+            }
+            patch_irradiance.iter_mut().for_each(|v| *v *= sky_efficacy);
+        }
+
+        // The sun
+        if let (Some(sun), true) = (sun, add_sun) {
+            patch_irradiance[self.reinhart.dir_to_bin(sun)] += sun_efficacy * direct_normal;
+        }
+
+        // Radiance of each patch
+        let mut vec = Matrix::new(0.0, self.solid_angles.len(), 1);
+        for (bin, (irradiance, solid_angle)) in patch_irradiance
+            .iter()
+            .zip(self.solid_angles.iter())
+            .enumerate()
+            .skip(1)
+        {
+            if *solid_angle > 0.0 {
+                vec.set(bin, 0, irradiance / solid_angle)?;
+            }
+        }
+
+        // The ground, a lambertian reflector
+        if add_sky {
+            let ground = albedo
+                * (sun_efficacy * direct_horizontal + sky_efficacy * diffuse_horizontal)
+                / PI;
+            vec.set(0, 0, ground)?;
+        }
+        Ok(vec)
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use schedule::ScheduleConstant;
+    use validate::assert_close;
+    use weather::{SyntheticWeather, Weather};
+
+    /// Barcelona
+    fn solar() -> Solar {
+        Solar::new(
+            (41.41 as Float).to_radians(),
+            -(2.18 as Float).to_radians(),
+            -(15. as Float).to_radians(),
+        )
+    }
+
+    fn weather_data(date: Date, direct_normal: Float, diffuse_horizontal: Float) -> CurrentWeather {
+        let mut weather = SyntheticWeather::default();
+        weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(24.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(direct_normal));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(diffuse_horizontal));
+        weather.get_weather_data(date)
+    }
+
+    /// Integrates the irradiance that a sky vector delivers to a plane
+    fn irradiance(patches: &SkyPatches, vec: &Matrix, normal: Vector3D) -> Float {
+        patches
+            .samples
+            .iter()
+            .map(|(bin, direction, solid_angle)| {
+                vec.get(*bin, 0).unwrap() * solid_angle * (*direction * normal).max(0.0)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_cie_relative_luminance() {
+        for sky_type in 1..=15 {
+            let sun_zenith = (40. as Float).to_radians();
+            assert_close!(
+                cie_relative_luminance(sky_type, 0.0, sun_zenith, sun_zenith),
+                1.
+            );
+        }
+        // The overcast sky is about three times brighter at the zenith than at the horizon
+        let horizon = cie_relative_luminance(1, PI / 2., 0.5, 1.0);
+        assert_close!(horizon, 1. / (1. + 4. * (-0.7 as Float).exp()), 1e-4);
+
+        // Clear skies are brighter around the sun
+        let sun_zenith = (40. as Float).to_radians();
+        let near = cie_relative_luminance(12, 0.8, sun_zenith, 0.1);
+        let far = cie_relative_luminance(12, 0.8, sun_zenith, 2.0);
+        assert!(near > 3. * far, "near = {}, far = {}", near, far);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(SkyModel::CieGeneral { sky_type: 15 }.validate().is_ok());
+        assert!(SkyModel::CieGeneral { sky_type: 0 }.validate().is_err());
+        assert!(SkyModel::CieGeneral { sky_type: 16 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_sky_vectors() {
+        let date = Date {
+            month: 3,
+            day: 21,
+            hour: 12.,
+        };
+        let (direct_normal, diffuse_horizontal, albedo) = (600., 150., 0.2);
+        let data = weather_data(date, direct_normal, diffuse_horizontal);
+        let solar = solar();
+        let sun = solar
+            .sun_position(Time::Standard(date.day_of_year()))
+            .unwrap();
+        let global = direct_normal * sun.z + diffuse_horizontal;
+        let up = Vector3D::new(0., 0., 1.);
+
+        for mf in [1, 2] {
+            let patches = SkyPatches::new(mf);
+            assert!(patches.solid_angles.iter().skip(1).all(|v| *v > 0.0));
+            assert_close!(patches.solid_angles.iter().sum::<Float>(), 2. * PI, 1e-3);
+
+            for model in [
+                SkyModel::Uniform,
+                SkyModel::CieOvercast,
+                SkyModel::CieIntermediate,
+                SkyModel::CieClear,
+                SkyModel::CieGeneral { sky_type: 5 },
+                SkyModel::HayDavies,
+                SkyModel::Hdkr,
+            ] {
+                let gen = |add_sky: bool, add_sun: bool| {
+                    patches
+                        .gen_sky_vec(
+                            model,
+                            &solar,
+                            date,
+                            &data,
+                            SkyUnits::Solar,
+                            albedo,
+                            add_sky,
+                            add_sun,
+                        )
+                        .unwrap()
+                };
+
+                // Same shape as the Perez sky
+                let all = gen(true, true);
+                let n_bins = ReinhartSky::n_bins(mf);
+                assert_eq!(all.size(), (n_bins, 1));
+
+                // The ground
+                assert_close!(all.get(0, 0).unwrap(), albedo * global / PI, 1e-3);
+
+                // The sky adds up to the diffuse horizontal irradiance
+                let sky = gen(true, false);
+                assert_close!(irradiance(&patches, &sky, up), diffuse_horizontal, 1e-2);
+
+                // The sun is in a single patch, without ground
+                let sun_only = gen(false, true);
+                let sun_bin = patches.reinhart.dir_to_bin(sun);
+                for bin in 0..n_bins {
+                    let exp = if bin == sun_bin {
+                        direct_normal / patches.solid_angles[bin]
+                    } else {
+                        0.0
+                    };
+                    assert_close!(sun_only.get(bin, 0).unwrap(), exp, 1e-3);
+                }
+
+                // Both together
+                for bin in 1..n_bins {
+                    assert_close!(
+                        all.get(bin, 0).unwrap(),
+                        sky.get(bin, 0).unwrap() + sun_only.get(bin, 0).unwrap(),
+                        1e-3
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_uniform_and_anisotropic() {
+        let date = Date {
+            month: 6,
+            day: 21,
+            hour: 10.,
+        };
+        let (direct_normal, diffuse_horizontal) = (700., 120.);
+        let data = weather_data(date, direct_normal, diffuse_horizontal);
+        let solar = solar();
+        let sun = solar
+            .sun_position(Time::Standard(date.day_of_year()))
+            .unwrap();
+        // A fine subdivision, so the circumsolar patch is small
+        let patches = SkyPatches::new(4);
+        let gen = |model: SkyModel| {
+            patches
+                .gen_sky_vec(
+                    model,
+                    &solar,
+                    date,
+                    &data,
+                    SkyUnits::Solar,
+                    0.0,
+                    true,
+                    false,
+                )
+                .unwrap()
+        };
+
+        // Isotropic radiance
+        let uniform = gen(SkyModel::Uniform);
+        for bin in 1..ReinhartSky::n_bins(4) {
+            assert_close!(uniform.get(bin, 0).unwrap(), diffuse_horizontal / PI, 1e-2);
+        }
+
+        // A vertical surface facing the sun
+        let normal = Vector3D::new(sun.x, sun.y, 0.0).get_normalized();
+        let cos_incidence = sun * normal;
+        let anisotropy = direct_normal / extraterrestrial_irradiance(date.day_of_year());
+        let isotropic = (1. - anisotropy) * diffuse_horizontal * 0.5;
+        let circumsolar = anisotropy * diffuse_horizontal * cos_incidence / sun.z;
+
+        let hay_davies = irradiance(&patches, &gen(SkyModel::HayDavies), normal);
+        assert_close!(hay_davies, isotropic + circumsolar, 0.05);
+
+        let f = (direct_normal * sun.z / (direct_normal * sun.z + diffuse_horizontal)).sqrt();
+        let brightening = 1. + f * (PI / 4.).sin().powi(3);
+        let hdkr = irradiance(&patches, &gen(SkyModel::Hdkr), normal);
+        assert_close!(hdkr, isotropic * brightening + circumsolar, 0.05);
+
+        // Visible units
+        let visible = patches
+            .gen_sky_vec(
+                SkyModel::Uniform,
+                &solar,
+                date,
+                &data,
+                SkyUnits::Visible,
+                0.0,
+                true,
+                false,
+            )
+            .unwrap();
+        assert_close!(
+            WHITE_EFFICACY * visible.get(1, 0).unwrap(),
+            SKY_LUMINOUS_EFFICACY * diffuse_horizontal / PI,
+            1e-2
+        );
+
+        // Perez is not built here
+        assert!(patches
+            .gen_sky_vec(
+                SkyModel::Perez,
+                &solar,
+                date,
+                &data,
+                SkyUnits::Solar,
+                0.0,
+                true,
+                true
+            )
+            .is_err());
+    }
+}
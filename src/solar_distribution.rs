@@ -0,0 +1,415 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Calculates the solar radiation transmitted through fenestrations and
+//! distributes it over the interior surfaces of each space.
+//!
+//! The beam (i.e., direct sun) radiation lands on the floors of the space, which
+//! absorb part of it and reflect the rest diffusely. The diffuse radiation (transmitted
+//! and reflected) is absorbed by all the surfaces bounding the space, proportionally to
+//! their area and solar absorbtance. This is what EnergyPlus calls `FullExterior`
+//! distribution.
+
+use crate::model::SolarModelMemory;
+use crate::solar_surface::SolarSurface;
+use crate::{materials, Float, PI};
+use geometry3d::Vector3D;
+use simple_model::{Boundary, SimpleModel};
+
+/// The coefficient of the ASHRAE incidence angle modifier used for
+/// calculating the angular dependence of the transmittance of glazing
+const B0: Float = 0.1;
+
+/// The minimum vertical component of the normal of the interior side
+/// of a surface for it to be considered a floor
+pub(crate) const FLOOR_MIN_Z: Float = 0.7;
+
+/// Calculates the solar transmittance of a glazing for a certain angle of
+/// incidence, based on its transmittance at normal incidence. This uses the
+/// ASHRAE incidence angle modifier:
+///
+/// $$ \tau(\theta) = \tau_n \left(1 - b_0 \left(\frac{1}{\cos(\theta)} - 1\right)\right) $$
+pub(crate) fn angular_transmittance(normal_transmittance: Float, cos_theta: Float) -> Float {
+    if cos_theta <= 1e-3 {
+        return 0.0;
+    }
+    let modifier = 1. - B0 * (1. / cos_theta - 1.);
+    normal_transmittance * modifier.clamp(0.0, 1.0)
+}
+
+/// Calculates the transmittance of a glazing for isotropic diffuse radiation, by
+/// integrating the [`angular_transmittance`] over the hemisphere:
+///
+/// $$ \tau_d = 2 \int_0^{\pi/2} \tau(\theta) \cos(\theta) \sin(\theta) d\theta $$
+pub(crate) fn diffuse_transmittance(normal_transmittance: Float) -> Float {
+    const N: usize = 90;
+    let d_theta = 0.5 * PI / N as Float;
+    (0..N)
+        .map(|i| {
+            let theta = (i as Float + 0.5) * d_theta;
+            let (sin, cos) = theta.sin_cos();
+            2. * angular_transmittance(normal_transmittance, cos) * cos * sin * d_theta
+        })
+        .sum()
+}
+
+/// A fenestration that lets solar radiation from the outside into a space
+#[derive(Debug, Clone)]
+pub(crate) struct TransmittingFenestration {
+    /// The index of the fenestration in the model
+    pub index: usize,
+
+    /// Whether the outdoor side of the fenestration is the front one
+    pub outdoor_front: bool,
+
+    /// The row of the outdoor side in the corresponding Daylight Coefficient matrix
+    pub dc_row: usize,
+
+    /// The normal of the outdoor side, pointing outwards
+    pub outward_normal: Vector3D,
+
+    /// The area of the fenestration
+    pub area: Float,
+
+    /// The solar transmittance at normal incidence
+    pub normal_transmittance: Float,
+
+    /// The index of the space that receives the radiation
+    pub space: usize,
+}
+
+/// A side of a surface that faces a space
+#[derive(Debug, Clone)]
+pub(crate) struct InteriorSide {
+    /// The index of the surface in the model
+    pub index: usize,
+
+    /// Whether this is the front side of the surface
+    pub front: bool,
+
+    /// The area of the surface
+    pub area: Float,
+
+    /// The solar absorbtance of this side
+    pub absorbtance: Float,
+
+    /// Whether this side is a floor (i.e., faces up)
+    pub is_floor: bool,
+}
+
+/// The information required for distributing the solar radiation transmitted
+/// through the fenestrations of a model.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SolarDistribution {
+    /// The fenestrations that let solar radiation into spaces
+    pub fenestrations: Vec<TransmittingFenestration>,
+
+    /// The interior sides of the surfaces bounding each space
+    pub spaces: Vec<Vec<InteriorSide>>,
+}
+
+impl SolarDistribution {
+    /// Gets the index of the space a boundary points to, if any
+    pub(crate) fn space_index(
+        model: &SimpleModel,
+        boundary: &Boundary,
+    ) -> Result<Option<usize>, String> {
+        if let Boundary::Space { space } = boundary {
+            match model.spaces.iter().position(|s| &s.name == space) {
+                Some(i) => Ok(Some(i)),
+                None => Err(format!("Could not find space '{}' in the model", space)),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Builds the distribution information for a model
+    pub fn new(model: &SimpleModel) -> Result<Self, String> {
+        let mut spaces: Vec<Vec<InteriorSide>> = vec![Vec::new(); model.spaces.len()];
+        for (index, s) in model.surfaces.iter().enumerate() {
+            let normal = s.vertices.normal();
+            let area = s.vertices.area();
+            for (front, boundary) in [(true, &s.front_boundary), (false, &s.back_boundary)] {
+                if let Some(space) = Self::space_index(model, boundary)? {
+                    let inward_normal = if front { normal } else { normal * -1. };
+                    spaces[space].push(InteriorSide {
+                        index,
+                        front,
+                        area,
+                        absorbtance: materials::solar_absorbtance(model, &s.construction, front)?,
+                        is_floor: inward_normal.z > FLOOR_MIN_Z,
+                    })
+                }
+            }
+        }
+
+        let mut fenestrations = Vec::new();
+        let mut front_row = 0;
+        let mut back_row = 0;
+        for (index, s) in model.fenestrations.iter().enumerate() {
+            let normal = s.vertices.normal();
+            let this_front_row = front_row;
+            let this_back_row = back_row;
+            if SolarSurface::boundary_receives_sun(&s.front_boundary) {
+                front_row += 1;
+            }
+            if SolarSurface::boundary_receives_sun(&s.back_boundary) {
+                back_row += 1;
+            }
+
+            let (outdoor_front, dc_row, outward_normal, space) =
+                match (&s.front_boundary, &s.back_boundary) {
+                    (Boundary::Outdoor, b) => match Self::space_index(model, b)? {
+                        Some(space) => (true, this_front_row, normal, space),
+                        None => continue,
+                    },
+                    (b, Boundary::Outdoor) => match Self::space_index(model, b)? {
+                        Some(space) => (false, this_back_row, normal * -1., space),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+
+            let normal_transmittance = materials::solar_transmittance(model, &s.construction)?;
+            if normal_transmittance <= 0.0 {
+                continue;
+            }
+
+            fenestrations.push(TransmittingFenestration {
+                index,
+                outdoor_front,
+                dc_row,
+                outward_normal,
+                area: s.vertices.area(),
+                normal_transmittance,
+                space,
+            })
+        }
+
+        Ok(Self {
+            fenestrations,
+            spaces,
+        })
+    }
+
+    /// Distributes the solar radiation transmitted through the fenestrations.
+    ///
+    /// Receives the total and beam solar irradiance (in W/m2) incident on the
+    /// outdoor side of each of the transmitting fenestrations, as well as the
+    /// direction of the sun (`None` if it is below the horizon). The results,
+    /// multiplied by `weight`, are added to those already in the `memory`; so
+    /// several instants within a timestep can be distributed one after the other.
+    pub fn distribute(
+        &self,
+        incident: &[(Float, Float)],
+        sun_direction: Option<Vector3D>,
+        weight: Float,
+        memory: &mut SolarModelMemory,
+    ) -> Result<(), String> {
+        if incident.len() != self.fenestrations.len() {
+            return Err(format!(
+                "Expecting incident solar radiation for {} fenestrations... found {}",
+                self.fenestrations.len(),
+                incident.len()
+            ));
+        }
+
+        for (fen, (total, beam)) in self.fenestrations.iter().zip(incident.iter()) {
+            let diffuse = (total - beam).max(0.0);
+            let beam = match sun_direction {
+                Some(dir) => {
+                    let cos_theta = dir.get_normalized() * fen.outward_normal.get_normalized();
+                    beam * angular_transmittance(fen.normal_transmittance, cos_theta)
+                }
+                None => 0.0,
+            } * fen.area
+                * weight;
+            let diffuse =
+                diffuse * diffuse_transmittance(fen.normal_transmittance) * fen.area * weight;
+
+            memory.fenestration_transmitted_beam[fen.index] += beam;
+            memory.fenestration_transmitted_diffuse[fen.index] += diffuse;
+
+            let sides = &self.spaces[fen.space];
+            if sides.is_empty() {
+                continue;
+            }
+
+            // Beam radiation goes to the floors... or to all the surfaces
+            // if there are no floors.
+            let floor_area: Float = sides.iter().filter(|s| s.is_floor).map(|s| s.area).sum();
+            let mut reflected = 0.0;
+            for side in sides.iter() {
+                let beam_share = if floor_area > 0.0 {
+                    if side.is_floor {
+                        side.area / floor_area
+                    } else {
+                        0.0
+                    }
+                } else {
+                    side.area / sides.iter().map(|s| s.area).sum::<Float>()
+                };
+                let received = beam * beam_share;
+                reflected += received * (1. - side.absorbtance);
+                add_absorbed(memory, side, received * side.absorbtance);
+            }
+
+            // Diffuse radiation (transmitted and reflected) goes everywhere
+            let diffuse = diffuse + reflected;
+            let absorbing: Float = sides.iter().map(|s| s.area * s.absorbtance).sum();
+            if absorbing > 0.0 {
+                for side in sides.iter() {
+                    add_absorbed(
+                        memory,
+                        side,
+                        diffuse * side.area * side.absorbtance / absorbing,
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds an absorbed power (in W) to an interior side, in W/m2
+fn add_absorbed(memory: &mut SolarModelMemory, side: &InteriorSide, power: Float) {
+    if side.front {
+        memory.surface_front_transmitted_solar[side.index] += power / side.area;
+    } else {
+        memory.surface_back_transmitted_solar[side.index] += power / side.area;
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::SolarGainsReport;
+    use validate::assert_close;
+
+    #[test]
+    fn test_angular_transmittance() {
+        assert_close!(angular_transmittance(0.8, 1.0), 0.8);
+        assert_close!(angular_transmittance(0.8, 0.0), 0.0);
+        assert_close!(angular_transmittance(0.8, -0.5), 0.0);
+        // 60 degrees
+        assert_close!(angular_transmittance(0.8, 0.5), 0.8 * 0.9);
+
+        // It decreases with the angle of incidence
+        let mut last = angular_transmittance(0.8, 1.0);
+        for i in 1..90 {
+            let theta = (i as Float).to_radians();
+            let t = angular_transmittance(0.8, theta.cos());
+            assert!(t <= last);
+            last = t;
+        }
+    }
+
+    #[test]
+    fn test_diffuse_transmittance() {
+        let t = diffuse_transmittance(0.8);
+        assert!(t < 0.8);
+        assert!(t > 0.6, "t = {}", t);
+        assert_close!(diffuse_transmittance(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_distribute() {
+        let (model, ..) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let distribution = SolarDistribution::new(&model).unwrap();
+
+        // One window, one space with six surfaces and a floor
+        assert_eq!(distribution.fenestrations.len(), 1);
+        assert_eq!(distribution.spaces.len(), 1);
+        assert_eq!(distribution.spaces[0].len(), 6);
+        assert_eq!(
+            distribution.spaces[0].iter().filter(|s| s.is_floor).count(),
+            1
+        );
+        let fen = &distribution.fenestrations[0];
+        assert!(fen.outdoor_front);
+        assert_close!(fen.normal_transmittance, 0.8);
+        assert_close!(fen.area, 2.0);
+
+        let mut memory = SolarModelMemory::new(
+            model.surfaces.len(),
+            model.fenestrations.len(),
+            SolarGainsReport::default(),
+        );
+
+        // The sun in front of the window
+        let sun = Some(Vector3D::new(0., -1., 1.));
+        distribution
+            .distribute(&[(500., 300.)], sun, 1., &mut memory)
+            .unwrap();
+
+        let transmitted =
+            memory.fenestration_transmitted_beam[0] + memory.fenestration_transmitted_diffuse[0];
+        assert!(memory.fenestration_transmitted_beam[0] > 0.0);
+        assert!(memory.fenestration_transmitted_diffuse[0] > 0.0);
+
+        // All of it is absorbed somewhere
+        let absorbed: Float = model
+            .surfaces
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let area = s.vertices.area();
+                (memory.surface_front_transmitted_solar[i]
+                    + memory.surface_back_transmitted_solar[i])
+                    * area
+            })
+            .sum();
+        assert_close!(absorbed, transmitted, 1e-6);
+
+        // Two halves of a timestep add up to the whole one
+        let mut halves = SolarModelMemory::new(
+            model.surfaces.len(),
+            model.fenestrations.len(),
+            SolarGainsReport::default(),
+        );
+        for _ in 0..2 {
+            distribution
+                .distribute(&[(500., 300.)], sun, 0.5, &mut halves)
+                .unwrap();
+        }
+        assert_close!(
+            halves.fenestration_transmitted_beam[0],
+            memory.fenestration_transmitted_beam[0],
+            1e-6
+        );
+        assert_close!(
+            halves.fenestration_transmitted_diffuse[0],
+            memory.fenestration_transmitted_diffuse[0],
+            1e-6
+        );
+
+        // No sun... no beam
+        let mut memory = SolarModelMemory::new(
+            model.surfaces.len(),
+            model.fenestrations.len(),
+            SolarGainsReport::default(),
+        );
+        distribution
+            .distribute(&[(500., 300.)], None, 1., &mut memory)
+            .unwrap();
+        assert_close!(memory.fenestration_transmitted_beam[0], 0.0);
+    }
+}
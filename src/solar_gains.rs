@@ -0,0 +1,287 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Aggregates the solar radiation absorbed and transmitted into each space,
+//! so that Solar Heat Gains can be reported per zone.
+
+use crate::model::SolarModelMemory;
+use crate::solar_distribution::SolarDistribution;
+use crate::{materials, Float};
+use serde::{Deserialize, Serialize};
+use simple_model::{Boundary, SimpleModel, SimulationState};
+
+/// The solar gains of a single space during a timestep. All values are in W.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpaceSolarGains {
+    /// The name of the space
+    pub space: String,
+
+    /// The beam (i.e., direct sun) solar radiation transmitted into the
+    /// space through its fenestrations
+    pub transmitted_beam: Float,
+
+    /// The diffuse solar radiation transmitted into the space through its
+    /// fenestrations
+    pub transmitted_diffuse: Float,
+
+    /// The transmitted solar radiation absorbed by the interior side of the
+    /// surfaces bounding the space
+    pub absorbed_interior: Float,
+
+    /// The solar radiation absorbed by the exterior side of the surfaces
+    /// that separate the space from the outdoors. This does not enter the
+    /// space directly, but it is conducted through the envelope.
+    pub absorbed_exterior: Float,
+}
+
+impl SpaceSolarGains {
+    /// The total solar radiation transmitted into the space
+    pub fn transmitted(&self) -> Float {
+        self.transmitted_beam + self.transmitted_diffuse
+    }
+}
+
+/// A report with the solar gains of every space in the model
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SolarGainsReport {
+    /// The solar gains of each space, in the same order as the
+    /// spaces in the `SimpleModel`
+    pub spaces: Vec<SpaceSolarGains>,
+}
+
+impl SolarGainsReport {
+    /// Creates an empty report for a model
+    pub fn new(model: &SimpleModel) -> Self {
+        let spaces = model
+            .spaces
+            .iter()
+            .map(|s| SpaceSolarGains {
+                space: s.name.clone(),
+                ..SpaceSolarGains::default()
+            })
+            .collect();
+        Self { spaces }
+    }
+
+    /// Gets the solar gains of a space, by name
+    pub fn get(&self, space: &str) -> Option<&SpaceSolarGains> {
+        self.spaces.iter().find(|s| s.space == space)
+    }
+
+    /// Sets all the gains to zero
+    pub(crate) fn reset(&mut self) {
+        for s in self.spaces.iter_mut() {
+            s.transmitted_beam = 0.0;
+            s.transmitted_diffuse = 0.0;
+            s.absorbed_interior = 0.0;
+            s.absorbed_exterior = 0.0;
+        }
+    }
+}
+
+/// The exterior side of a surface that separates a space from the outdoors
+#[derive(Debug, Clone)]
+struct ExteriorSide {
+    /// The index of the surface in the model
+    index: usize,
+
+    /// Whether the outdoor side of the surface is the front one
+    front: bool,
+
+    /// The area of the surface
+    area: Float,
+
+    /// The solar absorbtance of the outdoor side
+    absorbtance: Float,
+
+    /// The index of the space on the other side
+    space: usize,
+}
+
+/// Sums the solar radiation of each surface and fenestration into
+/// the spaces they bound.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SolarGainsAggregator {
+    exterior: Vec<ExteriorSide>,
+}
+
+impl SolarGainsAggregator {
+    /// Builds the aggregator for a model
+    pub fn new(model: &SimpleModel) -> Result<Self, String> {
+        let mut exterior = Vec::new();
+        for (index, s) in model.surfaces.iter().enumerate() {
+            let (front, space) = match (&s.front_boundary, &s.back_boundary) {
+                (Boundary::Outdoor, b) => match SolarDistribution::space_index(model, b)? {
+                    Some(space) => (true, space),
+                    None => continue,
+                },
+                (b, Boundary::Outdoor) => match SolarDistribution::space_index(model, b)? {
+                    Some(space) => (false, space),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            exterior.push(ExteriorSide {
+                index,
+                front,
+                area: s.vertices.area(),
+                absorbtance: materials::solar_absorbtance(model, &s.construction, front)?,
+                space,
+            })
+        }
+        Ok(Self { exterior })
+    }
+
+    /// Fills the `solar_gains` report in the `memory`, using the solar irradiance
+    /// in the `state` and the transmitted solar radiation in the `memory`.
+    pub fn aggregate(
+        &self,
+        model: &SimpleModel,
+        distribution: &SolarDistribution,
+        state: &SimulationState,
+        memory: &mut SolarModelMemory,
+    ) -> Result<(), String> {
+        memory.solar_gains.reset();
+
+        for fen in distribution.fenestrations.iter() {
+            let gains = &mut memory.solar_gains.spaces[fen.space];
+            gains.transmitted_beam += memory.fenestration_transmitted_beam[fen.index];
+            gains.transmitted_diffuse += memory.fenestration_transmitted_diffuse[fen.index];
+        }
+
+        for (space, sides) in distribution.spaces.iter().enumerate() {
+            let absorbed: Float = sides
+                .iter()
+                .map(|side| {
+                    let v = if side.front {
+                        memory.surface_front_transmitted_solar[side.index]
+                    } else {
+                        memory.surface_back_transmitted_solar[side.index]
+                    };
+                    v * side.area
+                })
+                .sum();
+            memory.solar_gains.spaces[space].absorbed_interior = absorbed;
+        }
+
+        for side in self.exterior.iter() {
+            let s = &model.surfaces[side.index];
+            let irradiance = if side.front {
+                s.front_incident_solar_irradiance(state)
+            } else {
+                s.back_incident_solar_irradiance(state)
+            };
+            let irradiance = irradiance.ok_or(format!(
+                "Could not get incident solar irradiance of surface '{}'",
+                s.name
+            ))?;
+            memory.solar_gains.spaces[side.space].absorbed_exterior +=
+                irradiance * side.absorbtance * side.area;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::SolarModel;
+    use calendar::Date;
+    use communication_protocols::{MetaOptions, SimulationModel};
+    use schedule::ScheduleConstant;
+    use simple_model::SolarOptions;
+    use validate::assert_close;
+    use weather::SyntheticWeather;
+
+    #[test]
+    fn test_report() {
+        let (model, _) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut report = SolarGainsReport::new(&model);
+        assert_eq!(report.spaces.len(), 1);
+        assert!(report.get("the room").is_some());
+        assert!(report.get("some other room").is_none());
+
+        report.spaces[0].transmitted_beam = 10.;
+        report.spaces[0].transmitted_diffuse = 5.;
+        assert_close!(report.spaces[0].transmitted(), 15.);
+        report.reset();
+        assert_close!(report.spaces[0].transmitted(), 0.0);
+        assert_eq!(report.spaces[0].space, "the room");
+    }
+
+    #[test]
+    fn test_aggregate() {
+        // Barcelona
+        let meta_options = MetaOptions {
+            latitude: (41.41 as Float).to_radians(),
+            longitude: (-2.18 as Float).to_radians(),
+            standard_meridian: (-15. as Float).to_radians(),
+            elevation: 0.0,
+        };
+        let (model, mut state_header) = SimpleModel::from_file("./tests/room/room.spl").unwrap();
+        let mut options = SolarOptions::new();
+        options.set_n_solar_irradiance_points(10);
+        options.set_solar_ambient_divitions(3000);
+        options.set_solar_sky_discretization(1);
+        let solar_model =
+            SolarModel::new(&meta_options, options, &model, &mut state_header, 1).unwrap();
+
+        let mut weather = SyntheticWeather::default();
+        weather.dew_point_temperature = Box::new(ScheduleConstant::new(11.));
+        weather.dry_bulb_temperature = Box::new(ScheduleConstant::new(12.));
+        weather.opaque_sky_cover = Box::new(ScheduleConstant::new(0.));
+        weather.direct_normal_radiation = Box::new(ScheduleConstant::new(800.));
+        weather.diffuse_horizontal_radiation = Box::new(ScheduleConstant::new(100.));
+
+        let mut state = state_header.take_values().unwrap();
+        let mut memory = solar_model.allocate_memory().unwrap();
+        solar_model
+            .march(
+                Date {
+                    month: 1,
+                    day: 15,
+                    hour: 12.5,
+                },
+                &weather,
+                &model,
+                &mut state,
+                &mut memory,
+            )
+            .unwrap();
+
+        let gains = memory.solar_gains.get("the room").unwrap();
+        assert_close!(
+            gains.transmitted_beam,
+            memory.fenestration_transmitted_beam[0]
+        );
+        assert_close!(
+            gains.transmitted_diffuse,
+            memory.fenestration_transmitted_diffuse[0]
+        );
+        assert!(gains.transmitted() > 0.0);
+
+        // Everything that comes in is absorbed by the room
+        assert_close!(gains.absorbed_interior / gains.transmitted(), 1.0, 1e-3);
+
+        // The walls are in the sun
+        assert!(gains.absorbed_exterior > 0.0);
+    }
+}
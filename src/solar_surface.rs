@@ -23,7 +23,7 @@ use std::sync::Arc;
 use crate::Float;
 
 use matrix::Matrix;
-use rendering::{colour_matrix::*, DCFactory, Ray, Scene};
+use rendering::{colour_matrix::*, DCFactory, Scene};
 
 use simple_model::{
     Boundary, Fenestration, SimulationStateElement, SimulationStateHeader, Surface,
@@ -33,8 +33,6 @@ use geometry3d::{Point3D, Polygon3D, Ray3D, Triangulation3D, Vector3D};
 use rendering::primitive_samplers::sample_triangle_surface;
 use rendering::rand::*;
 
-use crate::optical_info::IRViewFactorSet;
-
 fn get_sampler(triangles_areas: Vec<Float>) -> impl Fn(&mut RandGen) -> usize {
     let total_area: Float = triangles_areas.iter().sum();
 
@@ -113,11 +111,15 @@ impl SolarSurface {
     /// calculates the Daylight Coefficient Matrix that can be used for
     /// estimating the incident solar radiation in W/m2. The options for this calculation are
     /// contained in the `DCFactory` used as input.
-    pub fn calc_solar_dc_matrix(
+    ///
+    /// The rows given in `reuse` (one element per element of `list`) are used instead
+    /// of being calculated.
+    pub fn calc_solar_dc_matrix_reusing(
         list: &[SolarSurface],
         scene: &Scene,
         dc_factory: &DCFactory,
         front_side: bool,
+        reuse: &[Option<Matrix>],
     ) -> Result<Matrix, String> {
         if list.is_empty() {
             return Ok(Matrix::empty());
@@ -125,7 +127,7 @@ impl SolarSurface {
 
         let mut dcs: Vec<Matrix> = Vec::with_capacity(list.len());
 
-        for s in list.iter() {
+        for (i, s) in list.iter().enumerate() {
             // Skip front ones that do not receive front sun
             if front_side && !s.receives_sun_front {
                 continue;
@@ -134,6 +136,10 @@ impl SolarSurface {
             if !front_side && !s.receives_sun_back {
                 continue;
             }
+            if let Some(Some(row)) = reuse.get(i) {
+                dcs.push(row.clone());
+                continue;
+            }
             let rays = if front_side {
                 s.front_rays()
             } else {
@@ -280,125 +286,12 @@ impl SolarSurface {
         let dc = colour_matrix_to_radiance(&dc);
         average_matrix(&dc)
     }
-
-    /// Calculates an [`IRViewFactorSet`] for this surface
-    pub fn calc_view_factors(
-        &self,
-        scene: &Scene,
-        front_side: bool,
-    ) -> Result<IRViewFactorSet, String> {
-        let mut rng = rendering::rand::get_rng();
-
-        let rays = if front_side {
-            self.front_rays()
-        } else {
-            self.back_rays()
-        };
-
-        let mut ground = 0.0;
-        let mut sky = 0.0;
-
-        let n_samples = 10000;
-        let mut node_aux = Vec::with_capacity(2);
-        for r in &rays {
-            let mut ray = Ray {
-                geometry: *r,
-                ..Ray::default()
-            };
-            let normal = r.direction;
-            let e1 = normal.get_perpendicular()?;
-            let e2 = normal.cross(e1);
-
-            for _ in 0..n_samples {
-                let dir = rendering::samplers::uniform_sample_hemisphere(&mut rng, e1, e2, normal);
-
-                if scene.cast_ray(&mut ray, &mut node_aux).is_none() {
-                    if dir.z > 0.0 {
-                        sky += 1.0;
-                    } else {
-                        ground += 1.;
-                    }
-                }
-            }
-        }
-
-        let n = n_samples as Float * rays.len() as Float;
-        ground /= n;
-        sky /= n;
-
-        let beta = sky.sqrt();
-        let air = sky * (1. - beta);
-        sky *= beta;
-
-        Ok(IRViewFactorSet { sky, ground, air })
-    }
 }
 
 #[cfg(test)]
 mod testing {
     use super::*;
     use geometry3d::Loop3D;
-    use validate::assert_close;
-
-    #[test]
-    fn test_view_factors_empty_scene_vertical() {
-        let mut the_loop = Loop3D::new();
-        the_loop.push(Point3D::new(0., 0., 0.)).unwrap();
-        the_loop.push(Point3D::new(1., 0., 0.)).unwrap();
-        the_loop.push(Point3D::new(1., 0., 1.)).unwrap();
-        the_loop.push(Point3D::new(0., 0., 1.)).unwrap();
-        the_loop.close().unwrap();
-
-        let mut scene = Scene::new();
-        scene.build_accelerator();
-        let p = Polygon3D::new(the_loop).unwrap();
-        let s = SolarSurface::new(10, &p, true, true).unwrap();
-
-        let beta = (0.5 as Float).sqrt();
-
-        // Front side
-        let views = s.calc_view_factors(&scene, true).unwrap();
-
-        assert_close!(views.ground, 0.5, 1e-2);
-        assert_close!(views.sky, 0.5 * beta, 1e-2);
-        assert_close!(views.air, 0.5 * (1. - beta), 1e-2);
-
-        // back side
-        let views = s.calc_view_factors(&scene, false).unwrap();
-
-        assert_close!(views.ground, 0.5, 1e-2);
-        assert_close!(views.sky, 0.5 * beta, 1e-2);
-        assert_close!(views.air, 0.5 * (1. - beta), 1e-2);
-    }
-
-    #[test]
-    fn test_view_factors_empty_scene_horizontal() {
-        let mut the_loop = Loop3D::new();
-        the_loop.push(Point3D::new(0., 0., 0.)).unwrap();
-        the_loop.push(Point3D::new(1., 0., 0.)).unwrap();
-        the_loop.push(Point3D::new(1., 1., 0.)).unwrap();
-        the_loop.push(Point3D::new(0., 1., 0.)).unwrap();
-        the_loop.close().unwrap();
-
-        let mut scene = Scene::new();
-        scene.build_accelerator();
-        let p = Polygon3D::new(the_loop).unwrap();
-        let s = SolarSurface::new(10, &p, true, true).unwrap();
-
-        // Front side
-        let views = s.calc_view_factors(&scene, true).unwrap();
-
-        assert_close!(views.ground, 0.0);
-        assert_close!(views.sky, 1.0);
-        assert_close!(views.air, 0.0);
-
-        // back side
-        let views = s.calc_view_factors(&scene, false).unwrap();
-
-        assert_close!(views.ground, 1.0);
-        assert_close!(views.sky, 0.0);
-        assert_close!(views.air, 0.0);
-    }
 
     #[test]
     fn test_new_boundary_fenestrations() {
@@ -0,0 +1,344 @@
+/*
+MIT License
+Copyright (c) 2021 Germán Molina
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+//! Calculates the [`IRViewFactorSet`] of the sides of surfaces and fenestrations.
+//!
+//! Directions are sampled from the hemisphere of each point with a
+//! cosine-weighted distribution, so the fraction of the rays that reach
+//! something is directly the view factor to it.
+
+use crate::interior_radiation::{SpaceSide, SpaceSideKind};
+use crate::obstructions::Obstructions;
+use crate::optical_info::IRViewFactorSet;
+use crate::solar_surface::SolarSurface;
+use crate::{Float, ViewFactorMode, PI};
+use geometry3d::{Ray3D, Vector3D};
+use rendering::rand::RandGen;
+
+/// Samples a direction from a hemisphere, following a cosine-weighted distribution
+pub(crate) fn cosine_weighted_direction(
+    rng: &mut RandGen,
+    e1: Vector3D,
+    e2: Vector3D,
+    normal: Vector3D,
+) -> Vector3D {
+    let r1: Float = rng.gen();
+    let r2: Float = rng.gen();
+    let phi = 2. * PI * r1;
+    let sin_theta = r2.sqrt();
+    let cos_theta = (1. - r2).sqrt();
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    e1 * (cos_phi * sin_theta) + e2 * (sin_phi * sin_theta) + normal * cos_theta
+}
+
+/// The standard error of a fraction `p` estimated from `n` samples
+fn standard_error(p: Float, n: Float) -> Float {
+    (p * (1. - p) / n).sqrt()
+}
+
+/// Calculates view factors by casting rays into a `Scene`
+pub(crate) struct ViewFactorCalculator<'a> {
+    /// The scene, containing all the geometry. In [`ViewFactorMode::Physical`]
+    /// mode, it also classifies the obstructions hit by the rays.
+    obstructions: Obstructions<'a>,
+
+    /// How to split the view
+    mode: ViewFactorMode,
+
+    /// The number of directions sampled from each point
+    n_samples: usize,
+}
+
+impl<'a> ViewFactorCalculator<'a> {
+    /// Creates a new calculator
+    pub fn new(obstructions: Obstructions<'a>, mode: ViewFactorMode, n_samples: usize) -> Self {
+        Self {
+            obstructions,
+            mode,
+            n_samples,
+        }
+    }
+
+    /// Calculates the [`IRViewFactorSet`] of one side of a `SolarSurface`. The
+    /// surface or fenestration it was made from is passed as `own`, so that it
+    /// is not counted as an obstruction.
+    pub fn calc(
+        &self,
+        surface: &SolarSurface,
+        own: Option<(SpaceSideKind, usize)>,
+        front_side: bool,
+    ) -> Result<IRViewFactorSet, String> {
+        let mut rng = rendering::rand::get_rng();
+
+        let rays = if front_side {
+            surface.front_rays()
+        } else {
+            surface.back_rays()
+        };
+
+        let mut ground = 0.0;
+        let mut sky = 0.0;
+        let mut air = 0.0;
+        let mut hits: Vec<(SpaceSide, Float)> = Vec::new();
+
+        let mut node_aux = Vec::with_capacity(2);
+        for r in &rays {
+            let normal = r.direction;
+            let e1 = normal.get_perpendicular()?;
+            let e2 = normal.cross(e1);
+
+            for _ in 0..self.n_samples {
+                let geometry = Ray3D {
+                    origin: r.origin,
+                    direction: cosine_weighted_direction(&mut rng, e1, e2, normal),
+                };
+
+                match self.obstructions.cast(&geometry, own, &mut node_aux)? {
+                    // Surfaces and fenestrations of the model
+                    Some((_, Some(side))) => match hits.iter_mut().find(|(s, _)| *s == side) {
+                        Some((_, v)) => *v += 1.,
+                        None => hits.push((side, 1.)),
+                    },
+                    // Other obstructions
+                    Some((_, None)) => air += 1.,
+                    None => {
+                        if geometry.direction.z > 0.0 {
+                            sky += 1.;
+                        } else {
+                            ground += 1.;
+                        }
+                    }
+                }
+            }
+        }
+
+        let n = self.n_samples as Float * rays.len() as Float;
+        if n == 0.0 {
+            return Err(
+                "Cannot calculate view factors without sampling points or directions".into(),
+            );
+        }
+        ground /= n;
+        sky /= n;
+        air /= n;
+        hits.iter_mut().for_each(|(_, v)| *v /= n);
+
+        let standard_error = [ground, sky, air]
+            .iter()
+            .chain(hits.iter().map(|(_, v)| v))
+            .map(|p| standard_error(*p, n))
+            .fold(0.0, Float::max);
+
+        if self.mode == ViewFactorMode::Legacy {
+            let beta = sky.sqrt();
+            air += sky * (1. - beta);
+            sky *= beta;
+        }
+
+        Ok(IRViewFactorSet {
+            sky,
+            ground,
+            air,
+            obstructions: hits,
+            standard_error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+    use crate::analytical::{parallel_rectangles, perpendicular_rectangles};
+    use geometry3d::{Loop3D, Point3D, Polygon3D};
+    use rendering::{Scene, Wavelengths};
+    use simple_model::SimpleModel;
+    use validate::assert_close;
+
+    #[test]
+    fn test_view_factors_empty_scene_vertical() {
+        let mut the_loop = Loop3D::new();
+        the_loop.push(Point3D::new(0., 0., 0.)).unwrap();
+        the_loop.push(Point3D::new(1., 0., 0.)).unwrap();
+        the_loop.push(Point3D::new(1., 0., 1.)).unwrap();
+        the_loop.push(Point3D::new(0., 0., 1.)).unwrap();
+        the_loop.close().unwrap();
+
+        let mut scene = Scene::new();
+        scene.build_accelerator();
+        let p = Polygon3D::new(the_loop).unwrap();
+        let s = SolarSurface::new(10, &p, true, true).unwrap();
+        let calculator = ViewFactorCalculator::new(
+            Obstructions::unclassified(&scene),
+            ViewFactorMode::Legacy,
+            10000,
+        );
+
+        let beta = (0.5 as Float).sqrt();
+
+        // Front side
+        let views = calculator.calc(&s, None, true).unwrap();
+
+        assert_close!(views.ground, 0.5, 1e-2);
+        assert_close!(views.sky, 0.5 * beta, 1e-2);
+        assert_close!(views.air, 0.5 * (1. - beta), 1e-2);
+
+        // back side
+        let views = calculator.calc(&s, None, false).unwrap();
+
+        assert_close!(views.ground, 0.5, 1e-2);
+        assert_close!(views.sky, 0.5 * beta, 1e-2);
+        assert_close!(views.air, 0.5 * (1. - beta), 1e-2);
+    }
+
+    #[test]
+    fn test_view_factors_empty_scene_horizontal() {
+        let mut the_loop = Loop3D::new();
+        the_loop.push(Point3D::new(0., 0., 0.)).unwrap();
+        the_loop.push(Point3D::new(1., 0., 0.)).unwrap();
+        the_loop.push(Point3D::new(1., 1., 0.)).unwrap();
+        the_loop.push(Point3D::new(0., 1., 0.)).unwrap();
+        the_loop.close().unwrap();
+
+        let mut scene = Scene::new();
+        scene.build_accelerator();
+        let p = Polygon3D::new(the_loop).unwrap();
+        let s = SolarSurface::new(10, &p, true, true).unwrap();
+        let calculator = ViewFactorCalculator::new(
+            Obstructions::unclassified(&scene),
+            ViewFactorMode::Legacy,
+            10000,
+        );
+
+        // Front side
+        let views = calculator.calc(&s, None, true).unwrap();
+
+        assert_close!(views.ground, 0.0);
+        assert_close!(views.sky, 1.0);
+        assert_close!(views.air, 0.0);
+
+        // back side
+        let views = calculator.calc(&s, None, false).unwrap();
+
+        assert_close!(views.ground, 1.0);
+        assert_close!(views.sky, 0.0);
+        assert_close!(views.air, 0.0);
+    }
+
+    #[test]
+    fn test_view_factors_physical() {
+        let (model, _) = SimpleModel::from_file("./tests/courtyard/courtyard.spl").unwrap();
+        let mut scene = Scene::from_simple_model(&model, Wavelengths::Solar).unwrap();
+        scene.build_accelerator();
+        let floor = SolarSurface::new(10, &model.surfaces[0].vertices, true, true).unwrap();
+        let own = Some((SpaceSideKind::Surface, 0));
+
+        // The floor of the courtyard sees the sky and the front of the walls
+        let calculator = ViewFactorCalculator::new(
+            Obstructions::new(&model, &scene).unwrap(),
+            ViewFactorMode::Physical,
+            10000,
+        );
+        let views = calculator.calc(&floor, own, true).unwrap();
+        assert_close!(views.ground, 0.0);
+        assert_close!(views.air, 0.0);
+        assert!(views.sky > 0.0 && views.sky < 0.5);
+        assert!(views.standard_error > 0.0 && views.standard_error < 1e-2);
+        assert_eq!(views.obstructions.len(), 4);
+        assert!(views.obstructions.iter().all(|(side, _)| side.front));
+        let walls: Float = views.obstructions.iter().map(|(_, v)| v).sum();
+        assert_close!(views.sky + walls, 1.0, 1e-6);
+
+        // The heuristic does not identify the walls... it sees them as air
+        let calculator = ViewFactorCalculator::new(
+            Obstructions::unclassified(&scene),
+            ViewFactorMode::Legacy,
+            10000,
+        );
+        let views = calculator.calc(&floor, own, true).unwrap();
+        assert!(views.obstructions.is_empty());
+        assert_close!(views.sky + views.air, 1.0, 1e-6);
+        assert_close!(views.sky, (1. - walls).powf(1.5), 1e-2);
+    }
+
+    /// Calculates the view factors of the front of the first surface
+    /// of a model, with many points and few directions per point
+    fn front_view_factors(file: &str) -> IRViewFactorSet {
+        let (model, _) = SimpleModel::from_file(file).unwrap();
+        let mut scene = Scene::from_simple_model(&model, Wavelengths::Solar).unwrap();
+        scene.build_accelerator();
+        let calculator = ViewFactorCalculator::new(
+            Obstructions::new(&model, &scene).unwrap(),
+            ViewFactorMode::Physical,
+            2000,
+        );
+        let s = SolarSurface::new(200, &model.surfaces[0].vertices, true, true).unwrap();
+        calculator
+            .calc(&s, Some((SpaceSideKind::Surface, 0)), true)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parallel_plates() {
+        let views = front_view_factors("./tests/parallel_plates/parallel_plates.spl");
+        let expected = parallel_rectangles(1., 1., 1.);
+
+        // The bottom sees the back of the top, and the sky around it
+        assert_eq!(views.obstructions.len(), 1);
+        let (side, found) = views.obstructions[0];
+        assert_eq!(side.index, 1);
+        assert!(!side.front);
+        assert_close!(found, expected, 1e-2);
+        assert_close!(views.sky, 1. - expected, 1e-2);
+        assert_close!(views.ground, 0.0);
+        assert_close!(views.air, 0.0);
+        assert!(views.standard_error < 1e-3);
+    }
+
+    #[test]
+    fn test_perpendicular_plates() {
+        let views = front_view_factors("./tests/perpendicular_plates/perpendicular_plates.spl");
+        let expected = perpendicular_rectangles(1., 1., 1.);
+
+        // The floor sees the front of the wall, and the sky
+        assert_eq!(views.obstructions.len(), 1);
+        let (side, found) = views.obstructions[0];
+        assert_eq!(side.index, 1);
+        assert!(side.front);
+        assert_close!(found, expected, 1e-2);
+        assert_close!(views.sky, 1. - expected, 1e-2);
+        assert_close!(views.ground, 0.0);
+        assert_close!(views.air, 0.0);
+    }
+
+    #[test]
+    fn test_no_samples() {
+        let mut scene = Scene::new();
+        scene.build_accelerator();
+        let (model, _) =
+            SimpleModel::from_file("./tests/parallel_plates/parallel_plates.spl").unwrap();
+        let s = SolarSurface::new(10, &model.surfaces[0].vertices, true, true).unwrap();
+        let calculator = ViewFactorCalculator::new(
+            Obstructions::unclassified(&scene),
+            ViewFactorMode::Physical,
+            0,
+        );
+        assert!(calculator.calc(&s, None, true).is_err());
+    }
+}
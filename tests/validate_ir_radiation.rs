@@ -1,6 +1,6 @@
 use calendar::Date;
 use communication_protocols::{MetaOptions, SimulationModel};
-use light::{Float, SolarModel};
+use light::{Float, SolarModel, SolarModelOptions, TimestepAveraging};
 use schedule::ScheduleConstant;
 use simple_model::SolarOptions;
 use simple_test_models::*;
@@ -85,10 +85,24 @@ fn get_simple_results(city: &str, orientation_str: &str) -> (Vec<f64>, Vec<f64>)
         .set_solar_ambient_divitions(3000)
         .set_solar_sky_discretization(1);
 
+    // EnergyPlus reports the infrared irradiance at the end of each timestep
+    let model_options = SolarModelOptions {
+        timestep_averaging: TimestepAveraging::Instantaneous,
+        ..SolarModelOptions::default()
+    };
+
     let n: usize = 20;
-    let solar_model =
-        SolarModel::new(&meta_options, options, &simple_model, &mut state_header, n).unwrap();
+    let solar_model = SolarModel::new_with_options(
+        &meta_options,
+        options,
+        model_options,
+        &simple_model,
+        &mut state_header,
+        n,
+    )
+    .unwrap();
     let mut state = state_header.take_values().unwrap();
+    let mut memory = solar_model.allocate_memory().unwrap();
     let mut date = Date {
         month: 1,
         day: 1,
@@ -98,7 +112,8 @@ fn get_simple_results(city: &str, orientation_str: &str) -> (Vec<f64>, Vec<f64>)
     let mut expected = Vec::with_capacity(horizontal_ir.len());
 
     let surface_area = 60.0;
-    let emmisivity = 0.9;
+    // The emissivity of the outer layer of the wall
+    let emmisivity = solar_model.surface_emissivity(0, true).unwrap();
     for index in 0..horizontal_ir.len() {
         let gain = outside_ir_gain[index];
         let ts = outside_surface_temp[index];
@@ -122,7 +137,7 @@ fn get_simple_results(city: &str, orientation_str: &str) -> (Vec<f64>, Vec<f64>)
 
         // March
         solar_model
-            .march(date, &weather, &simple_model, &mut state,&mut ())
+            .march(date, &weather, &simple_model, &mut state, &mut memory)
             .unwrap();
 
         let front_radiation = surface.front_ir_irradiance(&state).unwrap();
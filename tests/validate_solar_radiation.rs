@@ -1,6 +1,6 @@
 use calendar::Date;
 use communication_protocols::{MetaOptions, SimulationModel};
-use light::{Float, SolarModel};
+use light::{Float, SolarModel, SolarModelOptions};
 use schedule::ScheduleConstant;
 use simple_model::SolarOptions;
 use simple_test_models::*;
@@ -39,6 +39,7 @@ fn get_simple_results(
     diffuse_horizontal_rad: Vec<f64>,
     direct_normal_rad: Vec<f64>,
     orientation: f64,
+    direct_sun_sky_discretization: Option<usize>,
 ) -> Vec<f64> {
     let (lat, lon, std_mer): (Float, Float, Float) = match city.as_bytes() {
         b"wellington" => (-41.3, 174.78, 180.),
@@ -69,13 +70,31 @@ fn get_simple_results(
     let mut options = SolarOptions::new();
     options
         .set_n_solar_irradiance_points(10)
-        .set_solar_ambient_divitions(3000)
         .set_solar_sky_discretization(1);
 
+    // Finer skies need more samples
+    if direct_sun_sky_discretization.is_some() {
+        options.set_solar_ambient_divitions(20000);
+    } else {
+        options.set_solar_ambient_divitions(3000);
+    }
+    let model_options = SolarModelOptions {
+        direct_sun_sky_discretization,
+        ..SolarModelOptions::default()
+    };
+
     let n: usize = 20;
-    let solar_model =
-        SolarModel::new(&meta_options, options, &simple_model, &mut state_header, n).unwrap();
+    let solar_model = SolarModel::new_with_options(
+        &meta_options,
+        options,
+        model_options,
+        &simple_model,
+        &mut state_header,
+        n,
+    )
+    .unwrap();
     let mut state = state_header.take_values().unwrap();
+    let mut memory = solar_model.allocate_memory().unwrap();
     let mut date = Date {
         month: 1,
         day: 1,
@@ -97,7 +116,7 @@ fn get_simple_results(
 
         // March
         solar_model
-            .march(date, &weather, &simple_model, &mut state, &mut ())
+            .march(date, &weather, &simple_model, &mut state, &mut memory)
             .unwrap();
 
         let front_radiation = surface.front_incident_solar_irradiance(&state).unwrap();
@@ -129,28 +148,29 @@ fn barcelona(validator: &mut Validator) {
     #[valid(Exterior Incident Solar Radiation - Barcelona, South)]
     fn validate_barcelona_south() -> Box<dyn Validate> {
         let (expected, diffuse_horizontal_rad, direct_normal_rad) = get_expected(CITY, "south");
-        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 0.0);
+        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 0.0, None);
         get_validator(expected, found)
     }
 
     #[valid(Exterior Incident Solar Radiation - Barcelona, North)]
     fn validate_barcelona_north() -> Box<dyn Validate> {
         let (expected, diffuse_horizontal_rad, direct_normal_rad) = get_expected(CITY, "north");
-        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 180.0);
+        let found =
+            get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 180.0, None);
         get_validator(expected, found)
     }
 
     #[valid(Exterior Incident Solar Radiation - Barcelona, West)]
     fn validate_barcelona_west() -> Box<dyn Validate> {
         let (expected, diffuse_horizontal_rad, direct_normal_rad) = get_expected(CITY, "west");
-        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 90.0);
+        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 90.0, None);
         get_validator(expected, found)
     }
 
     #[valid(Exterior Incident Solar Radiation - Barcelona, East)]
     fn validate_barcelona_east() -> Box<dyn Validate> {
         let (expected, diffuse_horizontal_rad, direct_normal_rad) = get_expected(CITY, "east");
-        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, -90.);
+        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, -90., None);
         get_validator(expected, found)
     }
 
@@ -166,28 +186,29 @@ fn wellington(validator: &mut Validator) {
     #[valid(Exterior Incident Solar Radiation - Wellington, South)]
     fn validate_wellington_south() -> Box<dyn Validate> {
         let (expected, diffuse_horizontal_rad, direct_normal_rad) = get_expected(CITY, "south");
-        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 0.0);
+        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 0.0, None);
         get_validator(expected, found)
     }
 
     #[valid(Exterior Incident Solar Radiation - Wellington, North)]
     fn validate_wellington_north() -> Box<dyn Validate> {
         let (expected, diffuse_horizontal_rad, direct_normal_rad) = get_expected(CITY, "north");
-        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 180.0);
+        let found =
+            get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 180.0, None);
         get_validator(expected, found)
     }
 
     #[valid(Exterior Incident Solar Radiation - Wellington, West)]
     fn validate_wellington_west() -> Box<dyn Validate> {
         let (expected, diffuse_horizontal_rad, direct_normal_rad) = get_expected(CITY, "west");
-        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 90.0);
+        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, 90.0, None);
         get_validator(expected, found)
     }
 
     #[valid(Exterior Incident Solar Radiation - Wellington, East)]
     fn validate_wellington_east() -> Box<dyn Validate> {
         let (expected, diffuse_horizontal_rad, direct_normal_rad) = get_expected(CITY, "east");
-        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, -90.);
+        let found = get_simple_results(CITY, diffuse_horizontal_rad, direct_normal_rad, -90., None);
         get_validator(expected, found)
     }
 
@@ -197,6 +218,132 @@ fn wellington(validator: &mut Validator) {
     validator.push(validate_wellington_east());
 }
 
+/// The MF used for calculating the direct sun separately from the diffuse sky
+const DIRECT_SUN_MF: Option<usize> = Some(4);
+
+fn direct_sun(validator: &mut Validator) {
+    #[valid(Exterior Incident Solar Radiation with Direct Sun Split - Barcelona, South)]
+    fn validate_barcelona_south() -> Box<dyn Validate> {
+        let (expected, diffuse_horizontal_rad, direct_normal_rad) =
+            get_expected("barcelona", "south");
+        let found = get_simple_results(
+            "barcelona",
+            diffuse_horizontal_rad,
+            direct_normal_rad,
+            0.0,
+            DIRECT_SUN_MF,
+        );
+        get_validator(expected, found)
+    }
+
+    #[valid(Exterior Incident Solar Radiation with Direct Sun Split - Barcelona, North)]
+    fn validate_barcelona_north() -> Box<dyn Validate> {
+        let (expected, diffuse_horizontal_rad, direct_normal_rad) =
+            get_expected("barcelona", "north");
+        let found = get_simple_results(
+            "barcelona",
+            diffuse_horizontal_rad,
+            direct_normal_rad,
+            180.0,
+            DIRECT_SUN_MF,
+        );
+        get_validator(expected, found)
+    }
+
+    #[valid(Exterior Incident Solar Radiation with Direct Sun Split - Barcelona, West)]
+    fn validate_barcelona_west() -> Box<dyn Validate> {
+        let (expected, diffuse_horizontal_rad, direct_normal_rad) =
+            get_expected("barcelona", "west");
+        let found = get_simple_results(
+            "barcelona",
+            diffuse_horizontal_rad,
+            direct_normal_rad,
+            90.0,
+            DIRECT_SUN_MF,
+        );
+        get_validator(expected, found)
+    }
+
+    #[valid(Exterior Incident Solar Radiation with Direct Sun Split - Barcelona, East)]
+    fn validate_barcelona_east() -> Box<dyn Validate> {
+        let (expected, diffuse_horizontal_rad, direct_normal_rad) =
+            get_expected("barcelona", "east");
+        let found = get_simple_results(
+            "barcelona",
+            diffuse_horizontal_rad,
+            direct_normal_rad,
+            -90.,
+            DIRECT_SUN_MF,
+        );
+        get_validator(expected, found)
+    }
+
+    #[valid(Exterior Incident Solar Radiation with Direct Sun Split - Wellington, South)]
+    fn validate_wellington_south() -> Box<dyn Validate> {
+        let (expected, diffuse_horizontal_rad, direct_normal_rad) =
+            get_expected("wellington", "south");
+        let found = get_simple_results(
+            "wellington",
+            diffuse_horizontal_rad,
+            direct_normal_rad,
+            0.0,
+            DIRECT_SUN_MF,
+        );
+        get_validator(expected, found)
+    }
+
+    #[valid(Exterior Incident Solar Radiation with Direct Sun Split - Wellington, North)]
+    fn validate_wellington_north() -> Box<dyn Validate> {
+        let (expected, diffuse_horizontal_rad, direct_normal_rad) =
+            get_expected("wellington", "north");
+        let found = get_simple_results(
+            "wellington",
+            diffuse_horizontal_rad,
+            direct_normal_rad,
+            180.0,
+            DIRECT_SUN_MF,
+        );
+        get_validator(expected, found)
+    }
+
+    #[valid(Exterior Incident Solar Radiation with Direct Sun Split - Wellington, West)]
+    fn validate_wellington_west() -> Box<dyn Validate> {
+        let (expected, diffuse_horizontal_rad, direct_normal_rad) =
+            get_expected("wellington", "west");
+        let found = get_simple_results(
+            "wellington",
+            diffuse_horizontal_rad,
+            direct_normal_rad,
+            90.0,
+            DIRECT_SUN_MF,
+        );
+        get_validator(expected, found)
+    }
+
+    #[valid(Exterior Incident Solar Radiation with Direct Sun Split - Wellington, East)]
+    fn validate_wellington_east() -> Box<dyn Validate> {
+        let (expected, diffuse_horizontal_rad, direct_normal_rad) =
+            get_expected("wellington", "east");
+        let found = get_simple_results(
+            "wellington",
+            diffuse_horizontal_rad,
+            direct_normal_rad,
+            -90.,
+            DIRECT_SUN_MF,
+        );
+        get_validator(expected, found)
+    }
+
+    validator.push(validate_barcelona_south());
+    validator.push(validate_barcelona_north());
+    validator.push(validate_barcelona_west());
+    validator.push(validate_barcelona_east());
+    validator.push(validate_wellington_south());
+    validator.push(validate_wellington_north());
+    validator.push(validate_wellington_west());
+    validator.push(validate_wellington_east());
+}
+
 #[test]
 fn validate_solar_radiation() {
     // cargo test --package light --test validate_solar_radiation -- validate_solar_radiation --exact --nocapture
@@ -210,3 +357,16 @@ fn validate_solar_radiation() {
 
     validator.validate().unwrap();
 }
+
+#[test]
+fn validate_solar_radiation_direct_sun() {
+    // cargo test --release --package light --test validate_solar_radiation -- validate_solar_radiation_direct_sun --exact --nocapture
+    let mut validator = Validator::new(
+        "Validate Solar Radiation - Direct sun calculated separately",
+        "./docs/validation/incident_solar_radiation_direct_sun.html",
+    );
+
+    direct_sun(&mut validator);
+
+    validator.validate().unwrap();
+}